@@ -1,55 +1,654 @@
+use egui_macroquad::egui;
 use egui_macroquad::egui::Align2;
 use egui_macroquad::egui::Color32;
 use egui_macroquad::egui::Widget;
 
-use crate::{cpu, egui, Memory};
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
 
-pub struct App {
-	pub paused: bool,
-	pub step: bool,
-	pub instructions_per_frame: u32,
+use nemu::breakpoint::{self, Trigger};
+use nemu::bus::{Access, Memory, Watch, WatchHit, MEMORY_SIZE};
+use nemu::cpu;
+use nemu::debug_info::DebugInfo;
+use nemu::disasm::{self, RunTarget};
+use nemu::expr::Expr;
+use nemu::number::{self, Radix};
+
+use crate::devices::{SCREEN_MEMORY_END, SCREEN_MEMORY_START};
+
+/// Instructions shown in the Disassembly window
+const DISASSEMBLY_LINES: usize = 24;
+/// Rows of 16 bytes visible at once in the Memory window
+const MEMORY_ROWS: usize = 16;
+const ZERO_PAGE_COLOR: Color32 = Color32::LIGHT_BLUE;
+const STACK_COLOR: Color32 = Color32::LIGHT_GREEN;
+const SCREEN_COLOR: Color32 = Color32::GOLD;
+/// Background of bytes changed by the last step
+const CHANGED_COLOR: Color32 = Color32::DARK_RED;
+/// Background of the last match found in the Memory window
+const FOUND_COLOR: Color32 = Color32::DARK_BLUE;
+/// Save state slots, saved with Shift and restored with F1 to F4
+pub const SAVE_SLOTS: usize = 4;
+const SLOT_KEYS: [egui::Key; SAVE_SLOTS] =
+	[egui::Key::F1, egui::Key::F2, egui::Key::F3, egui::Key::F4];
+/// Status register bits from bit 7 down, as shown in the Debug Controls window
+const STATUS_FLAGS: [(&str, cpu::StatusFlags); 8] = [
+	("N", cpu::StatusFlags::Negative),
+	("V", cpu::StatusFlags::Overflow),
+	("-", cpu::StatusFlags::Unused),
+	("B", cpu::StatusFlags::Break),
+	("D", cpu::StatusFlags::DecimalMode),
+	("I", cpu::StatusFlags::InterruptDisable),
+	("Z", cpu::StatusFlags::Zero),
+	("C", cpu::StatusFlags::Carry),
+];
+
+/// Source file shown in the Source Code window
+pub struct SourceFile {
+	pub name: String,
+	pub lines: Vec<String>,
 	/// A debug symbols relates a line from the source code to the corresponding
 	/// u16 program counter address
 	pub debug_symbols: Vec<u16>,
-	pub source_file: Vec<String>,
+	/// Whether each line emits bytes, the others only carry the address of
+	/// the line before them in `debug_symbols`
+	pub emits_code: Vec<bool>,
+}
+
+impl SourceFile {
+	/// Maps every line of `text` to the address of the closest line at or
+	/// before it in `line_addresses`
+	pub fn new(name: &str, text: &str, line_addresses: &HashMap<usize, u16>) -> Self {
+		let lines: Vec<String> = text.lines().map(String::from).collect();
+		let mut last_pc_value = 0;
+		let mut debug_symbols: Vec<u16> = Vec::with_capacity(lines.len());
+		let mut emits_code = Vec::with_capacity(lines.len());
+		for (line_number, _) in lines.iter().enumerate() {
+			if let Some(&pc_addr) = line_addresses.get(&(line_number + 1)) {
+				debug_symbols.push(pc_addr);
+				last_pc_value = pc_addr;
+			} else {
+				debug_symbols.push(last_pc_value);
+			}
+			emits_code.push(line_addresses.contains_key(&(line_number + 1)));
+		}
+		Self {
+			name: name.to_string(),
+			lines,
+			debug_symbols,
+			emits_code,
+		}
+	}
+}
+
+/// Where a breakpoint is set, a source line or an address
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Location {
+	/// Index into `App::sources` and line number
+	Line(usize, usize),
+	Address(u16),
+}
+
+/// Condition and counts of a breakpoint, as edited in the Breakpoints window
+#[derive(Default)]
+struct BreakpointSettings {
+	condition: String,
+	/// Why `condition` doesn't parse, the last valid condition stays in effect
+	error: Option<String>,
+	trigger: Trigger,
+}
+
+impl BreakpointSettings {
+	/// Parses `condition` again, resolving labels against `debug_info`
+	fn update_condition(&mut self, debug_info: &DebugInfo) {
+		self.error = None;
+		if self.condition.trim().is_empty() {
+			self.trigger.condition = None;
+			return;
+		}
+		match Expr::parse(&self.condition, debug_info) {
+			Ok(expr) => self.trigger.condition = Some(expr),
+			Err(err) => self.error = Some(err),
+		}
+	}
+	/// Condition and counts in brief, shown next to the breakpoint
+	fn summary(&self) -> String {
+		let mut parts = Vec::new();
+		if self.trigger.condition.is_some() {
+			parts.push(format!("if {}", self.condition.trim()));
+		}
+		if self.trigger.ignore_count > 0 {
+			parts.push(format!("ignore {}", self.trigger.ignore_count));
+		}
+		if let Some(count) = self.trigger.hit_count {
+			parts.push(format!("on hit {count}"));
+		}
+		if self.trigger.hits > 0 {
+			parts.push(format!("hits {}", self.trigger.hits));
+		}
+		parts.join(", ")
+	}
+}
+
+/// Movement back through the execution history, carried out by the frontend
+/// that owns it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rewind {
+	StepBack,
+	/// Steps back until the program counter is on a breakpoint whose
+	/// condition holds
+	ReverseContinue,
+	/// Moves to a position on the timeline
+	Seek(usize),
+}
+
+/// Save state slot for the frontend to write to or restore from, numbered
+/// from 1
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveStateRequest {
+	Save(usize),
+	Load(usize),
+}
+
+/// Address range shown in the Watchpoints window, optionally breaking on
+/// access
+struct Watchpoint {
+	range: RangeInclusive<u16>,
+	/// `None` only shows the bytes
+	break_on: Option<Access>,
+	/// Value accesses are filtered by, any value when empty
+	value_entry: String,
+}
+
+/// Hex editor over the whole memory in the Memory window
+#[derive(Default)]
+struct MemoryView {
+	/// Address whose row is scrolled to the top of the window
+	scroll_to: Option<u16>,
+	/// Contents before and after the CPU last moved on, see
+	/// [`MemoryView::update`]
+	previous: Vec<u8>,
+	current: Vec<u8>,
+	/// Cycle count `current` was taken at
+	cycles: Option<u64>,
+	/// Address of the byte edited in place and the text entered for it
+	editing: Option<(u16, String)>,
+	search_entry: String,
+	/// Last match of the search or why it failed, the next search starts
+	/// after the match
+	found: Option<Result<RangeInclusive<u16>, String>>,
+}
+
+impl MemoryView {
+	/// Takes a snapshot of memory whenever the cycle count changes, so the
+	/// bytes changed by the last step or frame of execution stand out
+	fn update(&mut self, cycles: u64, mem: &Memory) {
+		if self.cycles == Some(cycles) {
+			return;
+		}
+		let contents = (0..MEMORY_SIZE)
+			.map(|address| mem.peek(address as u16))
+			.collect();
+		self.previous = std::mem::replace(&mut self.current, contents);
+		if self.cycles.is_none() {
+			self.previous = self.current.clone();
+		}
+		self.cycles = Some(cycles);
+	}
+
+	fn changed(&self, address: u16, value: u8) -> bool {
+		self.previous
+			.get(address as usize)
+			.is_some_and(|&old| old != value)
+	}
+}
+
+/// Hex entry showing a register of `digits` digits, returning the value
+/// entered or why it isn't one when it changes while `editable`
+fn register_entry(
+	ui: &mut egui::Ui,
+	value: u16,
+	digits: usize,
+	color: Color32,
+	editable: bool,
+) -> Option<Result<u16, String>> {
+	let mut user_entry = format!("{value:0digits$x}");
+	let changed = egui::TextEdit::singleline(&mut user_entry)
+		.code_editor()
+		.text_color(color)
+		.desired_width(digits as f32 * 9.)
+		.interactive(editable)
+		.ui(ui)
+		.changed();
+	changed.then(|| {
+		let entry = user_entry.trim();
+		let value = number::parse(entry, Radix::Hex)?;
+		u16::try_from(value)
+			.ok()
+			.filter(|&value| (value as u32) < 1 << (4 * digits))
+			.ok_or_else(|| format!("{entry} doesn't fit in {digits} hex digits"))
+	})
+}
+
+/// Colour of the bytes in the memory region `address` is part of
+fn region_color(address: u16) -> Option<Color32> {
+	match address {
+		0x00..=0xff => Some(ZERO_PAGE_COLOR),
+		0x100..=0x1ff => Some(STACK_COLOR),
+		SCREEN_MEMORY_START..=SCREEN_MEMORY_END => Some(SCREEN_COLOR),
+		_ => None,
+	}
+}
+
+pub struct App {
+	pub paused: bool,
+	pub step: bool,
+	/// Dropped whenever execution pauses, whether on reaching it or not
+	run_target: Option<RunTarget>,
+	pub rewind: Option<Rewind>,
+	/// Position in the execution history and the number of instructions in it
+	pub timeline: (usize, usize),
+	pub save_state: Option<SaveStateRequest>,
+	/// Outcome of the last save or restore
+	pub save_state_message: Option<String>,
+	/// Bytes edited from the Memory and Watchpoints windows, written by the
+	/// frontend so they become part of the execution history
+	pub memory_edits: Vec<(u16, u8)>,
+	/// Registers edited from the CPU window, set by the frontend through the
+	/// execution history like `memory_edits`
+	pub register_edit: Option<cpu::CpuState>,
+	/// Target clock rate of the CPU in Hz
+	pub clock_rate: u32,
+	pub sources: Vec<SourceFile>,
+	/// Index into `sources` of the file shown, follows the program counter
+	current_source: usize,
+	/// Program counter the source view last followed, the file shown only
+	/// changes on its own once it moves
+	followed_program_counter: Option<u16>,
+	/// Index into `sources` and line to bring into view in the Source Code
+	/// window
+	scroll_to: Option<(usize, usize)>,
+	/// Labels shown in place of addresses and accepted in address entries
+	pub debug_info: DebugInfo,
 	pub reset: bool,
-	/// Vector of line numbers
-	breakpoints: Vec<usize>,
+	/// Fault that paused the CPU, cleared on reset
+	pub fault: Option<cpu::CpuError>,
+	/// Error from loading or assembling the program, cleared once a reload
+	/// succeeds
+	pub load_error: Option<String>,
+	/// Index into `sources` and line number of each breakpoint
+	breakpoints: Vec<(usize, usize)>,
+	/// Breakpoints set from the disassembly or entered as an address
+	/// expression
+	address_breakpoints: Vec<u16>,
 	breakpoints_user_entry: String,
-	break_address: Vec<u16>,
-	watchpoints: Vec<u16>,
+	/// Why the breakpoint entered last couldn't be added
+	breakpoints_error: Option<String>,
+	/// First address shown in the Disassembly window, moved to the program
+	/// counter when it leaves the window
+	disassembly_start: u16,
+	/// Address each breakpoint is at, checked after every instruction
+	break_locations: Vec<(u16, Location)>,
+	/// Breakpoints with a condition or counts, dropped along with the
+	/// breakpoint
+	breakpoint_settings: HashMap<Location, BreakpointSettings>,
+	/// Breakpoint shown in the editor of the Breakpoints window
+	editing: Option<Location>,
+	watchpoints: Vec<Watchpoint>,
 	watchpoints_user_entry: String,
+	/// Why the watchpoint or byte entered last was rejected
+	watchpoints_error: Option<String>,
+	/// Address of the instruction that triggered a watchpoint and the access,
+	/// cleared once the next instruction runs
+	pub watch_hit: Option<(u16, WatchHit)>,
+	memory: MemoryView,
+	memory_user_entry: String,
+	/// Why the address or byte entered last in the Memory window was rejected
+	memory_error: Option<String>,
+	/// Why the last register edit was rejected
+	register_error: Option<String>,
 	ui_scale: f32,
 	pub window_scale: f32,
 }
 
 impl App {
-	pub fn new(debug_symbols: Vec<u16>, source_file: Vec<String>) -> Self {
+	pub fn new(sources: Vec<SourceFile>, debug_info: DebugInfo) -> Self {
 		Self {
 			step: false,
 			paused: false,
-			instructions_per_frame: 100,
-			debug_symbols,
-			source_file,
+			run_target: None,
+			rewind: None,
+			timeline: (0, 0),
+			save_state: None,
+			save_state_message: None,
+			memory_edits: Vec::new(),
+			register_edit: None,
+			clock_rate: 20_000,
+			sources,
+			current_source: 0,
+			followed_program_counter: None,
+			scroll_to: None,
+			debug_info,
 			reset: false,
+			fault: None,
+			load_error: None,
 			breakpoints: vec![],
+			address_breakpoints: vec![],
 			breakpoints_user_entry: String::new(),
-			break_address: vec![],
+			breakpoints_error: None,
+			disassembly_start: 0,
+			break_locations: vec![],
+			breakpoint_settings: HashMap::new(),
+			editing: None,
 			watchpoints: vec![],
 			watchpoints_user_entry: String::new(),
+			watchpoints_error: None,
+			watch_hit: None,
+			memory: MemoryView::default(),
+			memory_user_entry: String::new(),
+			memory_error: None,
+			register_error: None,
 			ui_scale: 1.,
 			window_scale: 0.95,
 		}
 	}
-	pub fn breakpoints_addresses(&self) -> &[u16] {
-		&self.break_address
+	/// Records reaching the breakpoints at the program counter, returning
+	/// whether any of them stops execution
+	pub fn hit_breakpoint(&mut self, state: &cpu::CpuState, mem: &Memory) -> bool {
+		let mut stop = false;
+		for (address, location) in &self.break_locations {
+			if *address == state.program_counter {
+				stop |= self
+					.breakpoint_settings
+					.get_mut(location)
+					.is_none_or(|settings| settings.trigger.hit(state, mem));
+			}
+		}
+		stop
+	}
+	/// Records `opcode` having executed, leaving the CPU in `state`, pausing
+	/// if that reaches the target of Step Over, Step Out or Run to Cursor
+	pub fn check_run_target(&mut self, opcode: u8, state: &cpu::CpuState) {
+		if self
+			.run_target
+			.is_some_and(|target| target.reached(opcode, state))
+		{
+			self.paused = true;
+		}
+	}
+	/// Data breakpoints for [`Memory::watches`]
+	pub fn watches(&self) -> Vec<Watch> {
+		self.watchpoints
+			.iter()
+			.filter_map(|watchpoint| {
+				Some(Watch {
+					range: watchpoint.range.clone(),
+					access: watchpoint.break_on?,
+					value: self.parse_value(&watchpoint.value_entry).ok(),
+				})
+			})
+			.collect()
+	}
+	/// Whether a breakpoint is at the program counter with its condition met,
+	/// leaving hit counts alone
+	pub fn is_breakpoint(&self, state: &cpu::CpuState, mem: &Memory) -> bool {
+		self.break_locations.iter().any(|(address, location)| {
+			*address == state.program_counter
+				&& self
+					.breakpoint_settings
+					.get(location)
+					.and_then(|settings| settings.trigger.condition.as_ref())
+					.is_none_or(|condition| condition.is_true(state, mem))
+		})
+	}
+	/// Starts counting breakpoint hits over, for when the CPU is reset
+	pub fn reset_hits(&mut self) {
+		for settings in self.breakpoint_settings.values_mut() {
+			settings.trigger.hits = 0;
+		}
+	}
+	/// Swaps in a reloaded program, moving each breakpoint to the closest line
+	/// with the same text in the file of the same name and dropping those whose
+	/// line no longer exists. Conditions are parsed again against the new
+	/// labels.
+	pub fn reload(&mut self, sources: Vec<SourceFile>, debug_info: DebugInfo) {
+		let old_sources = std::mem::replace(&mut self.sources, sources);
+		self.debug_info = debug_info;
+		self.current_source = old_sources
+			.get(self.current_source)
+			.and_then(|old| self.sources.iter().position(|new| new.name == old.name))
+			.unwrap_or_default();
+		let moved: Vec<_> = self
+			.breakpoints
+			.iter()
+			.filter_map(|&(old_file, old_line)| {
+				let old = old_sources.get(old_file)?;
+				let file = self.sources.iter().position(|new| new.name == old.name)?;
+				let line = breakpoint::remap_line(&old.lines, &self.sources[file].lines, old_line)?;
+				Some(((old_file, old_line), (file, line)))
+			})
+			.collect();
+		self.breakpoint_settings = std::mem::take(&mut self.breakpoint_settings)
+			.into_iter()
+			.filter_map(|(location, mut settings)| {
+				let location = match location {
+					Location::Line(file, line) => moved
+						.iter()
+						.find(|(old, _)| *old == (file, line))
+						.map(|&(_, (file, line))| Location::Line(file, line))?,
+					location => location,
+				};
+				settings.update_condition(&self.debug_info);
+				Some((location, settings))
+			})
+			.collect();
+		self.breakpoints = moved.into_iter().map(|(_, new)| new).collect();
+		self.breakpoints.sort_unstable();
+		self.breakpoints.dedup();
+		self.update_break_addresses();
+	}
+	fn update_break_addresses(&mut self) {
+		self.break_locations = self
+			.breakpoints
+			.iter()
+			.filter_map(|&(file, line)| {
+				let address = self.sources.get(file)?.debug_symbols.get(line - 1)?;
+				Some((*address, Location::Line(file, line)))
+			})
+			.chain(
+				self.address_breakpoints
+					.iter()
+					.map(|&address| (address, Location::Address(address))),
+			)
+			.collect();
+		let breakpoints = &self.breakpoints;
+		let address_breakpoints = &self.address_breakpoints;
+		let exists = |location: &Location| match *location {
+			Location::Line(file, line) => breakpoints.contains(&(file, line)),
+			Location::Address(address) => address_breakpoints.contains(&address),
+		};
+		self.breakpoint_settings
+			.retain(|location, _| exists(location));
+		if !self.editing.as_ref().is_some_and(exists) {
+			self.editing = None;
+		}
 	}
-	pub fn render_ui(&mut self, ctx: &egui::Context, cpu: &cpu::Cpu, mem: &mut Memory) {
-		let current_line_number = self
-			.debug_symbols
+	/// Line the program counter is on in each source file
+	fn current_lines(&self, program_counter: u16) -> Vec<Option<usize>> {
+		self.sources
 			.iter()
-			.position(|&i| i == cpu.state().program_counter)
-			.unwrap();
+			.map(|source| {
+				source
+					.debug_symbols
+					.iter()
+					.position(|&i| i == program_counter)
+			})
+			.collect()
+	}
+	/// `address` in hex, followed by its label and offset when there is one
+	fn address_text(&self, address: u16) -> String {
+		match self.debug_info.symbolize(address) {
+			Some(symbol) => format!("${address:04x} {symbol}"),
+			None => format!("${address:04x}"),
+		}
+	}
+	/// Instruction text with the branch, jump or call target as a label
+	fn instruction_text(&self, line: &disasm::Line) -> String {
+		match line
+			.target
+			.and_then(|target| Some((target, self.debug_info.symbolize(target)?)))
+		{
+			Some((target, symbol)) => line.text.replace(&format!("${target:04x}"), &symbol),
+			None => line.text.clone(),
+		}
+	}
+	/// Address typed into an entry, an expression like `fb+2` or
+	/// `snake_body+2`, see [`DebugInfo::evaluate`]
+	fn parse_address(&self, entry: &str) -> Result<u16, String> {
+		self.debug_info.evaluate(entry)
+	}
+	/// Summary of the breakpoint's condition and counts along with a button
+	/// to edit them
+	fn breakpoint_row(
+		&self,
+		ui: &mut egui::Ui,
+		location: Location,
+		to_edit: &mut Option<Location>,
+	) {
+		if let Some(settings) = self.breakpoint_settings.get(&location) {
+			ui.label(
+				egui::RichText::new(settings.summary())
+					.monospace()
+					.color(Color32::GRAY),
+			);
+		}
+		if ui
+			.selectable_label(self.editing == Some(location), "Edit")
+			.clicked()
+		{
+			*to_edit = Some(location);
+		}
+	}
+	/// Byte typed into an entry, parsed the same way as addresses
+	fn parse_value(&self, entry: &str) -> Result<u8, String> {
+		let value = self.parse_address(entry)?;
+		u8::try_from(value).map_err(|_| format!("${value:x} doesn't fit in a byte"))
+	}
+	/// Address or `start..end` range typed into an entry
+	fn parse_range(&self, entry: &str) -> Result<RangeInclusive<u16>, String> {
+		let (start, end) = match entry.split_once("..") {
+			Some((start, end)) => (self.parse_address(start)?, self.parse_address(end)?),
+			None => {
+				let address = self.parse_address(entry)?;
+				(address, address)
+			}
+		};
+		if start > end {
+			return Err(format!("${start:04x} is after ${end:04x}"));
+		}
+		Ok(start..=end)
+	}
+	/// Searches for the bytes entered in the Memory window, starting after the
+	/// last match
+	fn find_in_memory(&self, mem: &Memory) -> Result<RangeInclusive<u16>, String> {
+		let pattern = self
+			.memory
+			.search_entry
+			.split_whitespace()
+			.map(|byte| match byte {
+				"??" => Ok(None),
+				_ => number::parse(byte, Radix::Hex)
+					.ok()
+					.and_then(|value| u8::try_from(value).ok())
+					.map(Some)
+					.ok_or_else(|| format!("Invalid byte {byte}")),
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+		if pattern.is_empty() {
+			return Err("Nothing to find".into());
+		}
+		let from = match &self.memory.found {
+			Some(Ok(range)) => range.start().wrapping_add(1),
+			_ => 0,
+		};
+		let start = mem.find(&pattern, from).ok_or("Not found")?;
+		Ok(start..=start + (pattern.len() - 1) as u16)
+	}
+	/// Byte in the Memory window, turning into an entry once clicked that
+	/// writes the value back when it loses focus
+	fn memory_byte(&mut self, ui: &mut egui::Ui, address: u16, mem: &Memory) {
+		if let Some((editing, entry)) = &mut self.memory.editing {
+			if *editing == address {
+				let response = egui::TextEdit::singleline(entry)
+					.code_editor()
+					.desired_width(16.)
+					.ui(ui);
+				if response.lost_focus() {
+					if !ui.input(|input| input.key_pressed(egui::Key::Escape)) {
+						let entry = entry.clone();
+						match self.parse_value(&entry) {
+							Ok(value) => {
+								self.memory_edits.push((address, value));
+								self.memory_error = None;
+							}
+							Err(error) => self.memory_error = Some(error),
+						}
+					}
+					self.memory.editing = None;
+				} else if !response.has_focus() {
+					response.request_focus();
+				}
+				return;
+			}
+		}
+		let value = mem.peek(address);
+		let mut text = egui::RichText::new(format!("{value:02x}")).monospace();
+		if let Some(color) = region_color(address) {
+			text = text.color(color);
+		}
+		if let Some(Ok(range)) = &self.memory.found {
+			if range.contains(&address) {
+				text = text.background_color(FOUND_COLOR);
+			}
+		}
+		if self.memory.changed(address, value) {
+			text = text.background_color(CHANGED_COLOR);
+		}
+		if ui
+			.add(egui::Label::new(text).sense(egui::Sense::click()))
+			.on_hover_text(self.address_text(address))
+			.clicked()
+		{
+			self.memory.editing = Some((address, format!("{value:02x}")));
+		}
+	}
+	/// First source file and line index assembled at `address`
+	fn source_line(&self, address: u16) -> Option<(usize, usize)> {
+		self.sources.iter().enumerate().find_map(|(file, source)| {
+			let line = source.debug_symbols.iter().position(|&i| i == address)?;
+			Some((file, line))
+		})
+	}
+	pub fn render_ui(&mut self, ctx: &egui::Context, cpu: &cpu::Cpu, mem: &Memory) {
+		if self.paused {
+			self.run_target = None;
+		}
+		let current_lines = self.current_lines(cpu.state().program_counter);
+		// Switches to the file the program counter is in when it leaves the
+		// one shown, leaving files picked while it stands still alone
+		if self.followed_program_counter != Some(cpu.state().program_counter)
+			&& current_lines
+				.get(self.current_source)
+				.copied()
+				.flatten()
+				.is_none()
+		{
+			if let Some(file) = current_lines.iter().position(Option::is_some) {
+				self.current_source = file;
+			}
+		}
+		self.followed_program_counter = Some(cpu.state().program_counter);
+		let current_line_number = current_lines.get(self.current_source).copied().flatten();
 		egui::Window::new("Debug Controls").show(ctx, |ui| {
 			ui.horizontal(|ui| {
 				ui.label("UI Scale: ");
@@ -65,11 +664,12 @@ impl App {
 				ui.add(egui::Slider::new(&mut self.window_scale, 0.1f32..=1.))
 			});
 			ui.horizontal(|ui| {
-				ui.label("Simulation Speed: ");
-				ui.add(egui::Slider::new(
-					&mut self.instructions_per_frame,
-					1u32..=500,
-				))
+				ui.label("Clock Rate: ");
+				ui.add(
+					egui::Slider::new(&mut self.clock_rate, 1_000u32..=2_000_000)
+						.logarithmic(true)
+						.suffix(" Hz"),
+				)
 			});
 			ui.horizontal(|ui| {
 				if ui
@@ -82,36 +682,147 @@ impl App {
 				{
 					self.paused = !self.paused;
 				};
-				if self.paused {
-					if ui.add(egui::Button::new("Step")).clicked() {
-						self.step = true
-					};
-				}
+				if self.paused && ui.add(egui::Button::new("Step")).clicked() {
+					self.step = true
+				};
+				let state = cpu.state();
+				if self.paused && ui.add(egui::Button::new("Step Over")).clicked() {
+					// Anything but a call is a single step
+					match RunTarget::step_over(mem, &state) {
+						Some(target) => {
+							self.run_target = Some(target);
+							self.paused = false;
+						}
+						None => self.step = true,
+					}
+				};
+				if self.paused && ui.add(egui::Button::new("Step Out")).clicked() {
+					self.run_target = Some(RunTarget::step_out(&state));
+					self.paused = false;
+				};
 				if ui.add(egui::Button::new("Reset")).clicked() {
 					self.reset = true
 				};
 			});
+			if self.paused {
+				let (mut position, len) = self.timeline;
+				ui.horizontal(|ui| {
+					if ui
+						.add_enabled(position > 0, egui::Button::new("Step Back"))
+						.clicked()
+					{
+						self.rewind = Some(Rewind::StepBack);
+					}
+					if ui
+						.add_enabled(position > 0, egui::Button::new("Reverse Continue"))
+						.clicked()
+					{
+						self.rewind = Some(Rewind::ReverseContinue);
+					}
+				});
+				ui.horizontal(|ui| {
+					ui.label("History: ");
+					if ui
+						.add(egui::Slider::new(&mut position, 0..=len).suffix(format!(" / {len}")))
+						.changed()
+					{
+						self.rewind = Some(Rewind::Seek(position));
+					}
+				});
+			}
+			if let Some(fault) = self.fault {
+				ui.label("Fault:");
+				ui.label(
+					egui::RichText::new(fault.to_string())
+						.monospace()
+						.color(Color32::RED),
+				);
+				ui.label(
+					egui::RichText::new(format!(
+						"PC: {}, Opcode: 0x{:02x}",
+						self.address_text(fault.address()),
+						mem.peek(fault.address())
+					))
+					.monospace()
+					.color(Color32::RED),
+				);
+			}
+			if let Some((address, hit)) = self.watch_hit {
+				ui.label("Watchpoint:");
+				let access = match hit.access {
+					Access::Read => format!("Read ${:02x}", hit.new),
+					Access::Write | Access::Change => {
+						format!("Wrote ${:02x} over ${:02x}", hit.new, hit.old)
+					}
+				};
+				ui.label(
+					egui::RichText::new(format!("{access} at {}", self.address_text(hit.address)))
+						.monospace()
+						.color(Color32::LIGHT_YELLOW),
+				);
+				let line = disasm::disassemble(cpu, mem, address);
+				ui.label(
+					egui::RichText::new(format!(
+						"By {}: {}",
+						self.address_text(address),
+						self.instruction_text(&line)
+					))
+					.monospace()
+					.color(Color32::LIGHT_YELLOW),
+				);
+			}
+			// Registers and flags can be edited while paused, written back once
+			// all of them are shown
 			let cpu_state = cpu.state();
+			let mut edited = cpu_state;
+			let mut register_error = None;
 			ui.label("Program Counter:");
-			ui.label(
-				egui::RichText::new(format!("0x{:04x}", cpu_state.program_counter))
-					.monospace()
-					.color(Color32::GOLD),
-			);
+			ui.horizontal(|ui| {
+				match register_entry(ui, cpu_state.program_counter, 4, Color32::GOLD, self.paused) {
+					Some(Ok(value)) => edited.program_counter = value,
+					Some(Err(error)) => register_error = Some(error),
+					None => (),
+				}
+				if let Some(label) = self.debug_info.symbolize(cpu_state.program_counter) {
+					ui.label(egui::RichText::new(label).monospace().color(Color32::GOLD));
+				}
+			});
 			ui.label("Stack Pointer:");
+			match register_entry(
+				ui,
+				cpu_state.stack_pointer as u16,
+				2,
+				Color32::BROWN,
+				self.paused,
+			) {
+				Some(Ok(value)) => edited.stack_pointer = value as u8,
+				Some(Err(error)) => register_error = Some(error),
+				None => (),
+			}
+			ui.label("Cycles:");
 			ui.label(
-				egui::RichText::new(format!("0x{:02x}", cpu_state.stack_pointer))
+				egui::RichText::new(cpu_state.cycles.to_string())
 					.monospace()
-					.color(Color32::BROWN),
+					.color(Color32::LIGHT_BLUE),
 			);
 			ui.label("Line Number:");
 			ui.label(
-				egui::RichText::new((current_line_number + 1).to_string())
-					.monospace()
-					.color(Color32::LIGHT_RED),
+				egui::RichText::new(
+					current_line_number.map_or(String::from("-"), |line| (line + 1).to_string()),
+				)
+				.monospace()
+				.color(Color32::LIGHT_RED),
 			);
 			ui.add(egui::Label::new("Instruction:"));
-			let line = self.source_file[current_line_number].trim_start();
+			// Falls back to the disassembly when there is no source line
+			let line = match current_line_number {
+				Some(line) => self.sources[self.current_source].lines[line]
+					.trim_start()
+					.to_string(),
+				None => {
+					self.instruction_text(&disasm::disassemble(cpu, mem, cpu_state.program_counter))
+				}
+			};
 			ui.horizontal(|ui| {
 				for (i, words) in line.split_whitespace().enumerate() {
 					ui.label(egui::RichText::new(words).monospace().color(if i == 0 {
@@ -122,49 +833,241 @@ impl App {
 				}
 			});
 			ui.add(egui::Label::new("Registers:"));
-			ui.label(
-				egui::RichText::new(format!(
-					"A: 0x{:02x}, X: 0x{:02x}, Y: 0x{:02x}",
-					cpu_state.a, cpu_state.x, cpu_state.y
-				))
-				.monospace()
-				.color(Color32::LIGHT_GREEN),
-			);
+			ui.horizontal(|ui| {
+				for (name, register) in [
+					("A", &mut edited.a),
+					("X", &mut edited.x),
+					("Y", &mut edited.y),
+				] {
+					ui.label(
+						egui::RichText::new(format!("{name}:"))
+							.monospace()
+							.color(Color32::LIGHT_GREEN),
+					);
+					match register_entry(ui, *register as u16, 2, Color32::LIGHT_GREEN, self.paused)
+					{
+						Some(Ok(value)) => *register = value as u8,
+						Some(Err(error)) => register_error = Some(error),
+						None => (),
+					}
+				}
+			});
+			ui.add(egui::Label::new("Flags:"));
+			ui.horizontal(|ui| {
+				for (name, flag) in STATUS_FLAGS {
+					// B and the unused bit only exist once pushed to the stack
+					let editable = self.paused
+						&& !matches!(flag, cpu::StatusFlags::Break | cpu::StatusFlags::Unused);
+					let set = cpu_state.get_flag(flag);
+					if ui
+						.add_enabled(
+							editable,
+							egui::SelectableLabel::new(set, egui::RichText::new(name).monospace()),
+						)
+						.clicked()
+					{
+						edited.set_flag(flag, !set);
+					}
+				}
+			});
+			if edited != cpu_state {
+				self.register_edit = Some(edited);
+				self.register_error = None;
+			}
+			if register_error.is_some() {
+				self.register_error = register_error;
+			}
+			if let Some(error) = &self.register_error {
+				ui.label(egui::RichText::new(error).color(Color32::RED));
+			}
+			ui.add(egui::Label::new("Return Addresses:"));
+			for address in disasm::return_addresses(mem, cpu_state.stack_pointer) {
+				ui.label(
+					egui::RichText::new(self.address_text(address))
+						.monospace()
+						.color(Color32::BROWN),
+				);
+			}
+		});
+		egui::Window::new("Call Stack").show(ctx, |ui| {
+			let call_stack = cpu.call_stack();
+			if call_stack.is_empty() {
+				ui.label("No calls in progress");
+			}
+			// Innermost first, clicking a frame shows its caller in the Source
+			// Code window
+			for frame in call_stack.iter().rev() {
+				let kind = match frame.kind {
+					cpu::FrameKind::Call => "",
+					cpu::FrameKind::Irq => "IRQ ",
+					cpu::FrameKind::Nmi => "NMI ",
+					cpu::FrameKind::Brk => "BRK ",
+				};
+				let target = self
+					.debug_info
+					.symbolize(frame.target)
+					.unwrap_or_else(|| format!("${:04x}", frame.target));
+				let source_line = self.source_line(frame.caller);
+				let location = match source_line {
+					Some((file, line)) => format!("  {}:{}", self.sources[file].name, line + 1),
+					None => String::new(),
+				};
+				if egui::Label::new(
+					egui::RichText::new(format!(
+						"{kind}{target} from {}{location}",
+						self.address_text(frame.caller)
+					))
+					.monospace()
+					.color(Color32::LIGHT_BLUE),
+				)
+				.sense(egui::Sense::click())
+				.ui(ui)
+				.clicked()
+				{
+					if let Some((file, line)) = source_line {
+						self.current_source = file;
+						self.scroll_to = Some((file, line));
+					}
+				}
+			}
+		});
+		egui::Window::new("Disassembly").show(ctx, |ui| {
+			let program_counter = cpu.state().program_counter;
+			let disassemble = |start| {
+				let mut address = start;
+				(0..DISASSEMBLY_LINES)
+					.map(|_| {
+						let line = disasm::disassemble(cpu, mem, address);
+						address = address.wrapping_add(line.bytes.len() as u16);
+						line
+					})
+					.collect::<Vec<_>>()
+			};
+			let mut lines = disassemble(self.disassembly_start);
+			if !lines.iter().any(|line| line.address == program_counter) {
+				self.disassembly_start = program_counter;
+				lines = disassemble(program_counter);
+			}
+			for line in lines {
+				for label in self.debug_info.labels_at(line.address) {
+					ui.label(
+						egui::RichText::new(format!("{label}:"))
+							.monospace()
+							.color(Color32::LIGHT_BLUE),
+					);
+				}
+				ui.horizontal(|ui| {
+					let response = egui::Label::new(
+						egui::RichText::new(format!("{:04x}", line.address))
+							.monospace()
+							.background_color(if line.address == program_counter {
+								Color32::RED
+							} else if self.address_breakpoints.contains(&line.address) {
+								Color32::BLUE
+							} else {
+								Color32::default()
+							}),
+					)
+					.sense(egui::Sense::click())
+					.ui(ui);
+					if response.clicked() {
+						if let Some(index) = self
+							.address_breakpoints
+							.iter()
+							.position(|&i| i == line.address)
+						{
+							self.address_breakpoints.remove(index);
+						} else {
+							self.address_breakpoints.push(line.address);
+						}
+					}
+					response.context_menu(|ui| {
+						if ui.button("Run to Cursor").clicked() {
+							self.run_target = Some(RunTarget::Address(line.address));
+							self.paused = false;
+							ui.close_menu();
+						}
+					});
+					let bytes: Vec<_> = line
+						.bytes
+						.iter()
+						.map(|byte| format!("{byte:02x}"))
+						.collect();
+					ui.label(egui::RichText::new(format!("{:<8}", bytes.join(" "))).monospace());
+					ui.label(
+						egui::RichText::new(self.instruction_text(&line))
+							.monospace()
+							.color(Color32::KHAKI),
+					);
+				});
+			}
 		});
 		egui::Window::new("Source Code").show(ctx, |ui| {
+			if self.sources.is_empty() {
+				ui.label("No source loaded, use the Disassembly window to debug.");
+			} else if self.sources.len() > 1 {
+				egui::ComboBox::from_label("File")
+					.selected_text(&self.sources[self.current_source].name)
+					.show_ui(ui, |ui| {
+						for (i, source) in self.sources.iter().enumerate() {
+							ui.selectable_value(&mut self.current_source, i, &source.name);
+						}
+					});
+			}
+			let current = self.current_source;
 			egui::ScrollArea::vertical().hscroll(true).show(ui, |ui| {
-				self.source_file
-					.iter()
+				self.sources
+					.get(current)
+					.into_iter()
+					.flat_map(|source| source.lines.iter())
 					.enumerate()
 					.for_each(|(line_number, line)| {
 						ui.horizontal(|ui| {
-							if egui::Label::new(
-								egui::RichText::new(format!(
-									"{}{} ",
-									" ".repeat(3 - (line_number + 1).to_string().len()),
-									line_number + 1
-								))
-								.monospace()
-								.background_color(if line_number == current_line_number {
-									Color32::RED
-								} else if self.breakpoints.contains(&(line_number + 1)) {
-									Color32::BLUE
-								} else {
-									Color32::default()
-								}),
+							let response = egui::Label::new(
+								egui::RichText::new(format!("{:>3} ", line_number + 1))
+									.monospace()
+									.background_color(
+										if Some(line_number) == current_line_number {
+											Color32::RED
+										} else if self
+											.breakpoints
+											.contains(&(current, line_number + 1))
+										{
+											Color32::BLUE
+										} else {
+											Color32::default()
+										},
+									),
 							)
 							.sense(egui::Sense::click())
-							.ui(ui)
-							.clicked()
-							{
+							.ui(ui);
+							if response.clicked() {
+								let breakpoint = (current, line_number + 1);
 								if let Some(index) =
-									self.breakpoints.iter().position(|&i| i == line_number + 1)
+									self.breakpoints.iter().position(|&i| i == breakpoint)
 								{
 									self.breakpoints.remove(index);
 								} else {
-									self.breakpoints.push(line_number + 1);
+									self.breakpoints.push(breakpoint);
 								}
 							};
+							if self.scroll_to == Some((current, line_number)) {
+								response.scroll_to_me(Some(egui::Align::Center));
+								self.scroll_to = None;
+							}
+							let emits_code = self.sources[current].emits_code[line_number];
+							response.context_menu(|ui| {
+								// Other lines would run to the instruction before them
+								if ui
+									.add_enabled(emits_code, egui::Button::new("Run to Cursor"))
+									.clicked()
+								{
+									let address = self.sources[current].debug_symbols[line_number];
+									self.run_target = Some(RunTarget::Address(address));
+									self.paused = false;
+									ui.close_menu();
+								}
+							});
 							if line.contains(";") {
 								ui.label(
 									egui::RichText::new(line)
@@ -192,7 +1095,7 @@ impl App {
 												Color32::GOLD
 											})
 											.background_color(
-												if current_line_number == line_number {
+												if current_line_number == Some(line_number) {
 													Color32::DARK_RED
 												} else {
 													Color32::default()
@@ -208,49 +1111,125 @@ impl App {
 		egui::Window::new("Watchpoints").show(ctx, |ui| {
 			ui.horizontal(|ui| {
 				ui.label("Address:");
-				if ui
+				if (ui
 					.add(
 						egui::TextEdit::singleline(&mut self.watchpoints_user_entry)
 							.font(egui::TextStyle::Monospace)
-							.desired_width(40.)
-							.hint_text("in hex"),
+							.desired_width(80.)
+							.hint_text("fb, label+2 or start..end"),
 					)
-					.lost_focus() || ui.button("Add").clicked()
+					.lost_focus() || ui.button("Add").clicked())
+					&& !self.watchpoints_user_entry.trim().is_empty()
 				{
-					// TODO: handle the case with 0x as prefix
-					if let Ok(line_number) = u16::from_str_radix(&self.watchpoints_user_entry, 16) {
-						if !self.watchpoints.contains(&line_number) {
-							self.watchpoints.push(line_number);
+					match self.parse_range(&self.watchpoints_user_entry) {
+						Ok(range) => {
+							if !self.watchpoints.iter().any(|w| w.range == range) {
+								self.watchpoints.push(Watchpoint {
+									range,
+									break_on: None,
+									value_entry: String::new(),
+								});
+							}
+							self.watchpoints_user_entry.clear();
+							self.watchpoints_error = None;
 						}
+						Err(error) => self.watchpoints_error = Some(error),
 					}
-					self.watchpoints_user_entry.clear();
 				}
 			});
+			let mut watchpoints_error = None;
 			let mut to_remove = Vec::new();
-			for (i, &watchpoint) in self.watchpoints.iter().enumerate() {
+			let mut edits = Vec::new();
+			let mut memory_edits = Vec::new();
+			for (i, watchpoint) in self.watchpoints.iter().enumerate() {
 				ui.horizontal(|ui| {
-					let mut user_entry = format!("{:x}", mem.read_byte(watchpoint));
+					let (start, end) = (*watchpoint.range.start(), *watchpoint.range.end());
+					let text = if start == end {
+						self.address_text(start)
+					} else {
+						format!("{}..${end:04x}", self.address_text(start))
+					};
 					ui.label(
-						egui::RichText::new(format!("0x{watchpoint:04x}"))
+						egui::RichText::new(text)
 							.monospace()
 							.color(Color32::LIGHT_YELLOW),
 					);
-					if egui::TextEdit::singleline(&mut user_entry)
-						.code_editor()
-						.desired_width(30.)
-						.interactive(self.paused)
-						.ui(ui)
-						.changed()
-					{
-						if let Ok(new_value) = u8::from_str_radix(&user_entry, 16) {
-							mem.write_byte(watchpoint, new_value)
+					if start == end {
+						let mut user_entry = format!("{:x}", mem.peek(start));
+						if egui::TextEdit::singleline(&mut user_entry)
+							.code_editor()
+							.desired_width(30.)
+							.interactive(self.paused)
+							.ui(ui)
+							.changed()
+						{
+							match self.parse_value(&user_entry) {
+								Ok(new_value) => memory_edits.push((start, new_value)),
+								Err(error) => watchpoints_error = Some(error),
+							}
+						}
+					} else {
+						let bytes: Vec<_> = (start..=end.min(start.saturating_add(15)))
+							.map(|address| format!("{:02x}", mem.peek(address)))
+							.collect();
+						let more = if end - start >= 16 { " .." } else { "" };
+						ui.label(egui::RichText::new(bytes.join(" ") + more).monospace());
+					}
+					let mut break_on = watchpoint.break_on;
+					let mut value_entry = watchpoint.value_entry.clone();
+					let name = |break_on| match break_on {
+						None => "Show",
+						Some(Access::Read) => "Read",
+						Some(Access::Write) => "Write",
+						Some(Access::Change) => "Change",
+					};
+					egui::ComboBox::from_id_source(("watchpoint", i))
+						.selected_text(name(break_on))
+						.width(70.)
+						.show_ui(ui, |ui| {
+							for option in [
+								None,
+								Some(Access::Read),
+								Some(Access::Write),
+								Some(Access::Change),
+							] {
+								ui.selectable_value(&mut break_on, option, name(option));
+							}
+						});
+					if break_on.is_some() {
+						egui::TextEdit::singleline(&mut value_entry)
+							.code_editor()
+							.desired_width(30.)
+							.hint_text("any, 0c or #12")
+							.ui(ui);
+						if !value_entry.trim().is_empty() {
+							if let Err(error) = self.parse_value(&value_entry) {
+								ui.label(egui::RichText::new(error).color(Color32::RED));
+							}
 						}
 					}
+					if break_on != watchpoint.break_on || value_entry != watchpoint.value_entry {
+						edits.push((i, break_on, value_entry));
+					}
 					if ui.button("X").clicked() {
 						to_remove.push(i);
 					}
 				});
 			}
+			if !memory_edits.is_empty() {
+				self.watchpoints_error = None;
+			}
+			self.memory_edits.extend(memory_edits);
+			if watchpoints_error.is_some() {
+				self.watchpoints_error = watchpoints_error;
+			}
+			if let Some(error) = &self.watchpoints_error {
+				ui.label(egui::RichText::new(error).color(Color32::RED));
+			}
+			for (i, break_on, value_entry) in edits {
+				self.watchpoints[i].break_on = break_on;
+				self.watchpoints[i].value_entry = value_entry;
+			}
 			to_remove.iter().for_each(|i| {
 				self.watchpoints.remove(*i);
 			});
@@ -259,66 +1238,280 @@ impl App {
 			.anchor(Align2::LEFT_BOTTOM, [10., -10.])
 			.show(ctx, |ui| {
 				ui.horizontal(|ui| {
-					ui.label("Line number:");
+					ui.label("Line, label or $address:");
 					if ui
 						.add(
 							egui::TextEdit::singleline(&mut self.breakpoints_user_entry)
-								.desired_width(40.),
+								.desired_width(80.),
 						)
 						.lost_focus() || ui.button("Add").clicked()
 					{
-						if let Ok(line_number) = self.breakpoints_user_entry.parse() {
-							if !self.breakpoints.contains(&line_number) {
-								self.breakpoints.push(line_number);
+						// Plain numbers are line numbers, addresses need a `$`
+						let entry = self.breakpoints_user_entry.trim();
+						let added = if entry.is_empty() {
+							Ok(())
+						} else if let Ok(line_number) = entry.parse() {
+							let breakpoint = (self.current_source, line_number);
+							if line_number > 0 && !self.breakpoints.contains(&breakpoint) {
+								self.breakpoints.push(breakpoint);
+							}
+							Ok(())
+						} else {
+							self.parse_address(entry).map(|address| {
+								if !self.address_breakpoints.contains(&address) {
+									self.address_breakpoints.push(address);
+								}
+							})
+						};
+						match added {
+							Ok(()) => {
+								self.breakpoints_user_entry.clear();
+								self.breakpoints_error = None;
 							}
+							Err(error) => self.breakpoints_error = Some(error),
 						}
-						self.breakpoints_user_entry.clear();
 					}
 				});
+				if let Some(error) = &self.breakpoints_error {
+					ui.label(egui::RichText::new(error).color(Color32::RED));
+				}
+				let mut to_edit = None;
 				let mut to_remove = Vec::new();
-				for (i, &breakpoint) in self.breakpoints.iter().enumerate() {
+				for (i, &(file, breakpoint)) in self.breakpoints.iter().enumerate() {
+					ui.horizontal(|ui| {
+						let text = match &self.sources[..] {
+							[_, _, ..] => format!("{}:{breakpoint}", self.sources[file].name),
+							_ => format!("{breakpoint:>3}"),
+						};
+						ui.label(egui::RichText::new(text).monospace().color(
+							if file == self.current_source
+								&& Some(breakpoint) == current_line_number.map(|line| line + 1)
+							{
+								Color32::LIGHT_RED
+							} else {
+								Color32::LIGHT_BLUE
+							},
+						));
+						let location = Location::Line(file, breakpoint);
+						self.breakpoint_row(ui, location, &mut to_edit);
+						if ui.button("X").clicked() {
+							to_remove.push(i);
+						}
+					});
+				}
+				to_remove.iter().for_each(|i| {
+					self.breakpoints.remove(*i);
+				});
+				let mut to_remove = Vec::new();
+				for (i, &address) in self.address_breakpoints.iter().enumerate() {
 					ui.horizontal(|ui| {
 						ui.label(
-							egui::RichText::new(format!("{breakpoint:>3}"))
+							egui::RichText::new(self.address_text(address))
 								.monospace()
-								.color(if breakpoint == current_line_number + 1 {
+								.color(if address == cpu.state().program_counter {
 									Color32::LIGHT_RED
 								} else {
 									Color32::LIGHT_BLUE
 								}),
 						);
+						self.breakpoint_row(ui, Location::Address(address), &mut to_edit);
 						if ui.button("X").clicked() {
 							to_remove.push(i);
 						}
 					});
 				}
 				to_remove.iter().for_each(|i| {
-					self.breakpoints.remove(*i);
+					self.address_breakpoints.remove(*i);
 				});
+				if let Some(location) = to_edit {
+					self.editing = (self.editing != Some(location)).then_some(location);
+				}
+				if let Some(location) = self.editing {
+					ui.separator();
+					let settings = self.breakpoint_settings.entry(location).or_default();
+					ui.horizontal(|ui| {
+						ui.label("Condition:");
+						if egui::TextEdit::singleline(&mut settings.condition)
+							.font(egui::TextStyle::Monospace)
+							.hint_text("A == 10 && [fb] != 0")
+							.ui(ui)
+							.changed()
+						{
+							settings.update_condition(&self.debug_info);
+						}
+					});
+					if let Some(error) = &settings.error {
+						ui.label(egui::RichText::new(error).color(Color32::RED));
+					}
+					ui.horizontal(|ui| {
+						ui.label("Ignore count:");
+						ui.add(egui::DragValue::new(&mut settings.trigger.ignore_count));
+					});
+					ui.horizontal(|ui| {
+						let mut on_hit = settings.trigger.hit_count.is_some();
+						let mut count = settings.trigger.hit_count.unwrap_or(1);
+						ui.checkbox(&mut on_hit, "Break only on hit");
+						ui.add_enabled(
+							on_hit,
+							egui::DragValue::new(&mut count).clamp_range(1..=u32::MAX),
+						);
+						settings.trigger.hit_count = on_hit.then_some(count);
+					});
+					ui.horizontal(|ui| {
+						ui.label(format!("Hits: {}", settings.trigger.hits));
+						if ui.button("Clear").clicked() {
+							settings.trigger.hits = 0;
+						}
+						if ui.button("Done").clicked() {
+							self.editing = None;
+						}
+					});
+				}
+			});
+		self.memory.update(cpu.state().cycles, mem);
+		egui::Window::new("Memory").show(ctx, |ui| {
+			ui.horizontal(|ui| {
+				ui.label("Address:");
+				if (ui
+					.add(
+						egui::TextEdit::singleline(&mut self.memory_user_entry)
+							.font(egui::TextStyle::Monospace)
+							.desired_width(80.)
+							.hint_text("fb or label+2"),
+					)
+					.lost_focus() || ui.button("Go").clicked())
+					&& !self.memory_user_entry.trim().is_empty()
+				{
+					match self.parse_address(&self.memory_user_entry) {
+						Ok(address) => {
+							self.memory.scroll_to = Some(address);
+							self.memory_user_entry.clear();
+							self.memory_error = None;
+						}
+						Err(error) => self.memory_error = Some(error),
+					}
+				}
+			});
+			if let Some(error) = &self.memory_error {
+				ui.label(egui::RichText::new(error).color(Color32::RED));
+			}
+			ui.horizontal(|ui| {
+				ui.label("Find:");
+				// Only on Enter, clicking Next also takes the focus away
+				let response = ui.add(
+					egui::TextEdit::singleline(&mut self.memory.search_entry)
+						.font(egui::TextStyle::Monospace)
+						.desired_width(120.)
+						.hint_text("a9 ?? 85, ?? for any"),
+				);
+				let entered =
+					response.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+				if entered || ui.button("Next").clicked() {
+					let found = self.find_in_memory(mem);
+					if let Ok(range) = &found {
+						self.memory.scroll_to = Some(*range.start());
+					}
+					self.memory.found = Some(found);
+				}
+				if let Some(Err(error)) = &self.memory.found {
+					ui.label(egui::RichText::new(error).color(Color32::RED));
+				}
+			});
+			ui.horizontal(|ui| {
+				for (name, color) in [
+					("Zero Page", ZERO_PAGE_COLOR),
+					("Stack", STACK_COLOR),
+					("Screen", SCREEN_COLOR),
+				] {
+					ui.label(egui::RichText::new(name).color(color));
+				}
+				ui.label(egui::RichText::new("Changed").background_color(CHANGED_COLOR));
+			});
+			let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+			let mut scroll_area = egui::ScrollArea::vertical()
+				.max_height((row_height + ui.spacing().item_spacing.y) * MEMORY_ROWS as f32);
+			if let Some(address) = self.memory.scroll_to.take() {
+				scroll_area = scroll_area.vertical_scroll_offset(
+					(address / 16) as f32 * (row_height + ui.spacing().item_spacing.y),
+				);
+			}
+			scroll_area.show_rows(ui, row_height, MEMORY_SIZE / 16, |ui, rows| {
+				for row in rows {
+					let start = (row * 16) as u16;
+					ui.horizontal(|ui| {
+						ui.spacing_mut().item_spacing.x = 4.;
+						ui.label(egui::RichText::new(format!("{start:04x} ")).monospace());
+						for address in (0..16).map(|i| start + i) {
+							self.memory_byte(ui, address, mem);
+						}
+						let text: String = (0..16)
+							.map(|i| match mem.peek(start + i) {
+								value @ 0x20..=0x7e => value as char,
+								_ => '.',
+							})
+							.collect();
+						ui.label(egui::RichText::new(format!(" {text}")).monospace());
+					});
+				}
 			});
+		});
+		egui::Window::new("Save States").show(ctx, |ui| {
+			for slot in 1..=SAVE_SLOTS {
+				ui.horizontal(|ui| {
+					ui.label(format!("Slot {slot}"));
+					if ui.button("Save").clicked() {
+						self.save_state = Some(SaveStateRequest::Save(slot));
+					}
+					if ui.button("Load").clicked() {
+						self.save_state = Some(SaveStateRequest::Load(slot));
+					}
+				});
+			}
+			ui.label("Shift+F1 to F4 saves, F1 to F4 loads");
+			if let Some(message) = &self.save_state_message {
+				ui.label(message);
+			}
+		});
+		ctx.input(|input| {
+			for (slot, key) in (1..).zip(SLOT_KEYS) {
+				if input.key_pressed(key) {
+					self.save_state = Some(if input.modifiers.shift {
+						SaveStateRequest::Save(slot)
+					} else {
+						SaveStateRequest::Load(slot)
+					});
+				}
+			}
+		});
+		if let Some(error) = &self.load_error {
+			egui::Window::new("Load Error")
+				.anchor(Align2::CENTER_CENTER, [0., 0.])
+				.show(ctx, |ui| {
+					ui.label(egui::RichText::new(error).monospace().color(Color32::RED));
+				});
+		}
 		#[cfg(target_family = "wasm")]
 		egui::Window::new("Help").show(ctx, |ui| {
-			ui.label(egui::RichText::new(
-				"1. Use the arrow or WASD keys to move the snake.",
-			).color(Color32::LIGHT_GREEN));
-			ui.label(egui::RichText::new(
-				"2. Click 'Pause' button in 'Debug Controls' to pause execution.",
-			).color(Color32::LIGHT_GREEN));
+			ui.label(
+				egui::RichText::new("1. Use the arrow or WASD keys to move the snake.")
+					.color(Color32::LIGHT_GREEN),
+			);
+			ui.label(
+				egui::RichText::new(
+					"2. Click 'Pause' button in 'Debug Controls' to pause execution.",
+				)
+				.color(Color32::LIGHT_GREEN),
+			);
 			ui.label(egui::RichText::new(
 				"3. Click on the line numbers on the 'Source Code' window to toggle breakpoints.",
 			).color(Color32::LIGHT_GREEN));
 			ui.label("Source Code:");
 			ui.hyperlink("https://github.com/ArchUsr64/6502_emulator/");
 		});
-		if self.breakpoints.contains(&(current_line_number + 1)) {
-			self.paused = true;
-		}
 		self.breakpoints.sort_unstable();
-		self.watchpoints.sort_unstable();
-		self.break_address = self
-			.breakpoints
-			.iter()
-			.map(|&i| self.debug_symbols[i - 1])
-			.collect();
+		self.address_breakpoints.sort_unstable();
+		self.watchpoints
+			.sort_by_key(|watchpoint| *watchpoint.range.start());
+		self.update_break_addresses();
 	}
 }
@@ -0,0 +1,411 @@
+//! Two pass assembler for the oldstyle syntax with dot directives used by the
+//! programs under `examples/`, producing the image and debug symbols the
+//! emulator loads.
+//!
+//! Supported syntax:
+//! - `label:` definitions, on their own line or before a statement
+//! - `.org ADDR`, `.byte VALUE, ...` and `.word VALUE, ...` directives
+//! - numbers in decimal, `$hex`, `0xhex` or `%binary` as read by
+//!   [`number::parse`], label names, `+`/`-` between terms, `-` to negate a
+//!   term and a leading `<`/`>` for the low or high byte
+//! - `;` comments
+//!
+//! Operands that fit in a byte use the zero page form of an instruction when
+//! one exists, labels defined after their first use take the absolute form
+//! unless the instruction has none, as with `stx label,y`. Negative bytes and
+//! words are stored in two's complement.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::bus::MEMORY_SIZE;
+use crate::cpu::{lookup, AddressingMode, Operation};
+use crate::debug_info::{DebugInfo, Label, LineInfo, Segment, SegmentKind};
+use crate::number::{self, Radix};
+
+/// Output of [`assemble`]
+#[derive(Clone)]
+pub struct Assembly {
+	/// Flat image loaded at address 0, bytes not emitted by the source are 0
+	pub image: [u8; MEMORY_SIZE],
+	/// Line number and address of every source line that emits bytes, in
+	/// source order
+	pub symbols: Vec<(usize, u16)>,
+	/// Label names and addresses, in the order they are defined
+	pub labels: Vec<(String, u16)>,
+	/// Contiguous runs of instructions or `.byte` and `.word` data
+	pub segments: Vec<Segment>,
+}
+
+impl Assembly {
+	/// Debug information with `path` as the only source file
+	pub fn debug_info(&self, path: &str) -> DebugInfo {
+		DebugInfo {
+			files: vec![path.to_string()],
+			lines: self
+				.symbols
+				.iter()
+				.map(|&(line, address)| LineInfo {
+					file: 0,
+					line,
+					address,
+				})
+				.collect(),
+			labels: self
+				.labels
+				.iter()
+				.map(|(name, address)| Label {
+					name: name.clone(),
+					address: *address,
+					scope: None,
+				})
+				.collect(),
+			scopes: Vec::new(),
+			segments: self.segments.clone(),
+		}
+	}
+}
+
+/// Error in the source, `line` and `column` start from 1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+	pub line: usize,
+	pub column: usize,
+	pub message: String,
+}
+
+impl fmt::Display for AsmError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}:{}: {}", self.line, self.column, self.message)
+	}
+}
+
+impl std::error::Error for AsmError {}
+
+/// Statement placed at an address by the first pass
+enum Statement<'a> {
+	Instruction(u8, AddressingMode, Option<&'a str>),
+	Data(Vec<&'a str>, u16),
+}
+
+struct Placed<'a> {
+	line: usize,
+	text: &'a str,
+	address: u16,
+	statement: Statement<'a>,
+}
+
+/// Assembles `source` into a flat memory image
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+	let mut labels = HashMap::new();
+	let mut label_order = Vec::new();
+	let mut placed = Vec::new();
+	let mut address = 0u32;
+	for (index, line) in source.lines().enumerate() {
+		let error = |at, message| AsmError {
+			line: index + 1,
+			column: column(line, at),
+			message,
+		};
+		let mut text = line.split(';').next().unwrap_or_default().trim();
+		if let Some((label, rest)) = text.split_once(':') {
+			let label = label.trim();
+			if is_identifier(label) {
+				if labels.insert(label, address as u16).is_some() {
+					return Err(error(label, format!("Label {label} is already defined")));
+				}
+				label_order.push(label);
+				text = rest.trim();
+			}
+		}
+		if text.is_empty() {
+			continue;
+		}
+		let (keyword, operand) = match text.split_once(char::is_whitespace) {
+			Some((keyword, operand)) => (keyword, Some(operand.trim())),
+			None => (text, None),
+		};
+		let statement = match keyword.to_ascii_lowercase().as_str() {
+			".org" => {
+				let operand =
+					operand.ok_or_else(|| error(keyword, "Expected an address".into()))?;
+				address = evaluate(operand, &labels)
+					.map_err(|(at, message)| error(at, message))?
+					.ok_or_else(|| error(operand, format!("Address {operand} must be known")))?
+					.try_into()
+					.ok()
+					.filter(|&address| address < MEMORY_SIZE as u32)
+					.ok_or_else(|| error(operand, format!("Address {operand} is out of range")))?;
+				continue;
+			}
+			".byte" | ".word" => {
+				let values: Vec<_> = operand
+					.ok_or_else(|| error(keyword, "Expected at least one value".into()))?
+					.split(',')
+					.map(str::trim)
+					.collect();
+				let width = if keyword.eq_ignore_ascii_case(".word") {
+					2
+				} else {
+					1
+				};
+				Statement::Data(values, width)
+			}
+			directive if directive.starts_with('.') => {
+				return Err(error(keyword, format!("Unknown directive {keyword}")));
+			}
+			_ => {
+				let (operation, mode, operand) = parse_instruction(keyword, operand, &labels)
+					.map_err(|(at, message)| error(at, message))?;
+				let opcode = opcode(operation, mode).ok_or_else(|| {
+					error(
+						keyword,
+						format!("{operation:?} does not support {mode:?} addressing"),
+					)
+				})?;
+				Statement::Instruction(opcode, mode, operand)
+			}
+		};
+		let statement = Placed {
+			line: index + 1,
+			text: line,
+			address: address as u16,
+			statement,
+		};
+		address += statement.len();
+		if address > MEMORY_SIZE as u32 {
+			return Err(error(text, "Program does not fit in memory".into()));
+		}
+		placed.push(statement);
+	}
+
+	let mut image = [0; MEMORY_SIZE];
+	let mut symbols = Vec::with_capacity(placed.len());
+	let mut segments: Vec<Segment> = Vec::new();
+	for statement in &placed {
+		let error = |at, message| AsmError {
+			line: statement.line,
+			column: column(statement.text, at),
+			message,
+		};
+		let value = |operand| {
+			evaluate(operand, &labels)
+				.map_err(|(at, message)| error(at, message))?
+				.ok_or_else(|| error(operand, format!("Undefined label in {operand}")))
+		};
+		let byte = |operand| {
+			let value = value(operand)?;
+			match value {
+				-0x80..=0xff => Ok(value as u8),
+				_ => Err(error(
+					operand,
+					format!("Value {operand} does not fit in a byte"),
+				)),
+			}
+		};
+		let bytes = match &statement.statement {
+			Statement::Instruction(opcode, mode, operand) => {
+				let mut bytes = vec![*opcode];
+				match (mode, operand) {
+					(_, None) => (),
+					(AddressingMode::Relative, Some(operand)) => {
+						let offset = value(operand)? - (statement.address as i32 + 2);
+						let offset = i8::try_from(offset).map_err(|_| {
+							error(operand, format!("Branch to {operand} is out of range"))
+						})?;
+						bytes.push(offset as u8);
+					}
+					(
+						AddressingMode::Absolute
+						| AddressingMode::AbsoluteX
+						| AddressingMode::AbsoluteY
+						| AddressingMode::Indirect,
+						Some(operand),
+					) => {
+						let address = u16::try_from(value(operand)?).map_err(|_| {
+							error(operand, format!("Address {operand} is out of range"))
+						})?;
+						bytes.extend(address.to_le_bytes());
+					}
+					(_, Some(operand)) => bytes.push(byte(operand)?),
+				}
+				bytes
+			}
+			Statement::Data(values, 1) => {
+				values.iter().map(|v| byte(v)).collect::<Result<_, _>>()?
+			}
+			Statement::Data(values, _) => values
+				.iter()
+				.map(|operand| match value(operand)? {
+					value @ -0x8000..=0xffff => Ok(value as u16),
+					_ => Err(error(
+						operand,
+						format!("Value {operand} does not fit in a word"),
+					)),
+				})
+				.collect::<Result<Vec<_>, _>>()?
+				.into_iter()
+				.flat_map(u16::to_le_bytes)
+				.collect(),
+		};
+		let start = statement.address as usize;
+		image[start..start + bytes.len()].copy_from_slice(&bytes);
+		symbols.push((statement.line, statement.address));
+
+		let end = statement.address + (bytes.len() - 1) as u16;
+		let kind = match statement.statement {
+			Statement::Instruction(..) => SegmentKind::Code,
+			Statement::Data(..) => SegmentKind::Data,
+		};
+		match segments.last_mut() {
+			Some(last) if last.kind == kind && *last.range.end() as u32 + 1 == start as u32 => {
+				last.range = *last.range.start()..=end;
+			}
+			_ => segments.push(Segment {
+				name: format!("{kind:?}").to_uppercase(),
+				range: statement.address..=end,
+				kind,
+			}),
+		}
+	}
+	let labels = label_order
+		.into_iter()
+		.map(|label| (label.to_string(), labels[label]))
+		.collect();
+	Ok(Assembly {
+		image,
+		symbols,
+		labels,
+		segments,
+	})
+}
+
+impl Placed<'_> {
+	/// Number of bytes the statement emits
+	fn len(&self) -> u32 {
+		match &self.statement {
+			Statement::Instruction(_, mode, _) => 1 + mode.operand_len() as u32,
+			Statement::Data(values, width) => values.len() as u32 * *width as u32,
+		}
+	}
+}
+
+/// Splits an instruction into its operation, addressing mode and operand
+/// expression, using `labels` defined so far to pick zero page forms
+fn parse_instruction<'a>(
+	mnemonic: &'a str,
+	operand: Option<&'a str>,
+	labels: &HashMap<&str, u16>,
+) -> Result<(Operation, AddressingMode, Option<&'a str>), (&'a str, String)> {
+	use AddressingMode::*;
+	let upper = mnemonic.to_ascii_uppercase();
+	let operation = (0..=u8::MAX)
+		.filter_map(lookup)
+		.map(|(operation, ..)| operation)
+		.find(|operation| format!("{operation:?}") == upper)
+		.ok_or_else(|| (mnemonic, format!("Unknown instruction {upper}")))?;
+	let Some(operand) = operand.filter(|operand| !operand.eq_ignore_ascii_case("a")) else {
+		return Ok((operation, Implicit, None));
+	};
+	let lower = operand
+		.to_ascii_lowercase()
+		.replace(char::is_whitespace, "");
+	let indexed = |suffix| {
+		lower
+			.strip_suffix(suffix)
+			.map(|_| operand[..operand.rfind(',').unwrap()].trim())
+	};
+	// Picks the zero page form when the value is known to fit in a byte, or
+	// when it is the only form and the second pass checks that it fits
+	let sized = |expression: &'a str, zero_page, absolute| {
+		let fits = match evaluate(expression, labels) {
+			Ok(Some(value)) => (0..=0xff).contains(&value),
+			_ => opcode(operation, absolute).is_none(),
+		};
+		if fits && opcode(operation, zero_page).is_some() {
+			(operation, zero_page, Some(expression))
+		} else {
+			(operation, absolute, Some(expression))
+		}
+	};
+	Ok(if let Some(value) = operand.strip_prefix('#') {
+		(operation, Immediate, Some(value.trim()))
+	} else if lower.starts_with('(') && lower.ends_with(",x)") {
+		let inner = &operand[1..operand.rfind(',').unwrap()];
+		(operation, IndexedIndirect, Some(inner.trim()))
+	} else if lower.starts_with('(') && lower.ends_with("),y") {
+		let inner = &operand[1..operand.rfind(')').unwrap()];
+		(operation, IndirectIndexed, Some(inner.trim()))
+	} else if lower.starts_with('(') && lower.ends_with(')') {
+		(
+			operation,
+			Indirect,
+			Some(operand[1..operand.len() - 1].trim()),
+		)
+	} else if let Some(expression) = indexed(",x") {
+		sized(expression, ZeroPageX, AbsoluteX)
+	} else if let Some(expression) = indexed(",y") {
+		sized(expression, ZeroPageY, AbsoluteY)
+	} else if opcode(operation, Relative).is_some() {
+		(operation, Relative, Some(operand))
+	} else {
+		sized(operand, ZeroPage, Absolute)
+	})
+}
+
+/// Opcode encoding `operation` with `mode`
+fn opcode(operation: Operation, mode: AddressingMode) -> Option<u8> {
+	(0..=u8::MAX)
+		.find(|&opcode| lookup(opcode).is_some_and(|(op, m, _)| op == operation && m == mode))
+}
+
+/// Column of `at` within `line`, which `at` is expected to be a slice of
+fn column(line: &str, at: &str) -> usize {
+	let offset = (at.as_ptr() as usize).wrapping_sub(line.as_ptr() as usize);
+	line.get(..offset)
+		.map_or(1, |before| before.chars().count() + 1)
+}
+
+fn is_identifier(name: &str) -> bool {
+	name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+		&& name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Evaluates `expression`, `None` if it refers to a label not defined yet.
+/// Errors carry the part of the expression they refer to
+fn evaluate<'a>(
+	expression: &'a str,
+	labels: &HashMap<&str, u16>,
+) -> Result<Option<i32>, (&'a str, String)> {
+	let (expression, select): (_, fn(i32) -> i32) = match expression.trim() {
+		e if e.starts_with('<') => (&e[1..], |value| value & 0xff),
+		e if e.starts_with('>') => (&e[1..], |value| (value >> 8) & 0xff),
+		e => (e, |value| value),
+	};
+	let mut total = Some(0);
+	let mut rest = expression.trim();
+	let mut sign = 1;
+	loop {
+		if let Some(negated) = rest.strip_prefix('-') {
+			sign = -sign;
+			rest = negated.trim_start();
+		}
+		let end = rest.find(['+', '-']).unwrap_or(rest.len());
+		let term = rest[..end].trim();
+		let value = if number::is_number(term) {
+			let value = number::parse(term, Radix::Decimal).map_err(|error| (term, error))?;
+			Some(i32::try_from(value).map_err(|_| (term, format!("Invalid number {term}")))?)
+		} else if is_identifier(term) {
+			labels.get(term).map(|&address| address as i32)
+		} else {
+			return Err((term, format!("Invalid expression {expression}")));
+		};
+		total = total.zip(value).map(|(total, value)| total + sign * value);
+		let Some(operator) = rest[end..].chars().next() else {
+			break;
+		};
+		sign = if operator == '+' { 1 } else { -1 };
+		rest = &rest[end + 1..];
+	}
+	Ok(total.map(select))
+}
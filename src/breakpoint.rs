@@ -0,0 +1,45 @@
+//! Conditions and counts deciding whether reaching a breakpoint stops
+//! execution, and following source breakpoints when the file is reloaded.
+
+use crate::bus::Memory;
+use crate::cpu::CpuState;
+use crate::expr::Expr;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Trigger {
+	/// Only hits where this evaluates to non zero are counted
+	pub condition: Option<Expr>,
+	/// Number of hits to let through before breaking
+	pub ignore_count: u32,
+	/// Breaks only on this hit, as in "the 50th time", instead of every one
+	/// past the ignore count
+	pub hit_count: Option<u32>,
+	/// Times the breakpoint was reached with its condition met
+	pub hits: u32,
+}
+
+impl Trigger {
+	/// Records the breakpoint being reached in `state`, returning whether
+	/// execution should stop
+	pub fn hit(&mut self, state: &CpuState, mem: &Memory) -> bool {
+		if let Some(condition) = &self.condition {
+			if !condition.is_true(state, mem) {
+				return false;
+			}
+		}
+		self.hits = self.hits.saturating_add(1);
+		self.hits > self.ignore_count && self.hit_count.is_none_or(|count| self.hits == count)
+	}
+}
+
+/// Line number, counted from 1, that a breakpoint on `line` of `old` moves to
+/// in `new`: the line with the same text, ignoring indentation, closest to
+/// where it was. `None` once no line has that text
+pub fn remap_line(old: &[String], new: &[String], line: usize) -> Option<usize> {
+	let text = old.get(line.checked_sub(1)?)?.trim();
+	new.iter()
+		.enumerate()
+		.filter(|(_, new_line)| new_line.trim() == text)
+		.map(|(index, _)| index + 1)
+		.min_by_key(|new_line| new_line.abs_diff(line))
+}
@@ -0,0 +1,316 @@
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+use log::debug;
+
+pub const MEMORY_SIZE: usize = 0x10000;
+
+/// Address bus the CPU reads from and writes to
+pub trait Bus {
+	fn read_byte(&mut self, address: u16) -> u8;
+	fn write_byte(&mut self, address: u16, value: u8);
+
+	/// Reads a little endian word
+	fn read_word(&mut self, address: u16) -> u16 {
+		let lower_byte = self.read_byte(address) as u16;
+		let higher_byte = self.read_byte(address.wrapping_add(1)) as u16;
+		higher_byte << 8 | lower_byte
+	}
+	/// Reads a byte, writes back the result of `f` and returns it
+	fn modify<F>(&mut self, address: u16, f: F) -> u8
+	where
+		F: Fn(u8) -> u8,
+	{
+		let value = f(self.read_byte(address));
+		self.write_byte(address, value);
+		value
+	}
+}
+
+/// Peripheral mapped to a range of addresses on the bus, `offset` is the
+/// distance from the start of the mapped range
+pub trait Device {
+	fn read(&mut self, offset: u16) -> u8;
+	fn write(&mut self, offset: u16, value: u8);
+
+	/// Value at `offset` for debuggers, without side effects. `None` for
+	/// devices where reading has side effects or gives no stable value
+	fn peek(&self, _offset: u16) -> Option<u8> {
+		None
+	}
+	/// Whether the device holds the IRQ line active
+	fn irq(&self) -> bool {
+		false
+	}
+	/// Whether the device holds the NMI line active
+	fn nmi(&self) -> bool {
+		false
+	}
+	/// State kept in save states besides what is read through the bus, empty
+	/// for devices without any
+	fn save(&self) -> Vec<u8> {
+		Vec::new()
+	}
+	/// Restores what [`Device::save`] returned, leaving the device untouched
+	/// when rejecting the state
+	fn load(&mut self, _state: &[u8]) -> Result<(), String> {
+		Ok(())
+	}
+}
+
+/// Allows the frontend to keep a handle on a device after mapping it
+impl<D: Device> Device for Rc<RefCell<D>> {
+	fn read(&mut self, offset: u16) -> u8 {
+		self.borrow_mut().read(offset)
+	}
+	fn write(&mut self, offset: u16, value: u8) {
+		self.borrow_mut().write(offset, value)
+	}
+	fn peek(&self, offset: u16) -> Option<u8> {
+		self.borrow().peek(offset)
+	}
+	fn irq(&self) -> bool {
+		self.borrow().irq()
+	}
+	fn nmi(&self) -> bool {
+		self.borrow().nmi()
+	}
+	fn save(&self) -> Vec<u8> {
+		self.borrow().save()
+	}
+	fn load(&mut self, state: &[u8]) -> Result<(), String> {
+		self.borrow_mut().load(state)
+	}
+}
+
+/// Kind of access a [`Watch`] stops on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+	Read,
+	Write,
+	/// A write storing a different value than the one already there
+	Change,
+}
+
+/// Data breakpoint over a range of addresses
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watch {
+	pub range: RangeInclusive<u16>,
+	pub access: Access,
+	/// Only accesses of this value count, the value read for reads and the
+	/// one written otherwise
+	pub value: Option<u8>,
+}
+
+impl Watch {
+	/// Whether a read (`write` false) or write of `new` over `old` at
+	/// `address` triggers the watch
+	fn matches(&self, address: u16, write: bool, old: u8, new: u8) -> bool {
+		self.range.contains(&address)
+			&& self.value.is_none_or(|value| value == new)
+			&& match self.access {
+				Access::Read => !write,
+				Access::Write => write,
+				Access::Change => write && old != new,
+			}
+	}
+}
+
+/// Access that triggered a [`Watch`], `old` and `new` are the same for reads
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+	pub address: u16,
+	pub access: Access,
+	pub old: u8,
+	pub new: u8,
+}
+
+struct MappedDevice {
+	range: RangeInclusive<u16>,
+	device: Box<dyn Device>,
+}
+
+/// 64 KiB of RAM with optional devices mapped over parts of it
+pub struct Memory {
+	pub data: Vec<u8>,
+	devices: Vec<MappedDevice>,
+	/// Checked on every read and write, the first access to trigger one is
+	/// kept until [`Memory::take_watch_hit`]
+	pub watches: Vec<Watch>,
+	watch_hit: Option<WatchHit>,
+	/// Every write made while set, as address, old value as given by
+	/// [`Memory::peek`] and new value, see [`crate::history::History`]
+	pub journal: Option<Vec<(u16, u8, u8)>>,
+}
+
+impl Memory {
+	pub fn new(data: [u8; MEMORY_SIZE]) -> Self {
+		Self {
+			data: data.to_vec(),
+			devices: Vec::new(),
+			watches: Vec::new(),
+			watch_hit: None,
+			journal: None,
+		}
+	}
+
+	/// Routes every access within `range` to `device` instead of RAM, ranges
+	/// mapped later take precedence over earlier ones when overlapping
+	pub fn map_device<D: Device + 'static>(&mut self, range: RangeInclusive<u16>, device: D) {
+		self.devices.insert(
+			0,
+			MappedDevice {
+				range,
+				device: Box::new(device),
+			},
+		);
+	}
+
+	/// Byte at `address` without side effects or watches being checked,
+	/// devices that can't be peeked at show the RAM underneath
+	pub fn peek(&self, address: u16) -> u8 {
+		self.devices
+			.iter()
+			.find(|mapped| mapped.range.contains(&address))
+			.and_then(|mapped| mapped.device.peek(address - mapped.range.start()))
+			.unwrap_or(self.data[address as usize])
+	}
+
+	/// Whether any mapped device holds the IRQ line active
+	pub fn irq(&self) -> bool {
+		self.devices.iter().any(|mapped| mapped.device.irq())
+	}
+
+	/// Whether any mapped device holds the NMI line active
+	pub fn nmi(&self) -> bool {
+		self.devices.iter().any(|mapped| mapped.device.nmi())
+	}
+
+	/// Address of the first match for `pattern` at or after `from`, wrapping
+	/// around to the start of memory. `None` in the pattern matches any byte
+	pub fn find(&self, pattern: &[Option<u8>], from: u16) -> Option<u16> {
+		let last = MEMORY_SIZE.checked_sub(pattern.len())?;
+		let matches = |start: usize| {
+			pattern
+				.iter()
+				.zip(start..)
+				.all(|(byte, address)| byte.is_none_or(|byte| byte == self.peek(address as u16)))
+		};
+		(from as usize..=last)
+			.chain(0..(from as usize).min(last + 1))
+			.find(|&start| matches(start))
+			.map(|start| start as u16)
+	}
+
+	/// State of every mapped device, see [`Device::save`]
+	pub fn save_devices(&self) -> Vec<Vec<u8>> {
+		self.devices
+			.iter()
+			.map(|mapped| mapped.device.save())
+			.collect()
+	}
+
+	/// Restores the state of every mapped device from what
+	/// [`Memory::save_devices`] returned, the same devices must be mapped.
+	/// Either every device is restored or, when one rejects its state, none
+	/// is. Without any mapped the states are ignored, so a headless run can
+	/// pick up a state saved from the GUI.
+	pub fn load_devices(&mut self, states: &[Vec<u8>]) -> Result<(), String> {
+		if self.devices.is_empty() {
+			return Ok(());
+		}
+		if states.len() != self.devices.len() {
+			return Err(format!(
+				"saved with {} devices, {} are mapped",
+				states.len(),
+				self.devices.len()
+			));
+		}
+		let previous = self.save_devices();
+		for (i, state) in states.iter().enumerate() {
+			if let Err(err) = self.devices[i].device.load(state) {
+				// Puts back the devices already restored
+				for (mapped, state) in self.devices.iter_mut().zip(&previous).take(i) {
+					mapped.device.load(state)?;
+				}
+				return Err(err);
+			}
+		}
+		Ok(())
+	}
+
+	/// First access to trigger a watch since the last call
+	pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+		self.watch_hit.take()
+	}
+
+	/// Records the access if it triggers a watch and none has been recorded
+	fn check_watches(&mut self, address: u16, write: bool, old: u8, new: u8) {
+		if self.watch_hit.is_some() {
+			return;
+		}
+		if let Some(watch) = self
+			.watches
+			.iter()
+			.find(|watch| watch.matches(address, write, old, new))
+		{
+			self.watch_hit = Some(WatchHit {
+				address,
+				access: watch.access,
+				old,
+				new,
+			});
+		}
+	}
+
+	/// Byte at `address` from the mapped device or RAM
+	fn load(&mut self, address: u16) -> u8 {
+		match self.device_at(address) {
+			Some((device, offset)) => device.read(offset),
+			None => self.data[address as usize],
+		}
+	}
+
+	fn device_at(&mut self, address: u16) -> Option<(&mut Box<dyn Device>, u16)> {
+		self.devices
+			.iter_mut()
+			.find(|mapped| mapped.range.contains(&address))
+			.map(|mapped| {
+				let offset = address - mapped.range.start();
+				(&mut mapped.device, offset)
+			})
+	}
+}
+
+impl Bus for Memory {
+	fn read_byte(&mut self, address: u16) -> u8 {
+		let value = self.load(address);
+		debug!("[Read]\t\t{:02x} from {:04x}", value, address);
+		if !self.watches.is_empty() {
+			self.check_watches(address, false, value, value);
+		}
+		value
+	}
+	fn write_byte(&mut self, address: u16, value: u8) {
+		debug!("[Write]\t\t{:02x} at {:04x}", value, address);
+		if let Some(mut journal) = self.journal.take() {
+			journal.push((address, self.peek(address), value));
+			self.journal = Some(journal);
+		}
+		// The old value is only needed for change watches, and is peeked so a
+		// device doesn't see a read the program never made
+		if self
+			.watches
+			.iter()
+			.any(|watch| watch.access != Access::Read && watch.range.contains(&address))
+		{
+			let old = self.peek(address);
+			self.check_watches(address, true, old, value);
+		}
+		match self.device_at(address) {
+			Some((device, offset)) => device.write(offset, value),
+			None => self.data[address as usize] = value,
+		}
+	}
+}
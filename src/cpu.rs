@@ -1,56 +1,45 @@
 use std::fmt;
 
-use log::{debug, error, info, warn};
+use log::{debug, info};
 
-const STACK_LOWEST_ADDRESS: u16 = 0x100;
-pub const MEMORY_SIZE: usize = 0x10000;
-pub struct Memory {
-	pub data: Vec<u8>,
-}
-impl Memory {
-	pub fn new(data: [u8; MEMORY_SIZE]) -> Self {
-		Self {
-			data: data.to_vec(),
-		}
-	}
-	pub fn read_byte(&self, address: u16) -> u8 {
-		debug!(
-			"[Read]\t\t{:02x} from {:04x}",
-			self.data[address as usize], address
-		);
-		self.data[address as usize]
-	}
-	fn read_word(&self, address: u16) -> u16 {
-		let lower_byte = self.read_byte(address) as u16;
-		let higher_byte = self.read_byte(address + 1) as u16;
-		higher_byte << 8 | lower_byte
-	}
-	pub fn write_byte(&mut self, address: u16, value: u8) {
-		debug!("[Write]\t\t{:02x} at {:04x}", value, address);
-		self.data[address as usize] = value;
-	}
+use crate::bus::{Bus, Memory};
+use crate::save_state::{Reader, SaveStateError};
 
-	fn modify<F>(&mut self, address: u16, f: F)
-	where
-		F: Fn(u8) -> u8,
-	{
-		self.data[address as usize] = f(self.data[address as usize]);
-	}
-}
+const STACK_LOWEST_ADDRESS: u16 = 0x100;
+const NMI_VECTOR: u16 = 0xfffa;
+const RESET_VECTOR: u16 = 0xfffc;
+const IRQ_VECTOR: u16 = 0xfffe;
+/// Clock cycles taken to push the state and jump through an interrupt vector
+const INTERRUPT_CYCLES: u8 = 7;
+/// NMOS opcodes that lock up the processor until the next reset
+const JAM_OPCODES: [u8; 12] = [
+	0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xb2, 0xd2, 0xf2,
+];
 
+/// Mnemonic of an instruction, independent of its addressing mode
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Clone, Copy, Debug)]
-enum Operation {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
 	/// Add with Carry
 	ADC,
+	/// AND then Logical Shift Right the accumulator, undocumented
+	ALR,
+	/// AND then copy bit 7 to Carry, undocumented
+	ANC,
 	/// Logical AND
 	AND,
+	/// AND then Rotate Right the accumulator, undocumented
+	ARR,
 	/// Arithemetic Shift Left
 	ASL,
 	/// Branch if Carry Clear
 	BCC,
 	/// Branch if Carry Set
 	BCS,
+	/// Branch on Bit `n` Reset in zero page, 65C02 only
+	BBR(u8),
+	/// Branch on Bit `n` Set in zero page, 65C02 only
+	BBS(u8),
 	/// Branch if Equal
 	BEQ,
 	/// Bit Test
@@ -61,6 +50,10 @@ enum Operation {
 	BNE,
 	/// Branch if Positive
 	BPL,
+	/// Branch Always, 65C02 only
+	BRA,
+	/// Force Interrupt
+	BRK,
 	/// Branch if Overflow Clear
 	BVC,
 	/// Branch if Overflow Set
@@ -79,6 +72,8 @@ enum Operation {
 	CPX,
 	/// Compare Y Register
 	CPY,
+	/// Decrement Memory then Compare, undocumented
+	DCP,
 	/// Decrement Memory
 	DEC,
 	/// Decrement X Register
@@ -93,10 +88,17 @@ enum Operation {
 	INX,
 	/// Increment Y Register
 	INY,
+	/// Increment Memory then Subtract with Carry, undocumented
+	ISC,
 	/// Jump
 	JMP,
 	/// Jump to Subroutine
 	JSR,
+	/// AND Memory with Stack Pointer into A, X and the Stack Pointer,
+	/// undocumented
+	LAS,
+	/// Load Accumulator and X Register, undocumented
+	LAX,
 	/// Load Accumulator
 	LDA,
 	/// Load X Register
@@ -113,34 +115,68 @@ enum Operation {
 	PHA,
 	/// Push Processor Status
 	PHP,
+	/// Push X Register, 65C02 only
+	PHX,
+	/// Push Y Register, 65C02 only
+	PHY,
 	/// Pull Accumulator
 	PLA,
 	/// Pull Processor Status
 	PLP,
+	/// Pull X Register, 65C02 only
+	PLX,
+	/// Pull Y Register, 65C02 only
+	PLY,
+	/// Rotate Left Memory then AND, undocumented
+	RLA,
+	/// Reset Memory Bit `n` in zero page, 65C02 only
+	RMB(u8),
 	/// Rotate Left
 	ROL,
 	/// Rotate Right
 	ROR,
+	/// Rotate Right Memory then Add with Carry, undocumented
+	RRA,
+	/// Return from Interrupt
+	RTI,
 	/// Return from Subroutine
 	RTS,
+	/// Store Accumulator AND X Register, undocumented
+	SAX,
 	/// Subtract with Carry
 	SBC,
+	/// X Register = Accumulator AND X minus operand, undocumented
+	SBX,
 	/// Set Carry Flag
 	SEC,
 	/// Set Decimal Flag
 	SED,
 	/// Set Interrupt Disable
 	SEI,
+	/// Shift Left Memory then OR, undocumented
+	SLO,
+	/// Set Memory Bit `n` in zero page, 65C02 only
+	SMB(u8),
+	/// Shift Right Memory then Exclusive OR, undocumented
+	SRE,
 	/// Store Accumulator
 	STA,
 	/// Store X
 	STX,
 	/// Store Y
 	STY,
+	/// Stop the processor until reset, 65C02 only
+	STP,
+	/// Store Zero, 65C02 only
+	STZ,
 	/// Transfer Accumulator to X
 	TAX,
 	/// Transfer Accumulator to Y
 	TAY,
+	/// Test and Reset Memory Bits against the accumulator, 65C02 only
+	TRB,
+	/// Test and Set Memory Bits against the accumulator, 65C02 only
+	TSB,
 	/// Transfer Stack Pointer to X
 	TSX,
 	/// Transfer X to Accumulator
@@ -149,19 +185,50 @@ enum Operation {
 	TXS,
 	/// Transfer Y to Accumulator
 	TYA,
+	/// Wait for Interrupt, 65C02 only
+	WAI,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum Operand {
+impl Operation {
+	/// Whether indexing across a page boundary costs an extra cycle, stores and
+	/// read-modify-write instructions always take the worst case time instead,
+	/// except for the 65C02 shifts and rotates
+	fn has_page_cross_penalty(self, variant: Variant) -> bool {
+		use Operation::*;
+		if variant.is_cmos() && matches!(self, ASL | LSR | ROL | ROR) {
+			return true;
+		}
+		!matches!(
+			self,
+			STA | STX
+				| STY | STZ | SAX
+				| ASL | LSR | ROL
+				| ROR | INC | DEC
+				| SLO | SRE | RLA
+				| RRA | DCP | ISC
+		)
+	}
+}
+
+/// Operand after the addressing mode has been resolved
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+	/// Immediate value or signed branch offset
 	Value(u8),
+	/// Effective address the instruction operates on
 	Address(u16),
+	/// Zero page address to test followed by a signed branch offset, used by
+	/// `BBR` and `BBS`
+	ZeroPageRelative(u16, u8),
 }
 
-#[derive(Clone, Copy, Debug)]
-struct Instruction(Operation, Option<Operand>);
+/// Decoded instruction, `None` for the operand when the addressing mode is
+/// implicit or accumulator
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instruction(pub Operation, pub Option<Operand>);
 
-#[derive(Clone, Copy, Debug)]
-enum AddressingMode {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressingMode {
 	Implicit,
 	Immediate,
 	ZeroPage,
@@ -174,47 +241,622 @@ enum AddressingMode {
 	Indirect,
 	IndexedIndirect,
 	IndirectIndexed,
+	/// `(zp)`, 65C02 only
+	ZeroPageIndirect,
+	/// `(abs,x)`, used by the 65C02 `JMP`
+	AbsoluteIndexedIndirect,
+	/// `zp,rel`, used by `BBR` and `BBS`
+	ZeroPageRelative,
 }
 
 impl AddressingMode {
-	fn get_operand(&self, cpu: &mut Cpu, mem: &Memory) -> Option<Operand> {
+	/// Number of bytes following the opcode
+	pub fn operand_len(self) -> u16 {
+		use AddressingMode::*;
+		match self {
+			Implicit => 0,
+			Immediate | ZeroPage | ZeroPageX | ZeroPageY | Relative | IndexedIndirect
+			| IndirectIndexed | ZeroPageIndirect => 1,
+			Absolute
+			| AbsoluteX
+			| AbsoluteY
+			| Indirect
+			| AbsoluteIndexedIndirect
+			| ZeroPageRelative => 2,
+		}
+	}
+	/// Fetches the operand, also reporting if indexing crossed a page boundary
+	fn get_operand<B: Bus>(&self, cpu: &mut Cpu, mem: &mut B) -> (Option<Operand>, bool) {
 		use AddressingMode as AM;
 		use Operand as Op;
-		Some(match self {
-			AM::Immediate => Op::Value(cpu.fetch_byte(mem)),
-			AM::ZeroPage => Op::Address(cpu.fetch_byte(mem) as u16),
-			AM::ZeroPageX => Op::Address(cpu.fetch_byte(mem).wrapping_add(cpu.x) as u16),
-			AM::ZeroPageY => Op::Address(cpu.fetch_byte(mem).wrapping_add(cpu.y) as u16),
-			AM::Absolute => Op::Address(cpu.fetch_word(mem)),
-			AM::AbsoluteX => Op::Address(cpu.fetch_word(mem).wrapping_add(cpu.x as u16)),
-			AM::AbsoluteY => Op::Address(cpu.fetch_word(mem).wrapping_add(cpu.y as u16)),
-			AM::Indirect => Op::Address(mem.read_word(cpu.fetch_word(mem))),
+		let indexed = |base: u16, index: u8| {
+			let address = base.wrapping_add(index as u16);
+			(Op::Address(address), base & 0xff00 != address & 0xff00)
+		};
+		let (operand, page_crossed) = match self {
+			AM::Immediate => (Op::Value(cpu.fetch_byte(mem)), false),
+			AM::ZeroPage => (Op::Address(cpu.fetch_byte(mem) as u16), false),
+			AM::ZeroPageX => (
+				Op::Address(cpu.fetch_byte(mem).wrapping_add(cpu.x) as u16),
+				false,
+			),
+			AM::ZeroPageY => (
+				Op::Address(cpu.fetch_byte(mem).wrapping_add(cpu.y) as u16),
+				false,
+			),
+			AM::Absolute => (Op::Address(cpu.fetch_word(mem)), false),
+			AM::AbsoluteX => indexed(cpu.fetch_word(mem), cpu.x),
+			AM::AbsoluteY => indexed(cpu.fetch_word(mem), cpu.y),
+			AM::Indirect => {
+				let pointer = cpu.fetch_word(mem);
+				if cpu.variant.is_cmos() {
+					return (Some(Op::Address(mem.read_word(pointer))), false);
+				}
+				// The high byte is fetched without carrying into the page, so
+				// JMP ($xxff) reads it from $xx00
+				let lower_byte = mem.read_byte(pointer) as u16;
+				let higher_byte =
+					mem.read_byte(pointer & 0xff00 | pointer.wrapping_add(1) & 0x00ff) as u16;
+				(Op::Address(higher_byte << 8 | lower_byte), false)
+			}
 			//(Indirect, X)
 			//Zero page address specified at the next byte + X as indexing register
 			AM::IndexedIndirect => {
-				Op::Address(mem.read_word(cpu.fetch_byte(mem).wrapping_add(cpu.x) as u16))
+				let pointer = cpu.fetch_byte(mem).wrapping_add(cpu.x);
+				(Op::Address(read_zero_page_word(mem, pointer)), false)
 			}
 			//(Indirect), Y
 			//16-bit address specified at the zero page at next byte address + Y as indexing register
-			AM::IndirectIndexed => Op::Address({
-				let address_from_zero_page = mem.read_word(cpu.fetch_byte(mem) as u16);
-				address_from_zero_page.wrapping_add(cpu.y as u16)
-			}),
-			AM::Relative => Op::Value(cpu.fetch_byte(mem)),
-			AM::Implicit => return None,
-		})
+			AM::IndirectIndexed => {
+				let pointer = cpu.fetch_byte(mem);
+				let address_from_zero_page = read_zero_page_word(mem, pointer);
+				indexed(address_from_zero_page, cpu.y)
+			}
+			AM::ZeroPageIndirect => {
+				let pointer = cpu.fetch_byte(mem);
+				(Op::Address(read_zero_page_word(mem, pointer)), false)
+			}
+			AM::AbsoluteIndexedIndirect => {
+				let pointer = cpu.fetch_word(mem).wrapping_add(cpu.x as u16);
+				(Op::Address(mem.read_word(pointer)), false)
+			}
+			AM::ZeroPageRelative => {
+				let address = cpu.fetch_byte(mem) as u16;
+				(Op::ZeroPageRelative(address, cpu.fetch_byte(mem)), false)
+			}
+			AM::Relative => (Op::Value(cpu.fetch_byte(mem)), false),
+			AM::Implicit => return (None, false),
+		};
+		(Some(operand), page_crossed)
+	}
+}
+
+/// Pointers stored at `0xff` wrap around to `0x00` for their high byte
+fn read_zero_page_word<B: Bus>(mem: &mut B, pointer: u8) -> u16 {
+	let lower_byte = mem.read_byte(pointer as u16) as u16;
+	let higher_byte = mem.read_byte(pointer.wrapping_add(1) as u16) as u16;
+	higher_byte << 8 | lower_byte
+}
+
+/// What a successful call to [`Cpu::execute`] did
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+	/// Ran an instruction taking this many clock cycles
+	Executed(u8),
+	/// Serviced a pending NMI or IRQ instead of running an instruction
+	Interrupted(u8),
+	/// Idled after a `WAI`, waiting for an interrupt
+	Waiting(u8),
+}
+
+impl StepOutcome {
+	pub fn cycles(self) -> u8 {
+		match self {
+			Self::Executed(cycles) | Self::Interrupted(cycles) | Self::Waiting(cycles) => cycles,
+		}
+	}
+}
+
+/// Fault raised by [`Cpu::execute`], `address` is where the offending
+/// instruction starts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuError {
+	/// The byte doesn't decode to any instruction, the program counter is left
+	/// pointing at it
+	InvalidOpcode { address: u16, opcode: u8 },
+	/// The processor locked up, only a reset recovers it
+	Jam { address: u16, opcode: u8 },
+	/// A 65C02 `STP` halted the processor, only a reset recovers it
+	Stopped { address: u16 },
+	/// Decoded operand doesn't fit the operation
+	InvalidInstruction {
+		address: u16,
+		instruction: Instruction,
+	},
+	/// A push wrapped the stack pointer from 0x00 to 0xff, only raised with
+	/// [`Cpu::set_stack_checks`] and after the instruction completed
+	StackOverflow { address: u16 },
+	/// A pull wrapped the stack pointer from 0xff to 0x00, see
+	/// [`CpuError::StackOverflow`]
+	StackUnderflow { address: u16 },
+}
+
+impl CpuError {
+	pub fn address(self) -> u16 {
+		match self {
+			Self::InvalidOpcode { address, .. }
+			| Self::Jam { address, .. }
+			| Self::Stopped { address }
+			| Self::InvalidInstruction { address, .. }
+			| Self::StackOverflow { address }
+			| Self::StackUnderflow { address } => address,
+		}
 	}
 }
 
+impl fmt::Display for CpuError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::InvalidOpcode { address, opcode } => {
+				write!(f, "invalid opcode 0x{opcode:02x} at 0x{address:04x}")
+			}
+			Self::Jam { address, opcode } => {
+				write!(f, "jammed by opcode 0x{opcode:02x} at 0x{address:04x}")
+			}
+			Self::InvalidInstruction {
+				address,
+				instruction,
+			} => write!(f, "invalid instruction {instruction:x?} at 0x{address:04x}"),
+			Self::Stopped { address } => write!(f, "stopped by STP at 0x{address:04x}"),
+			Self::StackOverflow { address } => write!(f, "stack overflow at 0x{address:04x}"),
+			Self::StackUnderflow { address } => write!(f, "stack underflow at 0x{address:04x}"),
+		}
+	}
+}
+
+impl std::error::Error for CpuError {}
+
+/// Chip the emulated core behaves like
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Variant {
+	/// Original MOS 6502
+	Nmos,
+	/// Ricoh 2A03, an NMOS core with decimal mode disconnected
+	Ricoh2A03,
+	/// WDC 65C02, the CMOS redesign including the Rockwell bit instructions
+	Wdc65C02,
+}
+
+impl Variant {
+	fn has_decimal_mode(self) -> bool {
+		match self {
+			Self::Nmos | Self::Wdc65C02 => true,
+			Self::Ricoh2A03 => false,
+		}
+	}
+	fn has_undocumented_opcodes(self) -> bool {
+		match self {
+			Self::Nmos | Self::Ricoh2A03 => true,
+			Self::Wdc65C02 => false,
+		}
+	}
+	fn is_cmos(self) -> bool {
+		self == Self::Wdc65C02
+	}
+}
+
+impl std::str::FromStr for Variant {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"6502" | "nmos" => Ok(Self::Nmos),
+			"2a03" | "ricoh" => Ok(Self::Ricoh2A03),
+			"65c02" | "cmos" => Ok(Self::Wdc65C02),
+			_ => Err(format!(
+				"Unknown CPU variant: {s}, expected one of: 6502, 2a03, 65c02"
+			)),
+		}
+	}
+}
+
+/// Decodes an opcode to its operation, addressing mode and base cycle count,
+/// `None` for opcodes outside the documented instruction set
+pub fn lookup(opcode: u8) -> Option<(Operation, AddressingMode, u8)> {
+	use AddressingMode::*;
+	use Operation::*;
+	Some(match opcode {
+		//Add with Carry
+		0x69 => (ADC, Immediate, 2),
+		0x65 => (ADC, ZeroPage, 3),
+		0x75 => (ADC, ZeroPageX, 4),
+		0x6d => (ADC, Absolute, 4),
+		0x7d => (ADC, AbsoluteX, 4),
+		0x79 => (ADC, AbsoluteY, 4),
+		0x61 => (ADC, IndexedIndirect, 6),
+		0x71 => (ADC, IndirectIndexed, 5),
+		//Logical AND
+		0x29 => (AND, Immediate, 2),
+		0x25 => (AND, ZeroPage, 3),
+		0x35 => (AND, ZeroPageX, 4),
+		0x2d => (AND, Absolute, 4),
+		0x3d => (AND, AbsoluteX, 4),
+		0x39 => (AND, AbsoluteY, 4),
+		0x21 => (AND, IndexedIndirect, 6),
+		0x31 => (AND, IndirectIndexed, 5),
+		//Arithemetic Shift Left
+		0x0a => (ASL, Implicit, 2),
+		0x06 => (ASL, ZeroPage, 5),
+		0x16 => (ASL, ZeroPageX, 6),
+		0x0e => (ASL, Absolute, 6),
+		0x1e => (ASL, AbsoluteX, 7),
+		//BIT
+		0x24 => (BIT, ZeroPage, 3),
+		0x2c => (BIT, Absolute, 4),
+		//Compare
+		0xc9 => (CMP, Immediate, 2),
+		0xc5 => (CMP, ZeroPage, 3),
+		0xd5 => (CMP, ZeroPageX, 4),
+		0xcd => (CMP, Absolute, 4),
+		0xdd => (CMP, AbsoluteX, 4),
+		0xd9 => (CMP, AbsoluteY, 4),
+		0xc1 => (CMP, IndexedIndirect, 6),
+		0xd1 => (CMP, IndirectIndexed, 5),
+		//Compare X
+		0xe0 => (CPX, Immediate, 2),
+		0xe4 => (CPX, ZeroPage, 3),
+		0xec => (CPX, Absolute, 4),
+		//Compare Y
+		0xc0 => (CPY, Immediate, 2),
+		0xc4 => (CPY, ZeroPage, 3),
+		0xcc => (CPY, Absolute, 4),
+		//Decrement
+		0xc6 => (DEC, ZeroPage, 5),
+		0xd6 => (DEC, ZeroPageX, 6),
+		0xce => (DEC, Absolute, 6),
+		0xde => (DEC, AbsoluteX, 7),
+		//Decrement X
+		0xca => (DEX, Implicit, 2),
+		//Decrement Y
+		0x88 => (DEY, Implicit, 2),
+		//Exclusive OR
+		0x49 => (EOR, Immediate, 2),
+		0x45 => (EOR, ZeroPage, 3),
+		0x55 => (EOR, ZeroPageX, 4),
+		0x4d => (EOR, Absolute, 4),
+		0x5d => (EOR, AbsoluteX, 4),
+		0x59 => (EOR, AbsoluteY, 4),
+		0x41 => (EOR, IndexedIndirect, 6),
+		0x51 => (EOR, IndirectIndexed, 5),
+		//Increment Memory
+		0xe6 => (INC, ZeroPage, 5),
+		0xf6 => (INC, ZeroPageX, 6),
+		0xee => (INC, Absolute, 6),
+		0xfe => (INC, AbsoluteX, 7),
+		//Decrement X
+		0xe8 => (INX, Implicit, 2),
+		//Decrement Y
+		0xc8 => (INY, Implicit, 2),
+		//Load Accumulator
+		0xa9 => (LDA, Immediate, 2),
+		0xa5 => (LDA, ZeroPage, 3),
+		0xb5 => (LDA, ZeroPageX, 4),
+		0xad => (LDA, Absolute, 4),
+		0xbd => (LDA, AbsoluteX, 4),
+		0xb9 => (LDA, AbsoluteY, 4),
+		0xa1 => (LDA, IndexedIndirect, 6),
+		0xb1 => (LDA, IndirectIndexed, 5),
+		//Load X Register
+		0xa2 => (LDX, Immediate, 2),
+		0xa6 => (LDX, ZeroPage, 3),
+		0xb6 => (LDX, ZeroPageY, 4),
+		0xae => (LDX, Absolute, 4),
+		0xbe => (LDX, AbsoluteY, 4),
+		//Load Y Register
+		0xa0 => (LDY, Immediate, 2),
+		0xa4 => (LDY, ZeroPage, 3),
+		0xb4 => (LDY, ZeroPageX, 4),
+		0xac => (LDY, Absolute, 4),
+		0xbc => (LDY, AbsoluteX, 4),
+		//Logical Shift Right
+		0x4a => (LSR, Implicit, 2),
+		0x46 => (LSR, ZeroPage, 5),
+		0x56 => (LSR, ZeroPageX, 6),
+		0x4e => (LSR, Absolute, 6),
+		0x5e => (LSR, AbsoluteX, 7),
+		//No Operation
+		0xea => (NOP, Implicit, 2),
+		//Logical Inclusive OR
+		0x09 => (ORA, Immediate, 2),
+		0x05 => (ORA, ZeroPage, 3),
+		0x15 => (ORA, ZeroPageX, 4),
+		0x0d => (ORA, Absolute, 4),
+		0x1d => (ORA, AbsoluteX, 4),
+		0x19 => (ORA, AbsoluteY, 4),
+		0x01 => (ORA, IndexedIndirect, 6),
+		0x11 => (ORA, IndirectIndexed, 5),
+		//Rotate Left
+		0x2a => (ROL, Implicit, 2),
+		0x26 => (ROL, ZeroPage, 5),
+		0x36 => (ROL, ZeroPageX, 6),
+		0x2e => (ROL, Absolute, 6),
+		0x3e => (ROL, AbsoluteX, 7),
+		//Rotate Right
+		0x6a => (ROR, Implicit, 2),
+		0x66 => (ROR, ZeroPage, 5),
+		0x76 => (ROR, ZeroPageX, 6),
+		0x6e => (ROR, Absolute, 6),
+		0x7e => (ROR, AbsoluteX, 7),
+		//Subtract with Carry
+		0xe9 => (SBC, Immediate, 2),
+		0xe5 => (SBC, ZeroPage, 3),
+		0xf5 => (SBC, ZeroPageX, 4),
+		0xed => (SBC, Absolute, 4),
+		0xfd => (SBC, AbsoluteX, 4),
+		0xf9 => (SBC, AbsoluteY, 4),
+		0xe1 => (SBC, IndexedIndirect, 6),
+		0xf1 => (SBC, IndirectIndexed, 5),
+		//Store accumulator
+		0x85 => (STA, ZeroPage, 3),
+		0x95 => (STA, ZeroPageX, 4),
+		0x8d => (STA, Absolute, 4),
+		0x9d => (STA, AbsoluteX, 5),
+		0x99 => (STA, AbsoluteY, 5),
+		0x81 => (STA, IndexedIndirect, 6),
+		0x91 => (STA, IndirectIndexed, 6),
+		//Store X Register
+		0x86 => (STX, ZeroPage, 3),
+		0x96 => (STX, ZeroPageY, 4),
+		0x8e => (STX, Absolute, 4),
+		//Store Y Register
+		0x84 => (STY, ZeroPage, 3),
+		0x94 => (STY, ZeroPageX, 4),
+		0x8c => (STY, Absolute, 4),
+		//Transfer
+		0xaa => (TAX, Implicit, 2),
+		0xa8 => (TAY, Implicit, 2),
+		0xba => (TSX, Implicit, 2),
+		0x8a => (TXA, Implicit, 2),
+		0x9a => (TXS, Implicit, 2),
+		0x98 => (TYA, Implicit, 2),
+		//Clear Flags
+		0x18 => (CLC, Implicit, 2),
+		0xd8 => (CLD, Implicit, 2),
+		0x58 => (CLI, Implicit, 2),
+		0xb8 => (CLV, Implicit, 2),
+		//Set Flags
+		0x38 => (SEC, Implicit, 2),
+		0xf8 => (SED, Implicit, 2),
+		0x78 => (SEI, Implicit, 2),
+		//Branch Instructions
+		0x90 => (BCC, Relative, 2),
+		0xb0 => (BCS, Relative, 2),
+		0xf0 => (BEQ, Relative, 2),
+		0x30 => (BMI, Relative, 2),
+		0xd0 => (BNE, Relative, 2),
+		0x10 => (BPL, Relative, 2),
+		0x50 => (BVC, Relative, 2),
+		0x70 => (BVS, Relative, 2),
+		//Jump
+		0x4c => (JMP, Absolute, 3),
+		0x6c => (JMP, Indirect, 5),
+		//Stack operations
+		0x20 => (JSR, Absolute, 6),
+		0x60 => (RTS, Implicit, 6),
+		0x48 => (PHA, Implicit, 3),
+		0x08 => (PHP, Implicit, 3),
+		0x68 => (PLA, Implicit, 4),
+		0x28 => (PLP, Implicit, 4),
+		//Interrupts
+		0x00 => (BRK, Implicit, 7),
+		0x40 => (RTI, Implicit, 6),
+		_ => return None,
+	})
+}
+
+/// Like [`lookup`] for the stable undocumented NMOS opcodes, the unstable ones
+/// such as `XAA` and `SHY` are left out
+pub fn lookup_undocumented(opcode: u8) -> Option<(Operation, AddressingMode, u8)> {
+	use AddressingMode::*;
+	use Operation::*;
+	Some(match opcode {
+		//Shift Left then OR
+		0x07 => (SLO, ZeroPage, 5),
+		0x17 => (SLO, ZeroPageX, 6),
+		0x0f => (SLO, Absolute, 6),
+		0x1f => (SLO, AbsoluteX, 7),
+		0x1b => (SLO, AbsoluteY, 7),
+		0x03 => (SLO, IndexedIndirect, 8),
+		0x13 => (SLO, IndirectIndexed, 8),
+		//Rotate Left then AND
+		0x27 => (RLA, ZeroPage, 5),
+		0x37 => (RLA, ZeroPageX, 6),
+		0x2f => (RLA, Absolute, 6),
+		0x3f => (RLA, AbsoluteX, 7),
+		0x3b => (RLA, AbsoluteY, 7),
+		0x23 => (RLA, IndexedIndirect, 8),
+		0x33 => (RLA, IndirectIndexed, 8),
+		//Shift Right then Exclusive OR
+		0x47 => (SRE, ZeroPage, 5),
+		0x57 => (SRE, ZeroPageX, 6),
+		0x4f => (SRE, Absolute, 6),
+		0x5f => (SRE, AbsoluteX, 7),
+		0x5b => (SRE, AbsoluteY, 7),
+		0x43 => (SRE, IndexedIndirect, 8),
+		0x53 => (SRE, IndirectIndexed, 8),
+		//Rotate Right then Add with Carry
+		0x67 => (RRA, ZeroPage, 5),
+		0x77 => (RRA, ZeroPageX, 6),
+		0x6f => (RRA, Absolute, 6),
+		0x7f => (RRA, AbsoluteX, 7),
+		0x7b => (RRA, AbsoluteY, 7),
+		0x63 => (RRA, IndexedIndirect, 8),
+		0x73 => (RRA, IndirectIndexed, 8),
+		//Store A AND X
+		0x87 => (SAX, ZeroPage, 3),
+		0x97 => (SAX, ZeroPageY, 4),
+		0x8f => (SAX, Absolute, 4),
+		0x83 => (SAX, IndexedIndirect, 6),
+		//Load A and X
+		0xa7 => (LAX, ZeroPage, 3),
+		0xb7 => (LAX, ZeroPageY, 4),
+		0xaf => (LAX, Absolute, 4),
+		0xbf => (LAX, AbsoluteY, 4),
+		0xa3 => (LAX, IndexedIndirect, 6),
+		0xb3 => (LAX, IndirectIndexed, 5),
+		0xbb => (LAS, AbsoluteY, 4),
+		//Decrement then Compare
+		0xc7 => (DCP, ZeroPage, 5),
+		0xd7 => (DCP, ZeroPageX, 6),
+		0xcf => (DCP, Absolute, 6),
+		0xdf => (DCP, AbsoluteX, 7),
+		0xdb => (DCP, AbsoluteY, 7),
+		0xc3 => (DCP, IndexedIndirect, 8),
+		0xd3 => (DCP, IndirectIndexed, 8),
+		//Increment then Subtract with Carry
+		0xe7 => (ISC, ZeroPage, 5),
+		0xf7 => (ISC, ZeroPageX, 6),
+		0xef => (ISC, Absolute, 6),
+		0xff => (ISC, AbsoluteX, 7),
+		0xfb => (ISC, AbsoluteY, 7),
+		0xe3 => (ISC, IndexedIndirect, 8),
+		0xf3 => (ISC, IndirectIndexed, 8),
+		//Immediate combinations
+		0x0b | 0x2b => (ANC, Immediate, 2),
+		0x4b => (ALR, Immediate, 2),
+		0x6b => (ARR, Immediate, 2),
+		0xcb => (SBX, Immediate, 2),
+		0xeb => (SBC, Immediate, 2),
+		//No Operation, reading and discarding their operand
+		0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => (NOP, Implicit, 2),
+		0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 => (NOP, Immediate, 2),
+		0x04 | 0x44 | 0x64 => (NOP, ZeroPage, 3),
+		0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 => (NOP, ZeroPageX, 4),
+		0x0c => (NOP, Absolute, 4),
+		0x1c | 0x3c | 0x5c | 0x7c | 0xdc | 0xfc => (NOP, AbsoluteX, 4),
+		_ => return None,
+	})
+}
+
+/// Like [`lookup`] for the opcodes the 65C02 adds or changes, every opcode
+/// unused by the 65C02 decodes to a `NOP`
+pub fn lookup_65c02(opcode: u8) -> Option<(Operation, AddressingMode, u8)> {
+	use AddressingMode::*;
+	use Operation::*;
+	let bit = opcode >> 4 & 0x7;
+	Some(match opcode {
+		0x80 => (BRA, Relative, 2),
+		0xda => (PHX, Implicit, 3),
+		0x5a => (PHY, Implicit, 3),
+		0xfa => (PLX, Implicit, 4),
+		0x7a => (PLY, Implicit, 4),
+		//Store Zero
+		0x64 => (STZ, ZeroPage, 3),
+		0x74 => (STZ, ZeroPageX, 4),
+		0x9c => (STZ, Absolute, 4),
+		0x9e => (STZ, AbsoluteX, 5),
+		//Test and Reset/Set Bits
+		0x14 => (TRB, ZeroPage, 5),
+		0x1c => (TRB, Absolute, 6),
+		0x04 => (TSB, ZeroPage, 5),
+		0x0c => (TSB, Absolute, 6),
+		0x1a => (INC, Implicit, 2),
+		0x3a => (DEC, Implicit, 2),
+		//Shifts and rotates only take the extra cycle on a page cross
+		0x1e => (ASL, AbsoluteX, 6),
+		0x5e => (LSR, AbsoluteX, 6),
+		0x3e => (ROL, AbsoluteX, 6),
+		0x7e => (ROR, AbsoluteX, 6),
+		//Zero Page Indirect
+		0x12 => (ORA, ZeroPageIndirect, 5),
+		0x32 => (AND, ZeroPageIndirect, 5),
+		0x52 => (EOR, ZeroPageIndirect, 5),
+		0x72 => (ADC, ZeroPageIndirect, 5),
+		0x92 => (STA, ZeroPageIndirect, 5),
+		0xb2 => (LDA, ZeroPageIndirect, 5),
+		0xd2 => (CMP, ZeroPageIndirect, 5),
+		0xf2 => (SBC, ZeroPageIndirect, 5),
+		//BIT
+		0x89 => (BIT, Immediate, 2),
+		0x34 => (BIT, ZeroPageX, 4),
+		0x3c => (BIT, AbsoluteX, 4),
+		//Jump
+		0x6c => (JMP, Indirect, 6),
+		0x7c => (JMP, AbsoluteIndexedIndirect, 6),
+		//Rockwell bit instructions, bits 4-6 of the opcode select the bit
+		0x0f | 0x1f | 0x2f | 0x3f | 0x4f | 0x5f | 0x6f | 0x7f => (BBR(bit), ZeroPageRelative, 5),
+		0x8f | 0x9f | 0xaf | 0xbf | 0xcf | 0xdf | 0xef | 0xff => (BBS(bit), ZeroPageRelative, 5),
+		0x07 | 0x17 | 0x27 | 0x37 | 0x47 | 0x57 | 0x67 | 0x77 => (RMB(bit), ZeroPage, 5),
+		0x87 | 0x97 | 0xa7 | 0xb7 | 0xc7 | 0xd7 | 0xe7 | 0xf7 => (SMB(bit), ZeroPage, 5),
+		0xcb => (WAI, Implicit, 3),
+		0xdb => (STP, Implicit, 3),
+		//Reserved
+		0x02 | 0x22 | 0x42 | 0x62 | 0x82 | 0xc2 | 0xe2 => (NOP, Immediate, 2),
+		0x44 => (NOP, ZeroPage, 3),
+		0x54 | 0xd4 | 0xf4 => (NOP, ZeroPageX, 4),
+		0x5c => (NOP, Absolute, 8),
+		0xdc | 0xfc => (NOP, Absolute, 4),
+		_ if opcode & 0x3 == 0x3 => (NOP, Implicit, 1),
+		_ => return None,
+	})
+}
+
+/// How a [`Frame`] of the shadow call stack was entered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+	Call,
+	Irq,
+	Nmi,
+	Brk,
+}
+
+/// Subroutine call or interrupt tracked by the shadow call stack, see
+/// [`Cpu::call_stack`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+	pub kind: FrameKind,
+	/// Address of the `JSR` or `BRK`, or of the instruction an interrupt
+	/// arrived before
+	pub caller: u16,
+	/// Address of the subroutine or interrupt handler
+	pub target: u16,
+	/// Stack pointer before the return address was pushed, the frame is
+	/// dropped once the stack pointer is back at or above it
+	pub stack_pointer: u8,
+}
+
+/// 6502 core, stepped one instruction at a time with [`Cpu::execute`]
+#[derive(Clone)]
 pub struct Cpu {
+	variant: Variant,
 	program_counter: u16,
 	x: u8,
 	y: u8,
 	a: u8,
 	status: u8,
 	stack_pointer: u8,
+	/// Last level seen on the NMI line, used for edge detection
+	nmi_line: bool,
+	nmi_pending: bool,
+	/// Level of the IRQ line, stays asserted until the device releases it
+	irq_line: bool,
+	/// Clock cycles elapsed since power on
+	cycles: u64,
+	/// Whether a wrapping stack pointer is reported as a fault
+	stack_checks: bool,
+	/// Rejects undocumented opcodes as invalid
+	strict: bool,
+	/// Set by `WAI` until an interrupt arrives
+	waiting: bool,
+	/// Direction the stack pointer wrapped in during the current instruction,
+	/// `true` for a push
+	stack_wrapped: Option<bool>,
+	/// Calls and interrupts not returned from yet, outermost first
+	call_stack: Vec<Frame>,
 }
 
+/// Snapshot of the registers, see [`Cpu::state`], written back with
+/// [`Cpu::set_state`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct CpuState {
 	pub program_counter: u16,
 	pub x: u8,
@@ -222,18 +864,116 @@ pub struct CpuState {
 	pub a: u8,
 	pub status: u8,
 	pub stack_pointer: u8,
+	pub cycles: u64,
+}
+
+impl CpuState {
+	pub fn get_flag(&self, flag: StatusFlags) -> bool {
+		self.status & flag.get_bit_mask() != 0
+	}
+
+	pub fn set_flag(&mut self, flag: StatusFlags, value: bool) {
+		if value {
+			self.status |= flag.get_bit_mask();
+		} else {
+			self.status &= !flag.get_bit_mask();
+		}
+	}
 }
 
 impl Cpu {
 	pub fn new() -> Self {
+		Self::with_variant(Variant::Nmos)
+	}
+
+	pub fn new_65c02() -> Self {
+		Self::with_variant(Variant::Wdc65C02)
+	}
+
+	pub fn with_variant(variant: Variant) -> Self {
 		Self {
+			variant,
 			program_counter: 0,
 			x: 0,
 			y: 0,
 			a: 0,
 			status: 0,
 			stack_pointer: 0xff,
+			nmi_line: false,
+			nmi_pending: false,
+			irq_line: false,
+			cycles: 0,
+			stack_checks: false,
+			strict: false,
+			waiting: false,
+			stack_wrapped: None,
+			call_stack: Vec::new(),
+		}
+	}
+
+	/// Runs the reset sequence, loading the program counter from the reset vector
+	/// at `0xfffc`
+	pub fn reset<B: Bus>(&mut self, mem: &mut B) {
+		self.stack_pointer = self.stack_pointer.wrapping_sub(3);
+		self.set_flag(StatusFlags::InterruptDisable, true);
+		self.nmi_pending = false;
+		self.waiting = false;
+		self.call_stack.clear();
+		if self.variant.is_cmos() {
+			self.set_flag(StatusFlags::DecimalMode, false);
+		}
+		self.program_counter = mem.read_word(RESET_VECTOR);
+	}
+
+	/// Drives the NMI line, an interrupt is latched on the rising edge only
+	pub fn set_nmi(&mut self, active: bool) {
+		if active && !self.nmi_line {
+			self.nmi_pending = true;
 		}
+		self.nmi_line = active;
+	}
+
+	/// Drives the IRQ line, serviced before every instruction while held active
+	/// and the interrupt disable flag is clear
+	pub fn set_irq(&mut self, active: bool) {
+		self.irq_line = active;
+	}
+
+	/// Drives both interrupt lines from the devices mapped in `mem`, for the
+	/// run loop to call before every instruction
+	pub fn poll_interrupts(&mut self, mem: &Memory) {
+		self.set_irq(mem.irq());
+		self.set_nmi(mem.nmi());
+	}
+
+	/// Reports the stack pointer wrapping around as [`CpuError::StackOverflow`]
+	/// or [`CpuError::StackUnderflow`], real hardware wraps silently
+	pub fn set_stack_checks(&mut self, enabled: bool) {
+		self.stack_checks = enabled;
+	}
+
+	/// Reports undocumented opcodes, the ones that jam the CPU included, as
+	/// [`CpuError::InvalidOpcode`] instead of executing them
+	pub fn set_strict(&mut self, enabled: bool) {
+		self.strict = enabled;
+	}
+
+	/// Jumps to `address` without touching any other register
+	pub fn set_program_counter(&mut self, address: u16) {
+		self.program_counter = address;
+	}
+
+	/// Loads every register from `state`, the break and unused status bits
+	/// are dropped like they are when pulled from the stack
+	pub fn set_state(&mut self, state: CpuState) {
+		self.program_counter = state.program_counter;
+		self.x = state.x;
+		self.y = state.y;
+		self.a = state.a;
+		self.set_status(state.status);
+		self.stack_pointer = state.stack_pointer;
+		self.cycles = state.cycles;
+		self.unwind_call_stack();
 	}
 
 	pub fn state(&self) -> CpuState {
@@ -244,29 +984,179 @@ impl Cpu {
 			a: self.a,
 			status: self.status,
 			stack_pointer: self.stack_pointer,
+			cycles: self.cycles,
+		}
+	}
+
+	/// Subroutine calls and interrupts entered and not yet returned from,
+	/// outermost first. Frames are dropped as soon as the stack pointer moves
+	/// back above where they were entered, so programs that discard return
+	/// addresses with `PLA` or reset the stack with `TXS` don't leave stale
+	/// frames behind.
+	pub fn call_stack(&self) -> &[Frame] {
+		&self.call_stack
+	}
+
+	/// Appends every field to a save state, see [`crate::save_state`]
+	pub(crate) fn save(&self, out: &mut Vec<u8>) {
+		out.push(match self.variant {
+			Variant::Nmos => 0,
+			Variant::Ricoh2A03 => 1,
+			Variant::Wdc65C02 => 2,
+		});
+		out.extend_from_slice(&self.program_counter.to_le_bytes());
+		out.extend_from_slice(&[self.x, self.y, self.a, self.status, self.stack_pointer]);
+		out.extend_from_slice(&self.cycles.to_le_bytes());
+		out.extend(
+			[
+				self.nmi_line,
+				self.nmi_pending,
+				self.irq_line,
+				self.waiting,
+				self.stack_checks,
+				self.strict,
+			]
+			.map(u8::from),
+		);
+		out.extend_from_slice(&(self.call_stack.len() as u16).to_le_bytes());
+		for frame in &self.call_stack {
+			out.push(match frame.kind {
+				FrameKind::Call => 0,
+				FrameKind::Irq => 1,
+				FrameKind::Nmi => 2,
+				FrameKind::Brk => 3,
+			});
+			out.extend_from_slice(&frame.caller.to_le_bytes());
+			out.extend_from_slice(&frame.target.to_le_bytes());
+			out.push(frame.stack_pointer);
 		}
 	}
 
-	pub fn execute(&mut self, mem: &mut Memory) {
-		let instruction = self.decode(mem);
+	/// Reads back the fields written by [`Cpu::save`]
+	pub(crate) fn load(&mut self, reader: &mut Reader) -> Result<(), SaveStateError> {
+		self.variant = match reader.u8()? {
+			0 => Variant::Nmos,
+			1 => Variant::Ricoh2A03,
+			2 => Variant::Wdc65C02,
+			value => {
+				return Err(SaveStateError::Invalid(format!(
+					"unknown CPU variant {value}"
+				)))
+			}
+		};
+		self.program_counter = reader.u16()?;
+		self.x = reader.u8()?;
+		self.y = reader.u8()?;
+		self.a = reader.u8()?;
+		self.status = reader.u8()?;
+		self.stack_pointer = reader.u8()?;
+		self.cycles = reader.u64()?;
+		self.nmi_line = reader.bool()?;
+		self.nmi_pending = reader.bool()?;
+		self.irq_line = reader.bool()?;
+		self.waiting = reader.bool()?;
+		self.stack_checks = reader.bool()?;
+		self.strict = reader.bool()?;
+		self.stack_wrapped = None;
+		let frames = reader.u16()?;
+		self.call_stack = (0..frames)
+			.map(|_| {
+				Ok(Frame {
+					kind: match reader.u8()? {
+						0 => FrameKind::Call,
+						1 => FrameKind::Irq,
+						2 => FrameKind::Nmi,
+						3 => FrameKind::Brk,
+						value => {
+							return Err(SaveStateError::Invalid(format!(
+								"unknown call stack frame kind {value}"
+							)))
+						}
+					},
+					caller: reader.u16()?,
+					target: reader.u16()?,
+					stack_pointer: reader.u8()?,
+				})
+			})
+			.collect::<Result<_, _>>()?;
+		Ok(())
+	}
+
+	/// Decodes the instruction at the program counter without executing it,
+	/// operands are resolved against the current registers
+	pub fn next_instruction<B: Bus>(&self, mem: &mut B) -> Option<Instruction> {
+		let (operation, addressing_mode, _) = self.lookup(mem.read_byte(self.program_counter))?;
+		let mut cpu = self.clone();
+		cpu.program_counter = cpu.program_counter.wrapping_add(1);
+		let (operand, _) = addressing_mode.get_operand(&mut cpu, mem);
+		Some(Instruction(operation, operand))
+	}
+
+	/// Executes the next instruction or services a pending interrupt
+	pub fn execute<B: Bus>(&mut self, mem: &mut B) -> Result<StepOutcome, CpuError> {
+		let address = self.program_counter;
+		self.stack_wrapped = None;
+		let outcome = self.step(mem);
+		self.unwind_call_stack();
+		let outcome = outcome?;
+		match self.stack_wrapped {
+			Some(true) if self.stack_checks => Err(CpuError::StackOverflow { address }),
+			Some(false) if self.stack_checks => Err(CpuError::StackUnderflow { address }),
+			_ => Ok(outcome),
+		}
+	}
+
+	fn step<B: Bus>(&mut self, mem: &mut B) -> Result<StepOutcome, CpuError> {
+		if self.waiting {
+			// WAI resumes on an IRQ even while interrupts are disabled, without
+			// servicing it
+			if !self.nmi_pending && !self.irq_line {
+				return Ok(StepOutcome::Waiting(self.tick(1)));
+			}
+			self.waiting = false;
+		}
+		let (caller, stack_pointer) = (self.program_counter, self.stack_pointer);
+		if self.nmi_pending {
+			info!("[Interrupt]\tNMI");
+			self.nmi_pending = false;
+			self.interrupt(mem, NMI_VECTOR, false);
+			self.enter_frame(FrameKind::Nmi, caller, stack_pointer);
+			return Ok(StepOutcome::Interrupted(self.tick(INTERRUPT_CYCLES)));
+		}
+		if self.irq_line && !self.get_flag(StatusFlags::InterruptDisable) {
+			info!("[Interrupt]\tIRQ");
+			self.interrupt(mem, IRQ_VECTOR, false);
+			self.enter_frame(FrameKind::Irq, caller, stack_pointer);
+			return Ok(StepOutcome::Interrupted(self.tick(INTERRUPT_CYCLES)));
+		}
+		let address = self.program_counter;
+		let (instruction, cycles) = self.decode(mem)?;
 		info!("[Execute]\t{instruction:x?}");
 		use Operation as Op;
-		let pass_by_value = |operand| match operand {
+		let mut pass_by_value = |operand| match operand {
 			Operand::Value(x) => x,
-			Operand::Address(x) => mem.read_byte(x),
-		};
-		let mut branch = |flag: StatusFlags, condition: bool, offset: u8| {
-			if self.get_flag(flag) == condition {
-				self.program_counter = (self.program_counter as i16 + (offset as i8) as i16) as u16
-			}
+			Operand::Address(x) | Operand::ZeroPageRelative(x, _) => mem.read_byte(x),
 		};
+		let mut branch_penalty = 0;
+		// The 65C02 fixes up the decimal flags in an extra cycle
+		let decimal_penalty = self.variant.is_cmos() && self.get_flag(StatusFlags::DecimalMode);
 
 		use Operand as Od;
 		match instruction {
 			//Logical Operations
-			Instruction(Op::ADC, Some(operand)) => self.add_with_carry(pass_by_value(operand)),
+			Instruction(Op::ADC, Some(operand)) => {
+				self.add_with_carry(pass_by_value(operand));
+				branch_penalty = decimal_penalty as u8;
+			}
 			Instruction(Op::AND, Some(operand)) => self.set_a(self.a & pass_by_value(operand)),
-			Instruction(Op::ASL, operand) => self.arithmetic_shift_left(mem, operand),
+			Instruction(Op::ASL, None) => self.a = self.shift_left(self.a),
+			Instruction(Op::ASL, Some(Od::Address(addr))) => {
+				self.read_modify_write(mem, addr, Self::shift_left);
+			}
+			// Immediate BIT only has the accumulator to test against
+			Instruction(Op::BIT, Some(Od::Value(value))) => {
+				self.set_flag(StatusFlags::Zero, self.a & value == 0)
+			}
 			Instruction(Op::BIT, Some(operand)) => self.bit(pass_by_value(operand)),
 			Instruction(Op::CMP, Some(operand)) => {
 				self.compare_register(pass_by_value(operand), self.a)
@@ -278,23 +1168,37 @@ impl Cpu {
 				self.compare_register(pass_by_value(operand), self.y)
 			}
 			Instruction(Op::DEC, Some(Od::Address(addr))) => {
-				mem.modify(addr, |x| x.wrapping_sub(1));
-				self.update_zero_and_negative_flag(mem.read_byte(addr))
+				let value = mem.modify(addr, |x| x.wrapping_sub(1));
+				self.update_zero_and_negative_flag(value)
 			}
+			Instruction(Op::DEC, None) => self.set_a(self.a.wrapping_sub(1)),
 			Instruction(Op::DEX, None) => self.set_x(self.x.wrapping_sub(1)),
 			Instruction(Op::DEY, None) => self.set_y(self.y.wrapping_sub(1)),
+			Instruction(Op::INC, None) => self.set_a(self.a.wrapping_add(1)),
 			Instruction(Op::INX, None) => self.set_x(self.x.wrapping_add(1)),
 			Instruction(Op::INY, None) => self.set_y(self.y.wrapping_add(1)),
 			Instruction(Op::EOR, Some(operand)) => self.set_a(self.a ^ pass_by_value(operand)),
 			Instruction(Op::INC, Some(Od::Address(addr))) => {
-				mem.modify(addr, |x| x.wrapping_add(1));
-				self.update_zero_and_negative_flag(mem.read_byte(addr))
+				let value = mem.modify(addr, |x| x.wrapping_add(1));
+				self.update_zero_and_negative_flag(value)
+			}
+			Instruction(Op::LSR, None) => self.a = self.shift_right(self.a),
+			Instruction(Op::LSR, Some(Od::Address(addr))) => {
+				self.read_modify_write(mem, addr, Self::shift_right);
 			}
-			Instruction(Op::LSR, operand) => self.logical_shift_right(mem, operand),
 			Instruction(Op::ORA, Some(operand)) => self.set_a(self.a | pass_by_value(operand)),
-			Instruction(Op::ROL, operand) => self.rotate_left(mem, operand),
-			Instruction(Op::ROR, operand) => self.rotate_right(mem, operand),
-			Instruction(Op::SBC, Some(operand)) => self.sub_with_carry(pass_by_value(operand)),
+			Instruction(Op::ROL, None) => self.a = self.rotate_left(self.a),
+			Instruction(Op::ROL, Some(Od::Address(addr))) => {
+				self.read_modify_write(mem, addr, Self::rotate_left);
+			}
+			Instruction(Op::ROR, None) => self.a = self.rotate_right(self.a),
+			Instruction(Op::ROR, Some(Od::Address(addr))) => {
+				self.read_modify_write(mem, addr, Self::rotate_right);
+			}
+			Instruction(Op::SBC, Some(operand)) => {
+				self.sub_with_carry(pass_by_value(operand));
+				branch_penalty = decimal_penalty as u8;
+			}
 			//Flags
 			Instruction(Op::CLC, None) => self.set_flag(StatusFlags::Carry, false),
 			Instruction(Op::CLD, None) => self.set_flag(StatusFlags::DecimalMode, false),
@@ -304,13 +1208,14 @@ impl Cpu {
 			Instruction(Op::SED, None) => self.set_flag(StatusFlags::DecimalMode, true),
 			Instruction(Op::SEI, None) => self.set_flag(StatusFlags::InterruptDisable, true),
 			//Misc
-			Instruction(Op::NOP, None) => (),
+			Instruction(Op::NOP, _) => (),
 			Instruction(Op::LDA, Some(operand)) => self.set_a(pass_by_value(operand)),
 			Instruction(Op::LDX, Some(operand)) => self.set_x(pass_by_value(operand)),
 			Instruction(Op::LDY, Some(operand)) => self.set_y(pass_by_value(operand)),
 			Instruction(Op::STA, Some(Od::Address(addr))) => mem.write_byte(addr, self.a),
 			Instruction(Op::STX, Some(Od::Address(addr))) => mem.write_byte(addr, self.x),
 			Instruction(Op::STY, Some(Od::Address(addr))) => mem.write_byte(addr, self.y),
+			Instruction(Op::STZ, Some(Od::Address(addr))) => mem.write_byte(addr, 0),
 			//Transfer
 			Instruction(Op::TAX, None) => self.set_x(self.a),
 			Instruction(Op::TAY, None) => self.set_y(self.a),
@@ -320,287 +1225,307 @@ impl Cpu {
 			Instruction(Op::TYA, None) => self.set_a(self.y),
 			//Branching
 			Instruction(Op::BCC, Some(Od::Value(offset))) => {
-				branch(StatusFlags::Carry, false, offset)
+				branch_penalty = self.branch(!self.get_flag(StatusFlags::Carry), offset)
 			}
 			Instruction(Op::BCS, Some(Od::Value(offset))) => {
-				branch(StatusFlags::Carry, true, offset)
+				branch_penalty = self.branch(self.get_flag(StatusFlags::Carry), offset)
 			}
 			Instruction(Op::BEQ, Some(Od::Value(offset))) => {
-				branch(StatusFlags::Zero, true, offset)
+				branch_penalty = self.branch(self.get_flag(StatusFlags::Zero), offset)
 			}
 			Instruction(Op::BMI, Some(Od::Value(offset))) => {
-				branch(StatusFlags::Negative, true, offset)
+				branch_penalty = self.branch(self.get_flag(StatusFlags::Negative), offset)
 			}
 			Instruction(Op::BNE, Some(Od::Value(offset))) => {
-				branch(StatusFlags::Zero, false, offset)
+				branch_penalty = self.branch(!self.get_flag(StatusFlags::Zero), offset)
 			}
 			Instruction(Op::BPL, Some(Od::Value(offset))) => {
-				branch(StatusFlags::Negative, false, offset)
+				branch_penalty = self.branch(!self.get_flag(StatusFlags::Negative), offset)
 			}
 			Instruction(Op::BVC, Some(Od::Value(offset))) => {
-				branch(StatusFlags::Overflow, false, offset)
+				branch_penalty = self.branch(!self.get_flag(StatusFlags::Overflow), offset)
 			}
 			Instruction(Op::BVS, Some(Od::Value(offset))) => {
-				branch(StatusFlags::Overflow, true, offset)
+				branch_penalty = self.branch(self.get_flag(StatusFlags::Overflow), offset)
+			}
+			Instruction(Op::BRA, Some(Od::Value(offset))) => {
+				branch_penalty = self.branch(true, offset)
+			}
+			Instruction(Op::BBR(bit), Some(operand @ Od::ZeroPageRelative(_, offset))) => {
+				let value = pass_by_value(operand);
+				branch_penalty = self.branch(value & 1 << bit == 0, offset)
+			}
+			Instruction(Op::BBS(bit), Some(operand @ Od::ZeroPageRelative(_, offset))) => {
+				let value = pass_by_value(operand);
+				branch_penalty = self.branch(value & 1 << bit != 0, offset)
 			}
 			//Stack operations
+			// The return address pushed is the last byte of the JSR instruction
 			Instruction(Op::JSR, Some(Od::Address(addr))) => {
-				self.push_word(mem, self.program_counter);
+				self.push_word(mem, self.program_counter.wrapping_sub(1));
 				self.program_counter = addr;
+				self.enter_frame(FrameKind::Call, address, stack_pointer);
 			}
-			Instruction(Op::RTS, None) => self.program_counter = self.pop_word(mem),
+			Instruction(Op::RTS, None) => self.program_counter = self.pop_word(mem).wrapping_add(1),
 			Instruction(Op::PHA, None) => self.push_byte(mem, self.a),
-			Instruction(Op::PHP, None) => self.push_byte(mem, self.status),
+			Instruction(Op::PHP, None) => self.push_byte(
+				mem,
+				self.status
+					| StatusFlags::Break.get_bit_mask()
+					| StatusFlags::Unused.get_bit_mask(),
+			),
 			Instruction(Op::PLA, None) => {
 				let data = self.pop_byte(mem);
 				self.set_a(data)
 			}
-			Instruction(Op::PLP, None) => self.status = self.pop_byte(mem),
+			Instruction(Op::PLP, None) => {
+				let data = self.pop_byte(mem);
+				self.set_status(data)
+			}
+			Instruction(Op::PHX, None) => self.push_byte(mem, self.x),
+			Instruction(Op::PHY, None) => self.push_byte(mem, self.y),
+			Instruction(Op::PLX, None) => {
+				let data = self.pop_byte(mem);
+				self.set_x(data)
+			}
+			Instruction(Op::PLY, None) => {
+				let data = self.pop_byte(mem);
+				self.set_y(data)
+			}
+			//Interrupts
+			Instruction(Op::BRK, None) => {
+				// BRK skips over the padding byte following the opcode
+				self.program_counter = self.program_counter.wrapping_add(1);
+				self.interrupt(mem, IRQ_VECTOR, true);
+				self.enter_frame(FrameKind::Brk, address, stack_pointer);
+			}
+			Instruction(Op::RTI, None) => {
+				let data = self.pop_byte(mem);
+				self.set_status(data);
+				self.program_counter = self.pop_word(mem);
+			}
 			//Jump
 			Instruction(Op::JMP, Some(Od::Address(addr))) => self.program_counter = addr,
-			_ => error!("Invalid instruction: {:x?}", instruction),
+			//65C02
+			Instruction(Op::TRB, Some(Od::Address(addr))) => {
+				let value = mem.read_byte(addr);
+				self.set_flag(StatusFlags::Zero, self.a & value == 0);
+				mem.write_byte(addr, value & !self.a);
+			}
+			Instruction(Op::TSB, Some(Od::Address(addr))) => {
+				let value = mem.read_byte(addr);
+				self.set_flag(StatusFlags::Zero, self.a & value == 0);
+				mem.write_byte(addr, value | self.a);
+			}
+			Instruction(Op::RMB(bit), Some(Od::Address(addr))) => {
+				mem.modify(addr, |x| x & !(1 << bit));
+			}
+			Instruction(Op::SMB(bit), Some(Od::Address(addr))) => {
+				mem.modify(addr, |x| x | 1 << bit);
+			}
+			Instruction(Op::WAI, None) => self.waiting = true,
+			Instruction(Op::STP, None) => {
+				self.program_counter = address;
+				return Err(CpuError::Stopped { address });
+			}
+			//Undocumented
+			Instruction(Op::SLO, Some(Od::Address(addr))) => {
+				let value = self.read_modify_write(mem, addr, Self::shift_left);
+				self.set_a(self.a | value)
+			}
+			Instruction(Op::RLA, Some(Od::Address(addr))) => {
+				let value = self.read_modify_write(mem, addr, Self::rotate_left);
+				self.set_a(self.a & value)
+			}
+			Instruction(Op::SRE, Some(Od::Address(addr))) => {
+				let value = self.read_modify_write(mem, addr, Self::shift_right);
+				self.set_a(self.a ^ value)
+			}
+			Instruction(Op::RRA, Some(Od::Address(addr))) => {
+				let value = self.read_modify_write(mem, addr, Self::rotate_right);
+				self.add_with_carry(value)
+			}
+			Instruction(Op::DCP, Some(Od::Address(addr))) => {
+				let value = mem.modify(addr, |x| x.wrapping_sub(1));
+				self.compare_register(value, self.a)
+			}
+			Instruction(Op::ISC, Some(Od::Address(addr))) => {
+				let value = mem.modify(addr, |x| x.wrapping_add(1));
+				self.sub_with_carry(value)
+			}
+			Instruction(Op::SAX, Some(Od::Address(addr))) => mem.write_byte(addr, self.a & self.x),
+			Instruction(Op::LAX, Some(operand)) => {
+				let value = pass_by_value(operand);
+				self.set_a(value);
+				self.x = value;
+			}
+			Instruction(Op::LAS, Some(operand)) => {
+				let value = pass_by_value(operand) & self.stack_pointer;
+				self.set_a(value);
+				self.x = value;
+				self.stack_pointer = value;
+			}
+			Instruction(Op::ANC, Some(Od::Value(value))) => {
+				self.set_a(self.a & value);
+				self.set_flag(StatusFlags::Carry, self.a & 0x80 > 0);
+			}
+			Instruction(Op::ALR, Some(Od::Value(value))) => {
+				self.a = self.shift_right(self.a & value)
+			}
+			Instruction(Op::ARR, Some(Od::Value(value))) => self.and_rotate_right(value),
+			Instruction(Op::SBX, Some(Od::Value(value))) => {
+				let masked = self.a & self.x;
+				self.set_flag(StatusFlags::Carry, masked >= value);
+				self.set_x(masked.wrapping_sub(value));
+			}
+			_ => {
+				self.program_counter = address;
+				return Err(CpuError::InvalidInstruction {
+					address,
+					instruction,
+				});
+			}
+		}
+		Ok(StepOutcome::Executed(self.tick(cycles + branch_penalty)))
+	}
+
+	fn tick(&mut self, cycles: u8) -> u8 {
+		self.cycles += cycles as u64;
+		cycles
+	}
+
+	/// Jumps by the signed `offset` when `taken`, returning the extra cycles:
+	/// one for a taken branch, two when it lands on another page
+	fn branch(&mut self, taken: bool, offset: u8) -> u8 {
+		if !taken {
+			return 0;
 		}
+		let target = self
+			.program_counter
+			.wrapping_add_signed((offset as i8) as i16);
+		let penalty = if target & 0xff00 != self.program_counter & 0xff00 {
+			2
+		} else {
+			1
+		};
+		self.program_counter = target;
+		penalty
 	}
 
-	fn push_word(&mut self, mem: &mut Memory, value: u16) {
+	/// Pushes the program counter and status to the stack and jumps to the
+	/// address stored at `vector`, `brk` sets the B flag in the pushed status
+	fn interrupt<B: Bus>(&mut self, mem: &mut B, vector: u16, brk: bool) {
+		self.push_word(mem, self.program_counter);
+		let mut status = self.status | StatusFlags::Unused.get_bit_mask();
+		if brk {
+			status |= StatusFlags::Break.get_bit_mask();
+		}
+		self.push_byte(mem, status);
+		self.set_flag(StatusFlags::InterruptDisable, true);
+		if self.variant.is_cmos() {
+			self.set_flag(StatusFlags::DecimalMode, false);
+		}
+		self.program_counter = mem.read_word(vector);
+	}
+	/// Records a call or interrupt that just jumped to the program counter
+	fn enter_frame(&mut self, kind: FrameKind, caller: u16, stack_pointer: u8) {
+		self.call_stack.push(Frame {
+			kind,
+			caller,
+			target: self.program_counter,
+			stack_pointer,
+		});
+	}
+	/// Drops the frames whose return address is no longer on the stack
+	fn unwind_call_stack(&mut self) {
+		while self
+			.call_stack
+			.last()
+			.is_some_and(|frame| frame.stack_pointer <= self.stack_pointer)
+		{
+			self.call_stack.pop();
+		}
+	}
+	/// B and the unused bit only exist on the stack copy of the status register
+	fn set_status(&mut self, value: u8) {
+		self.status =
+			value & !(StatusFlags::Break.get_bit_mask() | StatusFlags::Unused.get_bit_mask());
+	}
+
+	fn push_word<B: Bus>(&mut self, mem: &mut B, value: u16) {
 		self.push_byte(mem, (value >> 8) as u8);
 		self.push_byte(mem, value as u8);
 	}
-	fn pop_word(&mut self, mem: &Memory) -> u16 {
+	fn pop_word<B: Bus>(&mut self, mem: &mut B) -> u16 {
 		let low_byte = self.pop_byte(mem);
 		let high_byte = self.pop_byte(mem);
 		(high_byte as u16) << 8 | low_byte as u16
 	}
-	fn push_byte(&mut self, mem: &mut Memory, value: u8) {
+	fn push_byte<B: Bus>(&mut self, mem: &mut B, value: u8) {
 		mem.write_byte(self.stack_pointer as u16 | STACK_LOWEST_ADDRESS, value);
-		self.stack_pointer -= 1;
+		if self.stack_pointer == 0 {
+			self.stack_wrapped = Some(true);
+		}
+		self.stack_pointer = self.stack_pointer.wrapping_sub(1);
 	}
-	fn pop_byte(&mut self, mem: &Memory) -> u8 {
-		self.stack_pointer += 1;
+	fn pop_byte<B: Bus>(&mut self, mem: &mut B) -> u8 {
+		if self.stack_pointer == u8::MAX {
+			self.stack_wrapped = Some(false);
+		}
+		self.stack_pointer = self.stack_pointer.wrapping_add(1);
 		mem.read_byte(self.stack_pointer as u16 | STACK_LOWEST_ADDRESS)
 	}
 
-	fn decode(&mut self, mem: &Memory) -> Instruction {
-		let operation = self.fetch_byte(mem);
-		let mut instruction = |operation, addressing_mode: AddressingMode| {
-			Instruction(operation, addressing_mode.get_operand(self, mem))
+	/// Fetches the next instruction along with its operand, returning the number
+	/// of clock cycles it takes before any branch penalties
+	fn decode<B: Bus>(&mut self, mem: &mut B) -> Result<(Instruction, u8), CpuError> {
+		let address = self.program_counter;
+		let opcode = mem.read_byte(address);
+		if self.variant.has_undocumented_opcodes() && !self.strict && JAM_OPCODES.contains(&opcode)
+		{
+			return Err(CpuError::Jam { address, opcode });
+		}
+		let Some((operation, addressing_mode, cycles)) = self.lookup(opcode) else {
+			return Err(CpuError::InvalidOpcode { address, opcode });
 		};
-		use AddressingMode::*;
-		use Operation::*;
-		match operation {
-			//Add with Carry
-			0x69 => instruction(ADC, Immediate),
-			0x65 => instruction(ADC, ZeroPage),
-			0x75 => instruction(ADC, ZeroPageX),
-			0x6d => instruction(ADC, Absolute),
-			0x7d => instruction(ADC, AbsoluteX),
-			0x79 => instruction(ADC, AbsoluteY),
-			0x61 => instruction(ADC, IndexedIndirect),
-			0x71 => instruction(ADC, IndirectIndexed),
-			//Logical AND
-			0x29 => instruction(AND, Immediate),
-			0x25 => instruction(AND, ZeroPage),
-			0x35 => instruction(AND, ZeroPageX),
-			0x2d => instruction(AND, Absolute),
-			0x3d => instruction(AND, AbsoluteX),
-			0x39 => instruction(AND, AbsoluteY),
-			0x21 => instruction(AND, IndexedIndirect),
-			0x31 => instruction(AND, IndirectIndexed),
-			//Arithemetic Shift Left
-			0x0a => instruction(ASL, Implicit),
-			0x06 => instruction(ASL, ZeroPage),
-			0x16 => instruction(ASL, ZeroPageX),
-			0x0e => instruction(ASL, Absolute),
-			0x1e => instruction(ASL, AbsoluteX),
-			//BIT
-			0x24 => instruction(BIT, ZeroPage),
-			0x2c => instruction(BIT, Absolute),
-			//Compare
-			0xc9 => instruction(CMP, Immediate),
-			0xc5 => instruction(CMP, ZeroPage),
-			0xd5 => instruction(CMP, ZeroPageX),
-			0xcd => instruction(CMP, Absolute),
-			0xdd => instruction(CMP, AbsoluteX),
-			0xd9 => instruction(CMP, AbsoluteY),
-			0xc1 => instruction(CMP, IndexedIndirect),
-			0xd1 => instruction(CMP, IndirectIndexed),
-			//Compare X
-			0xe0 => instruction(CPX, Immediate),
-			0xe4 => instruction(CPX, ZeroPage),
-			0xec => instruction(CPX, Absolute),
-			//Compare Y
-			0xc0 => instruction(CPY, Immediate),
-			0xc4 => instruction(CPY, ZeroPage),
-			0xcc => instruction(CPY, Absolute),
-			//Decrement
-			0xc6 => instruction(DEC, ZeroPage),
-			0xd6 => instruction(DEC, ZeroPageX),
-			0xce => instruction(DEC, Absolute),
-			0xde => instruction(DEC, AbsoluteX),
-			//Decrement X
-			0xca => instruction(DEX, Implicit),
-			//Decrement Y
-			0x88 => instruction(DEY, Implicit),
-			//Exclusive OR
-			0x49 => instruction(EOR, Immediate),
-			0x45 => instruction(EOR, ZeroPage),
-			0x55 => instruction(EOR, ZeroPageX),
-			0x4d => instruction(EOR, Absolute),
-			0x5d => instruction(EOR, AbsoluteX),
-			0x59 => instruction(EOR, AbsoluteY),
-			0x41 => instruction(EOR, IndexedIndirect),
-			0x51 => instruction(EOR, IndirectIndexed),
-			//Increment Memory
-			0xe6 => instruction(INC, ZeroPage),
-			0xf6 => instruction(INC, ZeroPageX),
-			0xee => instruction(INC, Absolute),
-			0xfe => instruction(INC, AbsoluteX),
-			//Decrement X
-			0xe8 => instruction(INX, Implicit),
-			//Decrement Y
-			0xc8 => instruction(INY, Implicit),
-			//Load Accumulator
-			0xa9 => instruction(LDA, Immediate),
-			0xa5 => instruction(LDA, ZeroPage),
-			0xb5 => instruction(LDA, ZeroPageX),
-			0xad => instruction(LDA, Absolute),
-			0xbd => instruction(LDA, AbsoluteX),
-			0xb9 => instruction(LDA, AbsoluteY),
-			0xa1 => instruction(LDA, IndexedIndirect),
-			0xb1 => instruction(LDA, IndirectIndexed),
-			//Load X Register
-			0xa2 => instruction(LDX, Immediate),
-			0xa6 => instruction(LDX, ZeroPage),
-			0xb6 => instruction(LDX, ZeroPageY),
-			0xae => instruction(LDX, Absolute),
-			0xbe => instruction(LDX, AbsoluteY),
-			//Load Y Register
-			0xa0 => instruction(LDY, Immediate),
-			0xa4 => instruction(LDY, ZeroPage),
-			0xb4 => instruction(LDY, ZeroPageY),
-			0xac => instruction(LDY, Absolute),
-			0xbc => instruction(LDY, AbsoluteY),
-			//Logical Shift Right
-			0x4a => instruction(LSR, Implicit),
-			0x46 => instruction(LSR, ZeroPage),
-			0x56 => instruction(LSR, ZeroPageX),
-			0x4e => instruction(LSR, Absolute),
-			0x5e => instruction(LSR, AbsoluteX),
-			//No Operation
-			0xea => instruction(NOP, Implicit),
-			//Logical Inclusive OR
-			0x09 => instruction(ORA, Immediate),
-			0x05 => instruction(ORA, ZeroPage),
-			0x15 => instruction(ORA, ZeroPageX),
-			0x0d => instruction(ORA, Absolute),
-			0x1d => instruction(ORA, AbsoluteX),
-			0x19 => instruction(ORA, AbsoluteY),
-			0x01 => instruction(ORA, IndexedIndirect),
-			0x11 => instruction(ORA, IndirectIndexed),
-			//Rotate Left
-			0x2a => instruction(ROL, Implicit),
-			0x26 => instruction(ROL, ZeroPage),
-			0x36 => instruction(ROL, ZeroPageX),
-			0x2e => instruction(ROL, Absolute),
-			0x3e => instruction(ROL, AbsoluteX),
-			//Rotate Right
-			0x6a => instruction(ROR, Implicit),
-			0x66 => instruction(ROR, ZeroPage),
-			0x76 => instruction(ROR, ZeroPageX),
-			0x6e => instruction(ROR, Absolute),
-			0x7e => instruction(ROR, AbsoluteX),
-			//Subtract with Carry
-			0xe9 => instruction(SBC, Immediate),
-			0xe5 => instruction(SBC, ZeroPage),
-			0xf5 => instruction(SBC, ZeroPageX),
-			0xed => instruction(SBC, Absolute),
-			0xfd => instruction(SBC, AbsoluteX),
-			0xf9 => instruction(SBC, AbsoluteY),
-			0xe1 => instruction(SBC, IndexedIndirect),
-			0xf1 => instruction(SBC, IndirectIndexed),
-			//Store accumulator
-			0x85 => instruction(STA, ZeroPage),
-			0x95 => instruction(STA, ZeroPageX),
-			0x8d => instruction(STA, Absolute),
-			0x9d => instruction(STA, AbsoluteX),
-			0x99 => instruction(STA, AbsoluteY),
-			0x81 => instruction(STA, IndexedIndirect),
-			0x91 => instruction(STA, IndirectIndexed),
-			//Store X Register
-			0x86 => instruction(STX, ZeroPage),
-			0x96 => instruction(STX, ZeroPageY),
-			0x8e => instruction(STX, Absolute),
-			//Store Y Register
-			0x84 => instruction(STY, ZeroPage),
-			0x94 => instruction(STY, ZeroPageY),
-			0x8c => instruction(STY, Absolute),
-			//Transfer
-			0xaa => instruction(TAX, Implicit),
-			0xa8 => instruction(TAY, Implicit),
-			0xba => instruction(TSX, Implicit),
-			0x8a => instruction(TXA, Implicit),
-			0x9a => instruction(TXS, Implicit),
-			0x98 => instruction(TYA, Implicit),
-			//Clear Flags
-			0x18 => instruction(CLC, Implicit),
-			0xd8 => instruction(CLD, Implicit),
-			0x58 => instruction(CLI, Implicit),
-			0xb8 => instruction(CLV, Implicit),
-			//Set Flags
-			0x38 => instruction(SEC, Implicit),
-			0xf8 => instruction(SED, Implicit),
-			0x78 => instruction(SEI, Implicit),
-			//Branch Instructions
-			0x90 => instruction(BCC, Relative),
-			0xb0 => instruction(BCS, Relative),
-			0xf0 => instruction(BEQ, Relative),
-			0x30 => instruction(BMI, Relative),
-			0xd0 => instruction(BNE, Relative),
-			0x10 => instruction(BPL, Relative),
-			0x50 => instruction(BVC, Relative),
-			0x70 => instruction(BVS, Relative),
-			//Jump
-			0x4c => instruction(JMP, Absolute),
-			0x6c => instruction(JMP, Indirect),
-			//Stack operations
-			0x20 => instruction(JSR, Absolute),
-			0x60 => instruction(RTS, Implicit),
-			0x48 => instruction(PHA, Implicit),
-			0x08 => instruction(PHP, Implicit),
-			0x68 => instruction(PLA, Implicit),
-			0x28 => instruction(PLP, Implicit),
-			_ => {
-				error!(
-					"Invalid instruction found at location {:04x} => {operation:02x}",
-					self.program_counter - 1
-				);
-				panic!();
-			}
+		self.fetch_byte(mem);
+		let (operand, page_crossed) = addressing_mode.get_operand(self, mem);
+		let penalty = page_crossed && operation.has_page_cross_penalty(self.variant);
+		Ok((Instruction(operation, operand), cycles + penalty as u8))
+	}
+
+	/// Opcodes known to the variant, leaving out undocumented ones in strict mode
+	pub fn lookup(&self, opcode: u8) -> Option<(Operation, AddressingMode, u8)> {
+		if self.variant.is_cmos() {
+			return lookup_65c02(opcode).or_else(|| lookup(opcode));
 		}
+		lookup(opcode).or_else(|| {
+			(self.variant.has_undocumented_opcodes() && !self.strict)
+				.then(|| lookup_undocumented(opcode))
+				.flatten()
+		})
 	}
 
-	fn get_flag(&self, flag: StatusFlags) -> bool {
+	pub fn get_flag(&self, flag: StatusFlags) -> bool {
 		self.status & flag.get_bit_mask() != 0
 	}
 	fn set_flag(&mut self, flag: StatusFlags, value: bool) {
 		self.status = (flag.get_bit_mask() | self.status) * value as u8
 			+ (!flag.get_bit_mask() & self.status) * !value as u8
 	}
-	fn fetch_word(&mut self, mem: &Memory) -> u16 {
+	fn fetch_word<B: Bus>(&mut self, mem: &mut B) -> u16 {
 		let address = self.program_counter;
-		self.program_counter += 2;
+		self.program_counter = self.program_counter.wrapping_add(2);
 		let word = mem.read_word(address);
 		debug!("[Fetch]\t\tword: {:04x} from: {address:04x}", word);
 		word
 	}
-	fn fetch_byte(&mut self, mem: &Memory) -> u8 {
+	fn fetch_byte<B: Bus>(&mut self, mem: &mut B) -> u8 {
 		let address = self.program_counter;
-		debug!(
-			"[Fetch]\t\tbyte: {:02x} from: {address:04x}",
-			mem.read_byte(address)
-		);
-		self.program_counter += 1;
-		mem.read_byte(address)
+		let byte = mem.read_byte(address);
+		debug!("[Fetch]\t\tbyte: {:02x} from: {address:04x}", byte);
+		self.program_counter = self.program_counter.wrapping_add(1);
+		byte
 	}
 	fn set_a(&mut self, value: u8) {
 		self.a = value;
@@ -618,79 +1543,64 @@ impl Cpu {
 		self.set_flag(StatusFlags::Zero, value == 0);
 		self.set_flag(StatusFlags::Negative, value & 0x80 > 0);
 	}
-	fn arithmetic_shift_left(&mut self, mem: &mut Memory, operand: Option<Operand>) {
-		if let Some(operand) = operand {
-			match operand {
-				Operand::Address(addr) => {
-					self.set_flag(StatusFlags::Carry, mem.read_byte(addr) & 0x80 > 0);
-					mem.modify(addr, |x| x << 1);
-					self.update_zero_and_negative_flag(mem.read_byte(addr));
-				}
-				Operand::Value(_) => warn!("Value operand not supported for ASL: {operand:?}"),
-			}
-		} else {
-			self.set_flag(StatusFlags::Carry, self.a & 0x80 > 0);
-			self.set_a(self.a << 1);
-		}
+	/// Applies `f` to the byte at `address` and writes the result back,
+	/// returning it
+	fn read_modify_write<B: Bus>(
+		&mut self,
+		mem: &mut B,
+		address: u16,
+		f: fn(&mut Self, u8) -> u8,
+	) -> u8 {
+		let value = f(self, mem.read_byte(address));
+		mem.write_byte(address, value);
+		value
 	}
-	fn rotate_left(&mut self, mem: &mut Memory, operand: Option<Operand>) {
-		if let Some(operand) = operand {
-			match operand {
-				Operand::Address(addr) => {
-					let new_carray_value = mem.read_byte(addr) >> 7 > 0;
-					mem.modify(addr, |x| {
-						x << 1 | (self.get_flag(StatusFlags::Carry) as u8) << 7
-					});
-					self.set_flag(StatusFlags::Carry, new_carray_value);
-					self.update_zero_and_negative_flag(mem.read_byte(addr));
-				}
-				Operand::Value(_) => warn!("Value operand not supported for ROL: {operand:?}"),
-			}
-		} else {
-			let new_carray_value = self.a >> 7 > 0;
-			self.set_a(self.a << 1 | self.get_flag(StatusFlags::Carry) as u8);
-			self.set_flag(StatusFlags::Carry, new_carray_value);
-		}
+	fn shift_left(&mut self, value: u8) -> u8 {
+		self.set_flag(StatusFlags::Carry, value & 0x80 > 0);
+		self.update_zero_and_negative_flag(value << 1);
+		value << 1
 	}
-	fn rotate_right(&mut self, mem: &mut Memory, operand: Option<Operand>) {
-		if let Some(operand) = operand {
-			match operand {
-				Operand::Address(addr) => {
-					let new_carray_value = mem.read_byte(addr) & 0x1 > 0;
-					mem.modify(addr, |x| {
-						x >> 1 | (self.get_flag(StatusFlags::Carry) as u8) << 7
-					});
-					self.set_flag(StatusFlags::Negative, self.get_flag(StatusFlags::Carry));
-					self.set_flag(StatusFlags::Carry, new_carray_value);
-				}
-				Operand::Value(_) => warn!("Value operand not supported for ROR: {operand:?}"),
-			}
-		} else {
-			let new_carray_value = self.a & 0x1 > 0;
-			println!("A: {:x}, New Carry: {new_carray_value}", self.a);
-			self.a = self.a >> 1 | (self.get_flag(StatusFlags::Carry) as u8) << 7;
-			self.set_flag(StatusFlags::Negative, self.get_flag(StatusFlags::Carry));
-			self.set_flag(StatusFlags::Carry, new_carray_value);
-		}
+	fn shift_right(&mut self, value: u8) -> u8 {
+		self.set_flag(StatusFlags::Carry, value & 0x1 > 0);
+		self.update_zero_and_negative_flag(value >> 1);
+		value >> 1
 	}
-	fn logical_shift_right(&mut self, mem: &mut Memory, operand: Option<Operand>) {
-		if let Some(operand) = operand {
-			match operand {
-				Operand::Address(addr) => {
-					self.set_flag(StatusFlags::Carry, mem.read_byte(addr) & 0x1 > 0);
-					mem.modify(addr, |x| x >> 1);
-					self.update_zero_and_negative_flag(mem.read_byte(addr));
-				}
-				Operand::Value(_) => warn!("Value operand not supported for LSR: {operand:?}"),
+	fn rotate_left(&mut self, value: u8) -> u8 {
+		let result = value << 1 | self.get_flag(StatusFlags::Carry) as u8;
+		self.set_flag(StatusFlags::Carry, value & 0x80 > 0);
+		self.update_zero_and_negative_flag(result);
+		result
+	}
+	fn rotate_right(&mut self, value: u8) -> u8 {
+		let result = value >> 1 | (self.get_flag(StatusFlags::Carry) as u8) << 7;
+		self.set_flag(StatusFlags::Carry, value & 0x1 > 0);
+		self.update_zero_and_negative_flag(result);
+		result
+	}
+	/// ARR, the rotate goes through the adder so C and V come from bits 6 and
+	/// 5 of the result, with a BCD fixup in decimal mode
+	fn and_rotate_right(&mut self, value: u8) {
+		let masked = self.a & value;
+		let mut result = masked >> 1 | (self.get_flag(StatusFlags::Carry) as u8) << 7;
+		self.update_zero_and_negative_flag(result);
+		self.set_flag(StatusFlags::Overflow, (result ^ masked) & 0x40 > 0);
+		if self.decimal_mode() {
+			if (masked & 0xf) + (masked & 0x1) > 0x5 {
+				result = result & 0xf0 | result.wrapping_add(0x6) & 0xf;
 			}
+			let carry = (masked & 0xf0) as u16 + (masked & 0x10) as u16 > 0x50;
+			if carry {
+				result = result.wrapping_add(0x60);
+			}
+			self.set_flag(StatusFlags::Carry, carry);
 		} else {
-			self.set_flag(StatusFlags::Carry, self.a & 0x1 > 0);
-			self.set_a(self.a >> 1);
+			self.set_flag(StatusFlags::Carry, result & 0x40 > 0);
 		}
+		self.a = result;
 	}
 	fn bit(&mut self, value: u8) {
-		self.set_flag(StatusFlags::Negative, self.a & 0x80 > 0);
-		self.set_flag(StatusFlags::Overflow, self.a & 0x40 > 0);
+		self.set_flag(StatusFlags::Negative, value & 0x80 > 0);
+		self.set_flag(StatusFlags::Overflow, value & 0x40 > 0);
 		self.set_flag(StatusFlags::Zero, self.a & value == 0);
 	}
 	fn compare_register(&mut self, value: u8, register_value: u8) {
@@ -702,67 +1612,114 @@ impl Cpu {
 		self.set_flag(StatusFlags::Zero, register_value == value);
 	}
 	fn add_with_carry(&mut self, value: u8) {
-		let result = self.a as u16
-			+ value as u16
-			+ if self.get_flag(StatusFlags::Carry) {
-				1 << 8
-			} else {
-				0
-			};
-		self.a = result as u8;
-		self.set_flag(StatusFlags::Zero, self.a == 0);
-		self.set_flag(StatusFlags::Negative, self.a & 0x80 > 0);
-		self.set_flag(StatusFlags::Carry, result & 0x100 > 0);
-		let result = if result & 0x100 > 0 {
-			result | 0xff00
+		let carry = self.get_flag(StatusFlags::Carry) as u16;
+		let result = self.a as u16 + value as u16 + carry;
+		// Z is always computed from the binary sum, even in decimal mode
+		self.set_flag(StatusFlags::Zero, result as u8 == 0);
+		if self.decimal_mode() {
+			let mut low = (self.a & 0xf) as u16 + (value & 0xf) as u16 + carry;
+			if low >= 0xa {
+				low = ((low + 0x6) & 0xf) + 0x10;
+			}
+			// N and V reflect the intermediate result before the high nibble is adjusted
+			let signed = (self.a & 0xf0) as i8 as i16 + (value & 0xf0) as i8 as i16 + low as i16;
+			self.set_flag(StatusFlags::Negative, signed & 0x80 > 0);
+			self.set_flag(StatusFlags::Overflow, !(-128..=127).contains(&signed));
+			let mut result = (self.a & 0xf0) as u16 + (value & 0xf0) as u16 + low;
+			if result >= 0xa0 {
+				result += 0x60;
+			}
+			self.set_flag(StatusFlags::Carry, result >= 0x100);
+			self.a = result as u8;
+			// The 65C02 sets N and Z from the decimal result instead
+			if self.variant.is_cmos() {
+				self.update_zero_and_negative_flag(self.a);
+			}
 		} else {
-			result
-		} as i16;
-		self.set_flag(StatusFlags::Overflow, !(-128..=127).contains(&result));
+			self.set_flag(StatusFlags::Negative, result & 0x80 > 0);
+			self.set_flag(
+				StatusFlags::Overflow,
+				(self.a ^ result as u8) & (value ^ result as u8) & 0x80 > 0,
+			);
+			self.set_flag(StatusFlags::Carry, result > 0xff);
+			self.a = result as u8;
+		}
 	}
 	fn sub_with_carry(&mut self, value: u8) {
-		let result = self.a as u16
-			+ if self.get_flag(StatusFlags::Carry) {
-				0
-			} else {
-				1 << 8
-			} - value as u16;
-		self.a = result as u8;
-		self.set_flag(StatusFlags::Zero, self.a == 0);
-		self.set_flag(StatusFlags::Negative, self.a & 0x80 > 0);
-		self.set_flag(StatusFlags::Carry, result & 0x100 != 0);
-		let result = if result & 0x100 > 0 {
-			result | 0xff00
+		let borrow = !self.get_flag(StatusFlags::Carry) as i16;
+		let result = self.a as i16 - value as i16 - borrow;
+		// NMOS parts set every flag from the binary difference, even in decimal mode
+		self.update_zero_and_negative_flag(result as u8);
+		self.set_flag(
+			StatusFlags::Overflow,
+			(self.a ^ value) & (self.a ^ result as u8) & 0x80 > 0,
+		);
+		self.set_flag(StatusFlags::Carry, result >= 0);
+		if self.decimal_mode() && self.variant.is_cmos() {
+			// The 65C02 adjusts the binary difference and takes N and Z from the
+			// decimal result
+			let low = (self.a & 0xf) as i16 - (value & 0xf) as i16 - borrow;
+			let mut result = result;
+			if result < 0 {
+				result -= 0x60;
+			}
+			if low < 0 {
+				result -= 0x6;
+			}
+			self.set_a(result as u8);
+		} else if self.decimal_mode() {
+			let mut low = (self.a & 0xf) as i16 - (value & 0xf) as i16 - borrow;
+			if low < 0 {
+				low = ((low - 0x6) & 0xf) - 0x10;
+			}
+			let mut result = (self.a & 0xf0) as i16 - (value & 0xf0) as i16 + low;
+			if result < 0 {
+				result -= 0x60;
+			}
+			self.a = result as u8;
 		} else {
-			result
-		} as i16;
-		self.set_flag(StatusFlags::Overflow, !(-128..=127).contains(&result));
+			self.a = result as u8;
+		}
+	}
+	fn decimal_mode(&self) -> bool {
+		self.variant.has_decimal_mode() && self.get_flag(StatusFlags::DecimalMode)
 	}
 }
 
-#[derive(Clone, Copy)]
-enum StatusFlags {
+/// Bits of the processor status register
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatusFlags {
 	Carry,
 	Zero,
 	InterruptDisable,
 	DecimalMode,
+	Break,
+	Unused,
 	Overflow,
 	Negative,
 }
 
 impl StatusFlags {
-	fn get_bit_mask(self) -> u8 {
+	pub fn get_bit_mask(self) -> u8 {
 		1 << match self {
 			Self::Carry => 0,
 			Self::Zero => 1,
 			Self::InterruptDisable => 2,
 			Self::DecimalMode => 3,
+			Self::Break => 4,
+			Self::Unused => 5,
 			Self::Overflow => 6,
 			Self::Negative => 7,
 		}
 	}
 }
 
+impl Default for Cpu {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
 impl fmt::Debug for Cpu {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		let mut output = String::from("\n");
@@ -0,0 +1,528 @@
+//! Debug information relating addresses to source lines, labels, scopes and
+//! segments, loaded from the native format written by [`crate::asm`], an
+//! ld65 `--dbgfile` or a VICE label file.
+//!
+//! The native format has one entry per line, addresses are in hex and `;`
+//! starts a comment:
+//!
+//! ```text
+//! file ID PATH
+//! scope ID PARENT [NAME]
+//! segment START END code|data NAME
+//! label ADDRESS NAME SCOPE
+//! line FILE LINE ADDRESS
+//! ```
+//!
+//! `PARENT` and `SCOPE` are `-` when absent, a scope without a name is the
+//! global scope. IDs are assigned in order from 0.
+//! Plain `LINE ADDRESS` pairs from older `symbols.dbg` files are read as lines
+//! of file 0.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::number::{self, Radix};
+
+/// Furthest an address may be past a label to be shown relative to it
+const MAX_LABEL_OFFSET: u16 = 0xff;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+	/// Source file paths, indexed by [`LineInfo::file`]
+	pub files: Vec<String>,
+	pub lines: Vec<LineInfo>,
+	pub labels: Vec<Label>,
+	/// Indexed by [`Label::scope`] and [`Scope::parent`]
+	pub scopes: Vec<Scope>,
+	pub segments: Vec<Segment>,
+}
+
+/// Address the code for a source line starts at, `line` starts from 1
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineInfo {
+	pub file: usize,
+	pub line: usize,
+	pub address: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Label {
+	pub name: String,
+	pub address: u16,
+	pub scope: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Scope {
+	pub name: String,
+	pub parent: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+	pub name: String,
+	pub range: RangeInclusive<u16>,
+	pub kind: SegmentKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SegmentKind {
+	Code,
+	Data,
+}
+
+/// Malformed debug information, `line` starts from 1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DebugInfoError {
+	pub line: usize,
+	pub message: String,
+}
+
+impl fmt::Display for DebugInfoError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "line {}: {}", self.line, self.message)
+	}
+}
+
+impl std::error::Error for DebugInfoError {}
+
+impl DebugInfo {
+	/// Parses `text`, detecting which of the supported formats it is in
+	pub fn parse(text: &str) -> Result<Self, DebugInfoError> {
+		let first = text.lines().find(|line| !line.trim().is_empty());
+		match first.map(str::split_whitespace).and_then(|mut w| w.next()) {
+			Some("version") => Self::parse_ld65(text),
+			Some("al") => Self::parse_vice(text),
+			_ => Self::parse_native(text),
+		}
+	}
+
+	/// Parses the native format described in the [module](self) documentation
+	pub fn parse_native(text: &str) -> Result<Self, DebugInfoError> {
+		let mut info = Self::default();
+		for (index, line) in text.lines().enumerate() {
+			let error = |message| DebugInfoError {
+				line: index + 1,
+				message,
+			};
+			let line = line.split(';').next().unwrap_or_default().trim();
+			let fields: Vec<_> = line.split_whitespace().collect();
+			let field = |i: usize| {
+				fields
+					.get(i)
+					.copied()
+					.ok_or_else(|| error(format!("Expected at least {} fields", i + 1)))
+			};
+			let address = |i| {
+				let field = field(i)?;
+				u16::from_str_radix(field, 16)
+					.map_err(|_| error(format!("Invalid address {field}")))
+			};
+			let number = |i| {
+				let field = field(i)?;
+				field
+					.parse::<usize>()
+					.map_err(|_| error(format!("Invalid number {field}")))
+			};
+			// Id of an entry defined earlier, `-` for none
+			let optional = |i, count: usize| {
+				if field(i)? == "-" {
+					return Ok(None);
+				}
+				let id = number(i)?;
+				if id >= count {
+					return Err(error(format!("Unknown id {id}")));
+				}
+				Ok(Some(id))
+			};
+			let next_id = |i, expected: usize| {
+				let id = number(i)?;
+				if id != expected {
+					return Err(error(format!("Expected id {expected}, got {id}")));
+				}
+				Ok(())
+			};
+			match fields.first().copied() {
+				None => (),
+				Some("file") => {
+					next_id(1, info.files.len())?;
+					field(2)?;
+					let name = line
+						.splitn(3, char::is_whitespace)
+						.nth(2)
+						.unwrap_or_default();
+					info.files.push(name.trim().to_string());
+				}
+				Some("scope") => {
+					next_id(1, info.scopes.len())?;
+					let scope = Scope {
+						name: fields.get(3).copied().unwrap_or_default().to_string(),
+						parent: optional(2, info.scopes.len())?,
+					};
+					info.scopes.push(scope);
+				}
+				Some("segment") => {
+					let kind = match field(3)? {
+						"code" => SegmentKind::Code,
+						"data" => SegmentKind::Data,
+						kind => return Err(error(format!("Unknown segment kind {kind}"))),
+					};
+					info.segments.push(Segment {
+						name: field(4)?.to_string(),
+						range: address(1)?..=address(2)?,
+						kind,
+					});
+				}
+				Some("label") => info.labels.push(Label {
+					name: field(2)?.to_string(),
+					address: address(1)?,
+					scope: optional(3, info.scopes.len())?,
+				}),
+				Some("line") => {
+					let file = number(1)?;
+					if file >= info.files.len() {
+						return Err(error(format!("Unknown file {file}")));
+					}
+					info.lines.push(LineInfo {
+						file,
+						line: number(2)?,
+						address: address(3)?,
+					});
+				}
+				Some(first) if first.starts_with(|c: char| c.is_ascii_digit()) => {
+					info.lines.push(LineInfo {
+						file: 0,
+						line: number(0)?,
+						address: address(1)?,
+					})
+				}
+				Some(first) => return Err(error(format!("Unknown entry {first}"))),
+			}
+		}
+		Ok(info)
+	}
+
+	/// Parses the debug file written by `ld65 --dbgfile`, keeping the files,
+	/// segments, scopes, `lab` symbols and the lines that map to code
+	pub fn parse_ld65(text: &str) -> Result<Self, DebugInfoError> {
+		// Entries are collected by their ld65 id first, since they can refer
+		// to ids defined further down
+		let mut files = HashMap::new();
+		let mut segments = HashMap::new();
+		let mut spans = HashMap::new();
+		let mut scopes = HashMap::new();
+		let mut symbols = Vec::new();
+		let mut lines = Vec::new();
+		for (index, line) in text.lines().enumerate() {
+			let error = |message| DebugInfoError {
+				line: index + 1,
+				message,
+			};
+			let Some((kind, attributes)) = line.trim().split_once(char::is_whitespace) else {
+				continue;
+			};
+			let attributes = parse_attributes(attributes);
+			let text = |key: &str| {
+				attributes
+					.get(key)
+					.cloned()
+					.ok_or_else(|| error(format!("Missing {key} in {kind}")))
+			};
+			let number = |key: &str| {
+				let value = text(key)?;
+				parse_number(&value).ok_or_else(|| error(format!("Invalid {key} {value}")))
+			};
+			let optional = |key: &str| {
+				attributes
+					.contains_key(key)
+					.then(|| number(key))
+					.transpose()
+			};
+			match kind {
+				"file" => {
+					files.insert(number("id")?, text("name")?);
+				}
+				"seg" => {
+					let kind = match text("type")?.as_str() {
+						"rw" | "bss" | "zp" => SegmentKind::Data,
+						_ if text("name")?.contains("DATA") => SegmentKind::Data,
+						_ => SegmentKind::Code,
+					};
+					segments.insert(
+						number("id")?,
+						(text("name")?, number("start")?, number("size")?, kind),
+					);
+				}
+				"span" => {
+					spans.insert(number("id")?, (number("seg")?, number("start")?));
+				}
+				"scope" => {
+					scopes.insert(number("id")?, (text("name")?, optional("parent")?));
+				}
+				"sym" if attributes.get("type").map(String::as_str) == Some("lab") => {
+					symbols.push((text("name")?, number("val")?, optional("scope")?));
+				}
+				// Macro expansions, type 2, would point into the macro body
+				"line" if attributes.contains_key("span") && optional("type")? != Some(2) => {
+					let span = text("span")?;
+					let span = span.split('+').next().and_then(parse_number);
+					let span = span.ok_or_else(|| error("Invalid span".into()))?;
+					lines.push((index + 1, number("file")?, number("line")?, span));
+				}
+				_ => (),
+			}
+		}
+
+		let error = |line, message| DebugInfoError { line, message };
+		let mut info = Self::default();
+		let file_ids = sorted_ids(&files);
+		info.files = file_ids.iter().map(|id| files[id].clone()).collect();
+		let scope_ids = sorted_ids(&scopes);
+		let scope_index =
+			|id: Option<usize>| id.and_then(|id| scope_ids.iter().position(|&s| s == id));
+		info.scopes = scope_ids
+			.iter()
+			.map(|id| Scope {
+				name: scopes[id].0.clone(),
+				parent: scope_index(scopes[id].1),
+			})
+			.collect();
+		for id in sorted_ids(&segments) {
+			let (name, start, size, kind) = &segments[&id];
+			if *size == 0 {
+				continue;
+			}
+			info.segments.push(Segment {
+				name: name.clone(),
+				range: *start as u16..=(start + size - 1) as u16,
+				kind: *kind,
+			});
+		}
+		info.labels = symbols
+			.into_iter()
+			.map(|(name, value, scope)| Label {
+				name,
+				address: value as u16,
+				scope: scope_index(scope),
+			})
+			.collect();
+		for (line, file, line_number, span) in lines {
+			let (segment, offset) = spans
+				.get(&span)
+				.ok_or_else(|| error(line, format!("Unknown span {span}")))?;
+			let (_, start, ..) = segments
+				.get(segment)
+				.ok_or_else(|| error(line, format!("Unknown segment {segment}")))?;
+			info.lines.push(LineInfo {
+				file: file_ids
+					.iter()
+					.position(|&id| id == file)
+					.ok_or_else(|| error(line, format!("Unknown file {file}")))?,
+				line: line_number,
+				address: (start + offset) as u16,
+			});
+		}
+		Ok(info)
+	}
+
+	/// Parses a VICE label file as written by `ld65 -Ln`, `al C:0810 .start`
+	pub fn parse_vice(text: &str) -> Result<Self, DebugInfoError> {
+		let mut info = Self::default();
+		for (index, line) in text.lines().enumerate() {
+			let error = |message| DebugInfoError {
+				line: index + 1,
+				message,
+			};
+			let mut fields = line.split_whitespace();
+			if fields.next() != Some("al") {
+				continue;
+			}
+			let (Some(address), Some(name)) = (fields.next(), fields.next()) else {
+				return Err(error("Expected an address and a name".into()));
+			};
+			let hex = address.rsplit(':').next().unwrap_or_default();
+			let address = u32::from_str_radix(hex, 16)
+				.ok()
+				.and_then(|address| u16::try_from(address).ok())
+				.ok_or_else(|| error(format!("Invalid address {address}")))?;
+			info.labels.push(Label {
+				name: name.trim_start_matches('.').to_string(),
+				address,
+				scope: None,
+			});
+		}
+		Ok(info)
+	}
+
+	/// Address each line of `file` starts at
+	pub fn line_addresses(&self, file: usize) -> HashMap<usize, u16> {
+		self.lines
+			.iter()
+			.filter(|line| line.file == file)
+			.map(|line| (line.line, line.address))
+			.collect()
+	}
+
+	/// Labels at `address` along with their scopes, as in `main::loop`
+	pub fn labels_at(&self, address: u16) -> impl Iterator<Item = String> + '_ {
+		self.labels
+			.iter()
+			.filter(move |label| label.address == address)
+			.map(|label| self.qualified_name(label))
+	}
+
+	/// Name of `label` prefixed with its named enclosing scopes
+	pub fn qualified_name(&self, label: &Label) -> String {
+		let mut name = label.name.clone();
+		let mut scope = label.scope;
+		while let Some(Scope {
+			name: scope_name,
+			parent,
+		}) = scope.and_then(|s| self.scopes.get(s))
+		{
+			if !scope_name.is_empty() {
+				name = format!("{scope_name}::{name}");
+			}
+			scope = *parent;
+		}
+		name
+	}
+
+	/// Whether `address` lies in a data segment
+	pub fn is_data(&self, address: u16) -> bool {
+		self.segments
+			.iter()
+			.any(|segment| segment.kind == SegmentKind::Data && segment.range.contains(&address))
+	}
+
+	/// Address of the label called `name`, either plain or qualified with its
+	/// scopes
+	pub fn label_address(&self, name: &str) -> Option<u16> {
+		self.labels
+			.iter()
+			.find(|label| label.name == name)
+			.or_else(|| {
+				self.labels
+					.iter()
+					.find(|label| self.qualified_name(label) == name)
+			})
+			.map(|label| label.address)
+	}
+
+	/// `address` as `label` or `label+offset` from the closest label at or
+	/// before it, `None` when there is no label within [`MAX_LABEL_OFFSET`]
+	pub fn symbolize(&self, address: u16) -> Option<String> {
+		let label = self
+			.labels
+			.iter()
+			.filter(|label| label.address <= address && address - label.address <= MAX_LABEL_OFFSET)
+			// Earliest defined of the closest labels
+			.min_by_key(|label| address - label.address)?;
+		let name = self.qualified_name(label);
+		Some(match address - label.address {
+			0 => name,
+			offset => format!("{name}+{offset}"),
+		})
+	}
+
+	/// Evaluates an address expression such as `snake_body+2` or `fb+2`, a sum
+	/// of labels and numbers read with [`Radix::Hex`]
+	pub fn evaluate(&self, expression: &str) -> Result<u16, String> {
+		let mut value = 0i64;
+		let mut rest = expression.trim();
+		let mut sign = 1;
+		if let Some(negated) = rest.strip_prefix('-') {
+			(sign, rest) = (-1, negated);
+		}
+		loop {
+			let end = rest.find(['+', '-']).unwrap_or(rest.len());
+			let term = rest[..end].trim();
+			let term_value = if number::is_number(term) {
+				number::parse(term, Radix::Hex)?
+			} else if term.is_empty() {
+				return Err(format!("Missing term in {}", expression.trim()));
+			} else {
+				match self.label_address(term) {
+					Some(address) => address as i64,
+					None => number::parse(term, Radix::Hex)
+						.map_err(|_| format!("Unknown label {term}"))?,
+				}
+			};
+			value += sign * term_value;
+			let Some(operator) = rest[end..].chars().next() else {
+				break;
+			};
+			sign = if operator == '-' { -1 } else { 1 };
+			rest = &rest[end + 1..];
+		}
+		u16::try_from(value).map_err(|_| format!("Address {value} is out of range"))
+	}
+}
+
+/// Writes the native format
+impl fmt::Display for DebugInfo {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let id = |id: Option<usize>| id.map_or("-".to_string(), |id| id.to_string());
+		for (i, file) in self.files.iter().enumerate() {
+			writeln!(f, "file {i} {file}")?;
+		}
+		for (i, scope) in self.scopes.iter().enumerate() {
+			writeln!(f, "scope {i} {} {}", id(scope.parent), scope.name)?;
+		}
+		for segment in &self.segments {
+			let kind = match segment.kind {
+				SegmentKind::Code => "code",
+				SegmentKind::Data => "data",
+			};
+			let (start, end) = (segment.range.start(), segment.range.end());
+			writeln!(f, "segment {start:x} {end:x} {kind} {}", segment.name)?;
+		}
+		for label in &self.labels {
+			writeln!(
+				f,
+				"label {:x} {} {}",
+				label.address,
+				label.name,
+				id(label.scope)
+			)?;
+		}
+		for line in &self.lines {
+			writeln!(f, "line {} {} {:x}", line.file, line.line, line.address)?;
+		}
+		Ok(())
+	}
+}
+
+/// Splits `key=value,key="quoted, value"` pairs
+fn parse_attributes(attributes: &str) -> HashMap<String, String> {
+	let mut pairs = HashMap::new();
+	let mut rest = attributes.trim();
+	while let Some((key, value)) = rest.split_once('=') {
+		let (value, next) = match value.strip_prefix('"') {
+			Some(quoted) => {
+				let end = quoted.find('"').unwrap_or(quoted.len());
+				let next = quoted[end..].trim_start_matches('"');
+				(&quoted[..end], next.strip_prefix(',').unwrap_or(next))
+			}
+			None => value.split_once(',').unwrap_or((value, "")),
+		};
+		pairs.insert(key.trim().to_string(), value.to_string());
+		rest = next;
+	}
+	pairs
+}
+
+/// Decimal or `0x` prefixed hex number
+fn parse_number(value: &str) -> Option<usize> {
+	match value.strip_prefix("0x") {
+		Some(hex) => usize::from_str_radix(hex, 16).ok(),
+		None => value.parse().ok(),
+	}
+}
+
+fn sorted_ids<T>(map: &HashMap<usize, T>) -> Vec<usize> {
+	let mut ids: Vec<_> = map.keys().copied().collect();
+	ids.sort_unstable();
+	ids
+}
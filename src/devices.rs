@@ -0,0 +1,76 @@
+use egui_macroquad::macroquad::prelude::{is_key_down, rand, KeyCode};
+
+use nemu::bus::Device;
+
+pub const SCREEN_MEMORY_START: u16 = 0xfb00;
+pub const SCREEN_MEMORY_END: u16 = 0xfeff;
+pub const INPUT_MEMORY_START: u16 = 0xfb;
+pub const INPUT_MEMORY_END: u16 = 0xfe;
+pub const RNG_MEMORY_LOCATION: u16 = 0xff;
+
+/// Returns a fresh random byte on every read
+pub struct Rng;
+
+impl Device for Rng {
+	fn read(&mut self, _offset: u16) -> u8 {
+		rand::gen_range(u8::MIN, u8::MAX)
+	}
+	fn write(&mut self, _offset: u16, _value: u8) {}
+}
+
+/// One byte per direction in `left`, `down`, `up`, `right` order, reading 1
+/// while the key is held down
+pub struct Keyboard;
+
+impl Device for Keyboard {
+	fn read(&mut self, offset: u16) -> u8 {
+		let (arrow, letter) = match offset {
+			0 => (KeyCode::Left, KeyCode::A),
+			1 => (KeyCode::Down, KeyCode::S),
+			2 => (KeyCode::Up, KeyCode::W),
+			_ => (KeyCode::Right, KeyCode::D),
+		};
+		(is_key_down(arrow) | is_key_down(letter)) as u8
+	}
+	fn write(&mut self, _offset: u16, _value: u8) {}
+	fn peek(&self, offset: u16) -> Option<u8> {
+		Some(Keyboard.read(offset))
+	}
+}
+
+/// 32x32 framebuffer with one RGB byte per pixel in raster scan order
+pub struct Screen {
+	pub pixels: Vec<u8>,
+}
+
+impl Screen {
+	/// Creates the framebuffer with the initial contents from `data`, letting
+	/// programs ship a preloaded image
+	pub fn new(data: &[u8]) -> Self {
+		Self {
+			pixels: data[SCREEN_MEMORY_START as usize..=SCREEN_MEMORY_END as usize].to_vec(),
+		}
+	}
+}
+
+impl Device for Screen {
+	fn read(&mut self, offset: u16) -> u8 {
+		self.pixels[offset as usize]
+	}
+	fn write(&mut self, offset: u16, value: u8) {
+		self.pixels[offset as usize] = value;
+	}
+	fn peek(&self, offset: u16) -> Option<u8> {
+		Some(self.pixels[offset as usize])
+	}
+	fn save(&self) -> Vec<u8> {
+		self.pixels.clone()
+	}
+	fn load(&mut self, state: &[u8]) -> Result<(), String> {
+		if state.len() != self.pixels.len() {
+			return Err(format!("screen saved with {} pixels", state.len()));
+		}
+		self.pixels.copy_from_slice(state);
+		Ok(())
+	}
+}
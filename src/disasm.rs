@@ -0,0 +1,198 @@
+//! Disassembler built on the opcode table the CPU decodes with, producing
+//! syntax the [`crate::asm`] assembler accepts.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::bus::Memory;
+use crate::cpu::{AddressingMode, Cpu, CpuState, Operation};
+
+const JSR_OPCODE: u8 = 0x20;
+const RTS_OPCODE: u8 = 0x60;
+const RTI_OPCODE: u8 = 0x40;
+
+/// Single decoded instruction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+	pub address: u16,
+	/// Opcode followed by its operand
+	pub bytes: Vec<u8>,
+	/// Mnemonic and operand, `.byte $xx` for opcodes the CPU doesn't know
+	pub text: String,
+	/// Address a branch, jump or call goes to when it is known without running
+	/// the instruction
+	pub target: Option<u16>,
+}
+
+impl fmt::Display for Line {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let bytes: Vec<_> = self
+			.bytes
+			.iter()
+			.map(|byte| format!("{byte:02x}"))
+			.collect();
+		write!(
+			f,
+			"{:04x}  {:<8}  {}",
+			self.address,
+			bytes.join(" "),
+			self.text
+		)
+	}
+}
+
+/// Decodes the instruction at `address` with the opcodes known to `cpu`,
+/// reading through [`Memory::peek`] so devices see no accesses
+pub fn disassemble(cpu: &Cpu, mem: &Memory, address: u16) -> Line {
+	let opcode = mem.peek(address);
+	let Some((operation, mode, _)) = cpu.lookup(opcode) else {
+		return Line {
+			address,
+			bytes: vec![opcode],
+			text: format!(".byte ${opcode:02x}"),
+			target: None,
+		};
+	};
+	let mut bytes = vec![opcode];
+	bytes.extend((1..=mode.operand_len()).map(|i| mem.peek(address.wrapping_add(i))));
+	let byte = bytes.get(1).copied().unwrap_or_default();
+	let word = u16::from_le_bytes([byte, bytes.get(2).copied().unwrap_or_default()]);
+	// Branch targets are relative to the end of the instruction
+	let target = |offset: u8| {
+		address
+			.wrapping_add(bytes.len() as u16)
+			.wrapping_add(offset as i8 as u16)
+	};
+	use AddressingMode::*;
+	let operand = match mode {
+		Implicit => String::new(),
+		Immediate => format!("#${byte:02x}"),
+		ZeroPage => format!("${byte:02x}"),
+		ZeroPageX => format!("${byte:02x},x"),
+		ZeroPageY => format!("${byte:02x},y"),
+		Relative => format!("${:04x}", target(byte)),
+		Absolute => format!("${word:04x}"),
+		AbsoluteX => format!("${word:04x},x"),
+		AbsoluteY => format!("${word:04x},y"),
+		Indirect => format!("(${word:04x})"),
+		IndexedIndirect => format!("(${byte:02x},x)"),
+		IndirectIndexed => format!("(${byte:02x}),y"),
+		ZeroPageIndirect => format!("(${byte:02x})"),
+		AbsoluteIndexedIndirect => format!("(${word:04x},x)"),
+		ZeroPageRelative => format!("${byte:02x},${:04x}", target(bytes[2])),
+	};
+	let target = match (operation, mode) {
+		(_, Relative) => Some(target(byte)),
+		(_, ZeroPageRelative) => Some(target(bytes[2])),
+		(Operation::JMP | Operation::JSR, Absolute) => Some(word),
+		_ => None,
+	};
+	let mnemonic = mnemonic(operation);
+	let text = if operand.is_empty() {
+		mnemonic
+	} else {
+		format!("{mnemonic} {operand}")
+	};
+	Line {
+		address,
+		bytes,
+		text,
+		target,
+	}
+}
+
+/// Decodes every instruction starting within `range`, the last one may extend
+/// past its end
+pub fn disassemble_range(cpu: &Cpu, mem: &Memory, range: RangeInclusive<u16>) -> Vec<Line> {
+	let mut lines = Vec::new();
+	let mut address = *range.start() as u32;
+	while address <= *range.end() as u32 {
+		let line = disassemble(cpu, mem, address as u16);
+		address += line.bytes.len() as u32;
+		lines.push(line);
+	}
+	lines
+}
+
+/// Return addresses of the `JSR`s found on the stack above `stack_pointer`,
+/// innermost first. Pairs of bytes that don't point just past a `JSR` are
+/// skipped, so values pushed with `PHA` are not mistaken for calls.
+pub fn return_addresses(mem: &Memory, stack_pointer: u8) -> Vec<u16> {
+	let mut addresses = Vec::new();
+	let mut offset = stack_pointer as u16 + 1;
+	while offset < 0xff {
+		let low = mem.peek(0x100 | offset);
+		let high = mem.peek(0x100 | (offset + 1));
+		// JSR pushes the address of its last byte
+		let address = u16::from_le_bytes([low, high]).wrapping_add(1);
+		if mem.peek(address.wrapping_sub(3)) == JSR_OPCODE {
+			addresses.push(address);
+			offset += 2;
+		} else {
+			offset += 1;
+		}
+	}
+	addresses
+}
+
+/// Where execution started by Step Over, Step Out or Run to Cursor pauses
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunTarget {
+	/// Back at the instruction after a `JSR`, once the stack pointer is back
+	/// to where it was so recursive calls run through
+	Return {
+		address: u16,
+		stack_pointer: u8,
+	},
+	/// After an `RTS` or `RTI` pops the stack above this stack pointer
+	Out {
+		stack_pointer: u8,
+	},
+	Address(u16),
+}
+
+impl RunTarget {
+	/// Target of Step Over from `state`, `None` when the instruction there
+	/// isn't a `JSR` and a single step does
+	pub fn step_over(mem: &Memory, state: &CpuState) -> Option<Self> {
+		(mem.peek(state.program_counter) == JSR_OPCODE).then_some(Self::Return {
+			address: state.program_counter.wrapping_add(3),
+			stack_pointer: state.stack_pointer,
+		})
+	}
+
+	/// Target of Step Out from `state`, leaving the subroutine or interrupt
+	/// handler it is in
+	pub fn step_out(state: &CpuState) -> Self {
+		Self::Out {
+			stack_pointer: state.stack_pointer,
+		}
+	}
+
+	/// Whether `opcode` having executed, leaving the CPU in `state`, reaches
+	/// the target
+	pub fn reached(self, opcode: u8, state: &CpuState) -> bool {
+		match self {
+			Self::Return {
+				address,
+				stack_pointer,
+			} => state.program_counter == address && state.stack_pointer >= stack_pointer,
+			Self::Out { stack_pointer } => {
+				matches!(opcode, RTS_OPCODE | RTI_OPCODE) && state.stack_pointer > stack_pointer
+			}
+			Self::Address(address) => state.program_counter == address,
+		}
+	}
+}
+
+/// Lowercase mnemonic with the bit number appended for the Rockwell
+/// instructions, as in `bbr3`
+fn mnemonic(operation: Operation) -> String {
+	match operation {
+		Operation::BBR(bit) => format!("bbr{bit}"),
+		Operation::BBS(bit) => format!("bbs{bit}"),
+		Operation::RMB(bit) => format!("rmb{bit}"),
+		Operation::SMB(bit) => format!("smb{bit}"),
+		operation => format!("{operation:?}").to_lowercase(),
+	}
+}
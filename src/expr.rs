@@ -0,0 +1,260 @@
+//! Expressions over the CPU registers and memory, used for breakpoint
+//! conditions such as `A == 10 && X > #3` or `[fb] != 0`.
+//!
+//! Operands are numbers read with [`Radix::Hex`] like every other debugger
+//! entry, the registers `A`, `X`, `Y`, `SP`, `PC` and `P` (status), labels
+//! from the [`DebugInfo`] and `[address]` for the byte in memory. Registers
+//! and labels take precedence over hex numbers of the same name, `$a` is the
+//! number. From loosest to
+//! tightest binding the operators are `||`, `&&`, the comparisons `==`, `!=`,
+//! `<`, `<=`, `>`, `>=`, then `|`, `&`, `+` and `-`, and the unary `!` and
+//! `-`. Comparisons and logical operators give 1 for true and 0 for false.
+
+use crate::bus::Memory;
+use crate::cpu::CpuState;
+use crate::debug_info::DebugInfo;
+use crate::number::{self, Radix};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
+	A,
+	X,
+	Y,
+	SP,
+	PC,
+	/// Status register
+	P,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+	Or,
+	And,
+	Equal,
+	NotEqual,
+	Less,
+	LessEqual,
+	Greater,
+	GreaterEqual,
+	BitOr,
+	BitAnd,
+	Add,
+	Sub,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+	Value(i64),
+	Register(Register),
+	/// Byte at the address the inner expression evaluates to
+	Memory(Box<Expr>),
+	Not(Box<Expr>),
+	Negate(Box<Expr>),
+	Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+/// Binary operators grouped by precedence, loosest first
+const LEVELS: [&[(&str, BinaryOp)]; 6] = [
+	&[("||", BinaryOp::Or)],
+	&[("&&", BinaryOp::And)],
+	&[
+		("==", BinaryOp::Equal),
+		("!=", BinaryOp::NotEqual),
+		("<=", BinaryOp::LessEqual),
+		(">=", BinaryOp::GreaterEqual),
+		("<", BinaryOp::Less),
+		(">", BinaryOp::Greater),
+	],
+	&[("|", BinaryOp::BitOr)],
+	&[("&", BinaryOp::BitAnd)],
+	&[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+];
+
+/// Symbols recognised by the tokenizer, longest first so `&&` isn't read as
+/// two `&`
+const SYMBOLS: [&str; 17] = [
+	"||", "&&", "==", "!=", "<=", ">=", "<", ">", "|", "&", "+", "-", "!", "(", ")", "[", "]",
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+	Number(i64),
+	Name(String),
+	Symbol(&'static str),
+}
+
+impl Expr {
+	/// Parses `text`, resolving labels against `debug_info`
+	pub fn parse(text: &str, debug_info: &DebugInfo) -> Result<Self, String> {
+		let mut parser = Parser {
+			tokens: tokenize(text)?,
+			position: 0,
+			debug_info,
+		};
+		let expr = parser.parse_level(0)?;
+		match parser.tokens.get(parser.position) {
+			None => Ok(expr),
+			Some(token) => Err(format!("Unexpected {}", describe(token))),
+		}
+	}
+
+	pub fn evaluate(&self, state: &CpuState, mem: &Memory) -> i64 {
+		match self {
+			Expr::Value(value) => *value,
+			Expr::Register(register) => match register {
+				Register::A => state.a as i64,
+				Register::X => state.x as i64,
+				Register::Y => state.y as i64,
+				Register::SP => state.stack_pointer as i64,
+				Register::PC => state.program_counter as i64,
+				Register::P => state.status as i64,
+			},
+			Expr::Memory(address) => {
+				let address = address.evaluate(state, mem) as u16;
+				mem.peek(address) as i64
+			}
+			Expr::Not(expr) => (expr.evaluate(state, mem) == 0) as i64,
+			Expr::Negate(expr) => expr.evaluate(state, mem).wrapping_neg(),
+			Expr::Binary(op, lhs, rhs) => {
+				let lhs = lhs.evaluate(state, mem);
+				// Short circuits so memory isn't read needlessly
+				match op {
+					BinaryOp::Or if lhs != 0 => return 1,
+					BinaryOp::And if lhs == 0 => return 0,
+					_ => (),
+				}
+				let rhs = rhs.evaluate(state, mem);
+				match op {
+					BinaryOp::Or | BinaryOp::And => (rhs != 0) as i64,
+					BinaryOp::Equal => (lhs == rhs) as i64,
+					BinaryOp::NotEqual => (lhs != rhs) as i64,
+					BinaryOp::Less => (lhs < rhs) as i64,
+					BinaryOp::LessEqual => (lhs <= rhs) as i64,
+					BinaryOp::Greater => (lhs > rhs) as i64,
+					BinaryOp::GreaterEqual => (lhs >= rhs) as i64,
+					BinaryOp::BitOr => lhs | rhs,
+					BinaryOp::BitAnd => lhs & rhs,
+					BinaryOp::Add => lhs.wrapping_add(rhs),
+					BinaryOp::Sub => lhs.wrapping_sub(rhs),
+				}
+			}
+		}
+	}
+
+	/// Whether the expression evaluates to anything but 0
+	pub fn is_true(&self, state: &CpuState, mem: &Memory) -> bool {
+		self.evaluate(state, mem) != 0
+	}
+}
+
+struct Parser<'a> {
+	tokens: Vec<Token>,
+	position: usize,
+	debug_info: &'a DebugInfo,
+}
+
+impl Parser<'_> {
+	fn next(&mut self) -> Option<Token> {
+		let token = self.tokens.get(self.position).cloned();
+		self.position += 1;
+		token
+	}
+
+	fn eat(&mut self, symbol: &str) -> bool {
+		let found =
+			matches!(self.tokens.get(self.position), Some(Token::Symbol(s)) if *s == symbol);
+		if found {
+			self.position += 1;
+		}
+		found
+	}
+
+	fn expect(&mut self, symbol: &str) -> Result<(), String> {
+		if self.eat(symbol) {
+			return Ok(());
+		}
+		match self.tokens.get(self.position) {
+			Some(token) => Err(format!("Expected {symbol}, found {}", describe(token))),
+			None => Err(format!("Expected {symbol}")),
+		}
+	}
+
+	fn parse_level(&mut self, level: usize) -> Result<Expr, String> {
+		let Some(operators) = LEVELS.get(level) else {
+			return self.parse_unary();
+		};
+		let mut lhs = self.parse_level(level + 1)?;
+		while let Some(&(_, op)) = operators.iter().find(|(symbol, _)| self.eat(symbol)) {
+			let rhs = self.parse_level(level + 1)?;
+			lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+		}
+		Ok(lhs)
+	}
+
+	fn parse_unary(&mut self) -> Result<Expr, String> {
+		match self.next() {
+			Some(Token::Number(value)) => Ok(Expr::Value(value)),
+			Some(Token::Name(name)) => Ok(match name.to_ascii_uppercase().as_str() {
+				"A" => Expr::Register(Register::A),
+				"X" => Expr::Register(Register::X),
+				"Y" => Expr::Register(Register::Y),
+				"SP" => Expr::Register(Register::SP),
+				"PC" => Expr::Register(Register::PC),
+				"P" => Expr::Register(Register::P),
+				_ => Expr::Value(match self.debug_info.label_address(&name) {
+					Some(address) => address as i64,
+					None => number::parse(&name, Radix::Hex)
+						.map_err(|_| format!("Unknown label {name}"))?,
+				}),
+			}),
+			Some(Token::Symbol("!")) => Ok(Expr::Not(Box::new(self.parse_unary()?))),
+			Some(Token::Symbol("-")) => Ok(Expr::Negate(Box::new(self.parse_unary()?))),
+			Some(Token::Symbol("(")) => {
+				let expr = self.parse_level(0)?;
+				self.expect(")")?;
+				Ok(expr)
+			}
+			Some(Token::Symbol("[")) => {
+				let expr = self.parse_level(0)?;
+				self.expect("]")?;
+				Ok(Expr::Memory(Box::new(expr)))
+			}
+			Some(token) => Err(format!("Unexpected {}", describe(&token))),
+			None => Err("Unexpected end of expression".into()),
+		}
+	}
+}
+
+fn describe(token: &Token) -> String {
+	match token {
+		Token::Number(value) => value.to_string(),
+		Token::Name(name) => name.clone(),
+		Token::Symbol(symbol) => symbol.to_string(),
+	}
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+	let mut tokens = Vec::new();
+	let mut rest = text.trim_start();
+	while let Some(c) = rest.chars().next() {
+		let is_name = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '@' | '.' | ':');
+		let end = if number::is_number(rest) {
+			let end = rest[1..]
+				.find(|c: char| !c.is_ascii_alphanumeric())
+				.map_or(rest.len(), |i| i + 1);
+			tokens.push(Token::Number(number::parse(&rest[..end], Radix::Hex)?));
+			end
+		} else if is_name(c) {
+			let end = rest.find(|c| !is_name(c)).unwrap_or(rest.len());
+			tokens.push(Token::Name(rest[..end].to_string()));
+			end
+		} else if let Some(&symbol) = SYMBOLS.iter().find(|&&s| rest.starts_with(s)) {
+			tokens.push(Token::Symbol(symbol));
+			symbol.len()
+		} else {
+			return Err(format!("Unexpected character {c}"));
+		};
+		rest = rest[end..].trim_start();
+	}
+	Ok(tokens)
+}
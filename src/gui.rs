@@ -0,0 +1,407 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::SystemTime;
+
+use egui_macroquad::macroquad;
+use log::{error, info, warn};
+use macroquad::prelude::{
+	clear_background, draw_rectangle, draw_rectangle_lines, next_frame, screen_height,
+	screen_width, Color, BLACK, WHITE,
+};
+
+use nemu::bus::{Memory, MEMORY_SIZE};
+use nemu::debug_info::DebugInfo;
+use nemu::history::History;
+use nemu::save_state;
+
+use crate::app::{App, Rewind, SaveStateRequest, SourceFile};
+use crate::devices::*;
+use crate::{assemble_file, is_source, load_state, new_cpu, read_mem, Args};
+
+/// Slow enough to keep the examples playable, a real 6502 typically runs at 1 MHz
+pub const DEFAULT_CLOCK_RATE: u32 = 20_000;
+/// Longest frame time accounted for, avoids a burst of catch up work after a stall
+const MAX_FRAME_TIME: f32 = 0.1;
+/// Seconds between checks for changes to the loaded files
+const WATCH_INTERVAL: f64 = 0.5;
+
+/// Program image along with the sources and debug information for the
+/// debugger
+struct Program {
+	data: [u8; MEMORY_SIZE],
+	sources: Vec<SourceFile>,
+	debug_info: DebugInfo,
+}
+
+pub async fn run(args: Args) {
+	let mut load_error = None;
+	let program = load_program(&args).unwrap_or_else(|err| {
+		error!("{err}");
+		load_error = Some(err);
+		// Still show the source so the error can be located
+		let sources = std::fs::read_to_string(&args.executable)
+			.ok()
+			.filter(|_| is_source(&args.executable))
+			.map(|text| SourceFile::new(&args.executable, &text, &HashMap::new()));
+		Program {
+			data: [0; MEMORY_SIZE],
+			sources: sources.into_iter().collect(),
+			debug_info: DebugInfo::default(),
+		}
+	});
+	let mut data = program.data;
+	let mut watcher = Watcher::new(watched_paths(&args));
+
+	let (mut mem, mut screen) = build_memory(data);
+	let mut cpu = new_cpu(&args, &mut mem);
+	if let Some(path) = &args.load_state {
+		if let Err(err) = load_state(&args, path, &mut cpu, &mut mem) {
+			error!("{err}");
+			load_error = load_error.or(Some(err));
+		}
+	}
+	let mut history = History::new();
+	let mut app = App::new(program.sources, program.debug_info);
+
+	app.paused = args.start_debug || load_error.is_some();
+	app.load_error = load_error;
+	app.clock_rate = args.frequency;
+	// Cycles left to run in the current frame, goes negative when the last
+	// instruction overshoots so the average rate stays on target
+	let mut cycle_budget = 0f64;
+	loop {
+		// Keeps running the previous program when the new one fails to load
+		if watcher.changed() {
+			match load_program(&args) {
+				Ok(program) => {
+					info!("Reloaded {}", args.executable);
+					data = program.data;
+					app.reload(program.sources, program.debug_info);
+					app.load_error = None;
+					app.reset = true;
+				}
+				Err(err) => {
+					error!("{err}");
+					app.load_error = Some(err);
+				}
+			}
+		}
+		if app.reset {
+			(mem, screen) = build_memory(data);
+			cpu = new_cpu(&args, &mut mem);
+			history = History::new();
+			app.fault = None;
+			app.watch_hit = None;
+			app.reset_hits();
+			app.reset = false;
+		}
+		if let Some(rewind) = app.rewind.take() {
+			match rewind {
+				Rewind::StepBack => {
+					history.step_back(&mut cpu, &mut mem);
+				}
+				Rewind::ReverseContinue => {
+					while history.step_back(&mut cpu, &mut mem)
+						&& !app.is_breakpoint(&cpu.state(), &mem)
+					{}
+				}
+				Rewind::Seek(position) => history.seek(position, &mut cpu, &mut mem),
+			}
+			// Restoring memory goes through the bus and may trip a watchpoint
+			mem.take_watch_hit();
+			app.fault = None;
+			app.watch_hit = None;
+		}
+		if let Some(request) = app.save_state.take() {
+			app.save_state_message = Some(match request {
+				SaveStateRequest::Save(slot) => {
+					let path = state_path(&args, slot);
+					match std::fs::write(&path, save_state::save(&cpu, &mem)) {
+						Ok(()) => format!("Saved slot {slot} to {path}"),
+						Err(err) => format!("Failed to write {path}: {err}"),
+					}
+				}
+				SaveStateRequest::Load(slot) => {
+					let path = state_path(&args, slot);
+					match load_state(&args, &path, &mut cpu, &mut mem) {
+						Ok(()) => {
+							history = History::new();
+							app.fault = None;
+							app.watch_hit = None;
+							format!("Loaded slot {slot} from {path}")
+						}
+						Err(err) => err,
+					}
+				}
+			});
+		}
+		mem.watches = app.watches();
+		// Pauses on a fault so the debugger can show where it happened, on an
+		// access triggering a watchpoint or on reaching a breakpoint whose
+		// condition and counts are met
+		let mut execute_instruction = |app: &mut App| -> Option<u8> {
+			info!("{cpu:?}");
+			let program_counter = cpu.state().program_counter;
+			let opcode = mem.peek(program_counter);
+			// Drops accesses made by the debugger since the last instruction
+			mem.take_watch_hit();
+			app.watch_hit = None;
+			cpu.poll_interrupts(&mem);
+			match history.execute(&mut cpu, &mut mem) {
+				Ok(outcome) => {
+					if let Some(hit) = mem.take_watch_hit() {
+						app.watch_hit = Some((program_counter, hit));
+						app.paused = true;
+					}
+					if app.hit_breakpoint(&cpu.state(), &mem) {
+						app.paused = true;
+					}
+					app.check_run_target(opcode, &cpu.state());
+					Some(outcome.cycles())
+				}
+				Err(error) => {
+					error!("{error}");
+					app.fault = Some(error);
+					app.paused = true;
+					None
+				}
+			}
+		};
+		if !app.paused {
+			cycle_budget += (macroquad::time::get_frame_time().min(MAX_FRAME_TIME) as f64)
+				* app.clock_rate as f64;
+			while cycle_budget > 0. {
+				let Some(cycles) = execute_instruction(&mut app) else {
+					cycle_budget = 0.;
+					break;
+				};
+				cycle_budget -= cycles as f64;
+				if app.paused {
+					cycle_budget = 0.;
+					break;
+				};
+			}
+		} else {
+			cycle_budget = 0.;
+			if app.step {
+				execute_instruction(&mut app);
+				app.step = false;
+			}
+		}
+		// Window Decorations
+		clear_background(BLACK);
+		let screen_size = (screen_width(), screen_height());
+		let min_screen_dimension = screen_size.0.min(screen_size.1);
+		let pixel_size = app.window_scale * min_screen_dimension / 32.;
+		let gap = (
+			screen_size.0 - 32. * pixel_size,
+			screen_size.1 - 32. * pixel_size,
+		);
+		draw_rectangle_lines(
+			gap.0 - 2.,
+			gap.1 - 2.,
+			(pixel_size * 32.) + 4.,
+			(pixel_size * 32.) + 4.,
+			5.,
+			WHITE,
+		);
+		(0..32).for_each(|i| {
+			(0..32).for_each(|j| {
+				let color = |byte| {
+					Color::new(
+						(byte >> 5) as f32 / 8.,
+						((byte >> 2) % 8u8) as f32 / 8.,
+						(byte % 4) as f32 / 4.,
+						1.,
+					)
+				};
+				draw_rectangle(
+					gap.0 + j as f32 * pixel_size,
+					gap.1 + i as f32 * pixel_size,
+					pixel_size,
+					pixel_size,
+					color(screen.borrow().pixels[(i << 5) + j]),
+				);
+			})
+		});
+
+		app.timeline = (history.position(), history.len());
+		egui_macroquad::ui(|egui_ctx| {
+			app.render_ui(egui_ctx, &cpu, &mem);
+		});
+		for (address, value) in app.memory_edits.drain(..) {
+			history.write(&cpu, &mut mem, address, value);
+		}
+		if let Some(state) = app.register_edit.take() {
+			history.set_state(&mut cpu, state);
+		}
+
+		egui_macroquad::draw();
+
+		next_frame().await
+	}
+}
+
+/// Loads the program named by `args`, sources are assembled here so the image,
+/// symbols and source view all come from the same pass
+fn load_program(args: &Args) -> Result<Program, String> {
+	if !is_source(&args.executable) {
+		return load_binary(args);
+	}
+	let (source, assembly) = assemble_file(&args.executable)?;
+	let debug_info = assembly.debug_info(&args.executable);
+	let sources = vec![SourceFile::new(
+		&args.executable,
+		&source,
+		&debug_info.line_addresses(0),
+	)];
+	Ok(Program {
+		data: assembly.image,
+		sources,
+		debug_info,
+	})
+}
+
+/// Reads the binary along with its debug information and sources
+fn load_binary(args: &Args) -> Result<Program, String> {
+	#[cfg(target_family = "wasm")]
+	let (debug_info, source) = (
+		DebugInfo::parse(include_str!("../symbols.dbg")).map_err(|err| err.to_string())?,
+		Some((
+			String::from("examples/snake.asm"),
+			String::from(include_str!("../examples/snake.asm")),
+		)),
+	);
+	#[cfg(not(target_family = "wasm"))]
+	let (debug_info, source) = (
+		match &args.debug_symbols {
+			Some(path) => {
+				DebugInfo::parse(&read_file(path)?).map_err(|err| format!("{path}: {err}"))?
+			}
+			None => DebugInfo::default(),
+		},
+		match &args.assembly_source {
+			Some(path) => Some((path.clone(), read_file(path)?)),
+			None => None,
+		},
+	);
+	let sources = match source {
+		// Takes the place of the listed file with the same name, or the first
+		// one for debug symbols that don't name their files
+		Some((path, text)) => {
+			let name = Path::new(&path).file_name();
+			let file = debug_info
+				.files
+				.iter()
+				.position(|listed| Path::new(listed).file_name() == name)
+				.unwrap_or_default();
+			vec![SourceFile::new(
+				&path,
+				&text,
+				&debug_info.line_addresses(file),
+			)]
+		}
+		None => listed_sources(args, &debug_info),
+	};
+	Ok(Program {
+		data: read_mem(&args.executable)?,
+		sources,
+		debug_info,
+	})
+}
+
+/// Source files named by the debug information that can be read, relative
+/// paths are resolved against the directory of the debug symbols first
+fn listed_sources(args: &Args, debug_info: &DebugInfo) -> Vec<SourceFile> {
+	let base = args
+		.debug_symbols
+		.as_deref()
+		.and_then(|path| Path::new(path).parent())
+		.unwrap_or(Path::new(""));
+	let mut sources = Vec::new();
+	for (file, name) in debug_info.files.iter().enumerate() {
+		match std::fs::read_to_string(base.join(name)).or_else(|_| std::fs::read_to_string(name)) {
+			Ok(text) => sources.push(SourceFile::new(
+				name,
+				&text,
+				&debug_info.line_addresses(file),
+			)),
+			Err(err) => warn!("Skipping source {name}: {err}"),
+		}
+	}
+	sources
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn read_file(path: &str) -> Result<String, String> {
+	std::fs::read_to_string(path).map_err(|err| format!("Failed to read {path}: {err}"))
+}
+
+/// Save state file for `slot`, kept next to the program
+fn state_path(args: &Args, slot: usize) -> String {
+	format!("{}.state{slot}", args.executable)
+}
+
+/// Files the program is loaded from, a source or the binary along with its
+/// debug symbols and source
+fn watched_paths(args: &Args) -> Vec<String> {
+	let mut paths = vec![args.executable.clone()];
+	if !is_source(&args.executable) {
+		paths.extend(args.debug_symbols.clone());
+		paths.extend(args.assembly_source.clone());
+	}
+	paths
+}
+
+/// Polls the modification time of a set of files
+struct Watcher {
+	paths: Vec<String>,
+	modified: Vec<Option<SystemTime>>,
+	last_poll: f64,
+}
+
+impl Watcher {
+	fn new(paths: Vec<String>) -> Self {
+		let modified = paths.iter().map(|path| modified(path)).collect();
+		Self {
+			paths,
+			modified,
+			last_poll: macroquad::time::get_time(),
+		}
+	}
+	/// Whether any file changed since the last call, checks at most once every
+	/// [`WATCH_INTERVAL`] seconds
+	fn changed(&mut self) -> bool {
+		let now = macroquad::time::get_time();
+		if now - self.last_poll < WATCH_INTERVAL {
+			return false;
+		}
+		self.last_poll = now;
+		let modified: Vec<_> = self.paths.iter().map(|path| modified(path)).collect();
+		if modified == self.modified {
+			return false;
+		}
+		self.modified = modified;
+		true
+	}
+}
+
+/// Modification time of `path`, `None` if it can't be read
+fn modified(path: &str) -> Option<SystemTime> {
+	std::fs::metadata(path)
+		.and_then(|metadata| metadata.modified())
+		.ok()
+}
+
+/// Maps the peripherals over the loaded program, returning a handle to the
+/// screen for rendering
+fn build_memory(data: [u8; MEMORY_SIZE]) -> (Memory, Rc<RefCell<Screen>>) {
+	let mut mem = Memory::new(data);
+	let screen = Rc::new(RefCell::new(Screen::new(&data)));
+	mem.map_device(SCREEN_MEMORY_START..=SCREEN_MEMORY_END, screen.clone());
+	mem.map_device(INPUT_MEMORY_START..=INPUT_MEMORY_END, Keyboard);
+	mem.map_device(RNG_MEMORY_LOCATION..=RNG_MEMORY_LOCATION, Rng);
+	(mem, screen)
+}
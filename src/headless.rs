@@ -0,0 +1,153 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::bus::{Access, Memory, Watch};
+use crate::cpu::{Cpu, CpuError, StepOutcome};
+
+// Statuses from 252 up are reserved for the runner, so they can't be mistaken
+// for a value written by the program
+/// Highest exit status a byte written to the `on_write` address maps to,
+/// larger values are clamped to it
+pub const EXIT_WRITE_MAX: i32 = 251;
+/// Exit status when the program or a save state can't be loaded
+pub const EXIT_LOAD_ERROR: i32 = 252;
+/// Exit status when the instruction or cycle limit runs out before any other
+/// stop condition is met
+pub const EXIT_LIMIT_REACHED: i32 = 253;
+/// Exit status when the program gets stuck in a trap
+pub const EXIT_TRAPPED: i32 = 254;
+/// Exit status when the CPU faults, see [`CpuError`]
+pub const EXIT_FAULT: i32 = 255;
+
+const BRK_OPCODE: u8 = 0x00;
+
+#[derive(Default)]
+pub struct StopConditions {
+	pub max_instructions: Option<u64>,
+	pub max_cycles: Option<u64>,
+	/// Stop once the program counter lands on any of these addresses
+	pub addresses: Vec<u16>,
+	/// Stop before a BRK instruction is executed
+	pub on_brk: bool,
+	/// Stop as soon as the program writes to this address
+	pub on_write: Option<u16>,
+	/// Stop once an instruction jumps or branches to itself, the way test
+	/// suites signal success or failure
+	pub on_trap: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+	InstructionLimit,
+	CycleLimit,
+	Address(u16),
+	Break(u16),
+	Write(u16, u8),
+	Trap(u16),
+	Fault(CpuError),
+}
+
+impl StopReason {
+	/// Limits exit with `EXIT_LIMIT_REACHED`, traps with `EXIT_TRAPPED`, faults
+	/// with `EXIT_FAULT`, writes exit with the written value up to
+	/// `EXIT_WRITE_MAX` and everything else with success
+	pub fn exit_code(self) -> i32 {
+		match self {
+			Self::InstructionLimit | Self::CycleLimit => EXIT_LIMIT_REACHED,
+			Self::Trap(_) => EXIT_TRAPPED,
+			Self::Fault(_) => EXIT_FAULT,
+			Self::Write(_, value) => (value as i32).min(EXIT_WRITE_MAX),
+			Self::Address(_) | Self::Break(_) => 0,
+		}
+	}
+}
+
+impl fmt::Display for StopReason {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::InstructionLimit => write!(f, "instruction limit reached"),
+			Self::CycleLimit => write!(f, "cycle limit reached"),
+			Self::Address(address) => write!(f, "program counter reached 0x{address:04x}"),
+			Self::Break(address) => write!(f, "BRK at 0x{address:04x}"),
+			Self::Write(address, value) => write!(f, "wrote 0x{value:02x} to 0x{address:04x}"),
+			Self::Trap(address) => write!(f, "trapped at 0x{address:04x}"),
+			Self::Fault(error) => write!(f, "{error}"),
+		}
+	}
+}
+
+/// Runs the CPU until one of the `conditions` is met, returning the reason
+/// along with the number of instructions executed. The write condition is
+/// checked with [`Memory::watches`], which are put back the way they were
+/// once it returns
+pub fn run(cpu: &mut Cpu, mem: &mut Memory, conditions: &StopConditions) -> (StopReason, u64) {
+	let watches = conditions
+		.on_write
+		.map(|address| Watch {
+			range: address..=address,
+			access: Access::Write,
+			value: None,
+		})
+		.into_iter()
+		.collect();
+	let watches = std::mem::replace(&mut mem.watches, watches);
+	mem.take_watch_hit();
+	let stop = run_until(cpu, mem, conditions);
+	mem.watches = watches;
+	mem.take_watch_hit();
+	stop
+}
+
+fn run_until(cpu: &mut Cpu, mem: &mut Memory, conditions: &StopConditions) -> (StopReason, u64) {
+	let mut instructions = 0;
+	loop {
+		let state = cpu.state();
+		if conditions.addresses.contains(&state.program_counter) {
+			return (StopReason::Address(state.program_counter), instructions);
+		}
+		if conditions.on_brk && mem.peek(state.program_counter) == BRK_OPCODE {
+			return (StopReason::Break(state.program_counter), instructions);
+		}
+		if conditions
+			.max_instructions
+			.is_some_and(|max| instructions >= max)
+		{
+			return (StopReason::InstructionLimit, instructions);
+		}
+		if conditions.max_cycles.is_some_and(|max| state.cycles >= max) {
+			return (StopReason::CycleLimit, instructions);
+		}
+		cpu.poll_interrupts(mem);
+		let outcome = match cpu.execute(mem) {
+			Ok(outcome) => outcome,
+			Err(error) => return (StopReason::Fault(error), instructions),
+		};
+		instructions += 1;
+		// Waiting after a WAI leaves the program counter alone too
+		if conditions.on_trap
+			&& matches!(outcome, StepOutcome::Executed(_))
+			&& cpu.state().program_counter == state.program_counter
+		{
+			return (StopReason::Trap(state.program_counter), instructions);
+		}
+		if let Some(hit) = mem.take_watch_hit() {
+			return (StopReason::Write(hit.address, hit.new), instructions);
+		}
+	}
+}
+
+/// Formats `range` as rows of 16 bytes prefixed by their address
+pub fn dump_memory(mem: &Memory, range: RangeInclusive<u16>) -> String {
+	let mut output = String::new();
+	let mut address = *range.start() as u32;
+	while address <= *range.end() as u32 {
+		output.push_str(&format!("{address:04x}:"));
+		let row_end = (address + 16).min(*range.end() as u32 + 1);
+		for byte_address in address..row_end {
+			output.push_str(&format!(" {:02x}", mem.peek(byte_address as u16)));
+		}
+		output.push('\n');
+		address = row_end;
+	}
+	output
+}
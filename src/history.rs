@@ -0,0 +1,245 @@
+//! Execution history for stepping backwards, kept as the CPU before every
+//! instruction along with the writes it made, plus periodic snapshots of
+//! memory to seek quickly.
+//!
+//! Memory is restored by writing through the bus, so mapped devices such as a
+//! framebuffer see the old values again. Device state that isn't written by
+//! the program, like input, is not part of the history. Writes made outside of
+//! an instruction, like edits from the debugger, must go through
+//! [`History::write`] and [`History::set_state`] to be undone.
+
+use std::collections::VecDeque;
+
+use crate::bus::{Bus, Memory, MEMORY_SIZE};
+use crate::cpu::{Cpu, CpuError, CpuState, StepOutcome};
+
+/// Instructions kept by [`History::new`]
+pub const DEFAULT_CAPACITY: usize = 100_000;
+/// Instructions between snapshots taken by [`History::new`]
+pub const DEFAULT_SNAPSHOT_INTERVAL: usize = 5_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Write {
+	address: u16,
+	old: u8,
+	new: u8,
+}
+
+struct Entry {
+	/// State before the instruction
+	cpu: Cpu,
+	writes: Vec<Write>,
+}
+
+/// Memory before the entry at `index` executed
+struct Snapshot {
+	index: u64,
+	memory: Vec<u8>,
+}
+
+pub struct History {
+	/// Index of the first entry kept, counted from the start of execution
+	start: u64,
+	entries: VecDeque<Entry>,
+	/// Entries currently applied, the rest can be stepped forward through
+	position: usize,
+	/// State after the last entry
+	latest: Option<Cpu>,
+	snapshots: VecDeque<Snapshot>,
+	capacity: usize,
+	snapshot_interval: usize,
+}
+
+impl History {
+	pub fn new() -> Self {
+		Self::with_capacity(DEFAULT_CAPACITY, DEFAULT_SNAPSHOT_INTERVAL)
+	}
+
+	/// Keeps the last `capacity` instructions with a snapshot every
+	/// `snapshot_interval` of them
+	pub fn with_capacity(capacity: usize, snapshot_interval: usize) -> Self {
+		Self {
+			start: 0,
+			entries: VecDeque::new(),
+			position: 0,
+			latest: None,
+			snapshots: VecDeque::new(),
+			capacity: capacity.max(1),
+			snapshot_interval: snapshot_interval.max(1),
+		}
+	}
+
+	/// Executes the next instruction, recording it. Anything stepped back over
+	/// is discarded first, execution continues from the current point.
+	pub fn execute(&mut self, cpu: &mut Cpu, mem: &mut Memory) -> Result<StepOutcome, CpuError> {
+		self.entries.truncate(self.position);
+		let index = self.start + self.position as u64;
+		self.snapshots.retain(|snapshot| snapshot.index <= index);
+		if index.is_multiple_of(self.snapshot_interval as u64)
+			&& self.snapshots.back().map(|s| s.index) != Some(index)
+		{
+			self.snapshots.push_back(Snapshot {
+				index,
+				memory: (0..MEMORY_SIZE)
+					.map(|address| mem.peek(address as u16))
+					.collect(),
+			});
+		}
+		let before = cpu.clone();
+		mem.journal = Some(Vec::new());
+		let result = cpu.execute(mem);
+		let writes = mem
+			.journal
+			.take()
+			.unwrap_or_default()
+			.into_iter()
+			.map(|(address, old, new)| Write { address, old, new })
+			.collect();
+		self.entries.push_back(Entry {
+			cpu: before,
+			writes,
+		});
+		self.latest = Some(cpu.clone());
+		if self.entries.len() > self.capacity {
+			self.entries.pop_front();
+			self.start += 1;
+			while self
+				.snapshots
+				.front()
+				.is_some_and(|snapshot| snapshot.index < self.start)
+			{
+				self.snapshots.pop_front();
+			}
+		}
+		self.position = self.entries.len();
+		result
+	}
+
+	/// Writes `value` to `address` on behalf of the debugger. The write is
+	/// recorded as part of the last instruction applied, so stepping back over
+	/// that instruction undoes it too. Anything stepped back over is
+	/// discarded, as by [`History::execute`].
+	pub fn write(&mut self, cpu: &Cpu, mem: &mut Memory, address: u16, value: u8) {
+		self.entries.truncate(self.position);
+		let index = self.start + self.position as u64;
+		// A snapshot of the current point no longer matches memory
+		self.snapshots.retain(|snapshot| snapshot.index < index);
+		self.latest = Some(cpu.clone());
+		let old = mem.peek(address);
+		mem.write_byte(address, value);
+		if let Some(entry) = self.entries.back_mut() {
+			entry.writes.push(Write {
+				address,
+				old,
+				new: value,
+			});
+		}
+	}
+
+	/// Loads `state` into the CPU on behalf of the debugger. Stepping back
+	/// over the last instruction applied undoes it, as with [`History::write`],
+	/// and anything stepped back over is discarded.
+	pub fn set_state(&mut self, cpu: &mut Cpu, state: CpuState) {
+		self.entries.truncate(self.position);
+		let index = self.start + self.position as u64;
+		self.snapshots.retain(|snapshot| snapshot.index <= index);
+		cpu.set_state(state);
+		self.latest = Some(cpu.clone());
+	}
+
+	/// Number of instructions recorded
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	/// Instructions applied out of [`History::len`], less than it after
+	/// stepping back
+	pub fn position(&self) -> usize {
+		self.position
+	}
+
+	/// Instructions executed before the oldest one kept
+	pub fn start(&self) -> u64 {
+		self.start
+	}
+
+	/// Undoes the last applied instruction, returning `false` at the start of
+	/// the history
+	pub fn step_back(&mut self, cpu: &mut Cpu, mem: &mut Memory) -> bool {
+		let Some(entry) = self
+			.position
+			.checked_sub(1)
+			.and_then(|i| self.entries.get(i))
+		else {
+			return false;
+		};
+		for write in entry.writes.iter().rev() {
+			mem.write_byte(write.address, write.old);
+		}
+		*cpu = entry.cpu.clone();
+		self.position -= 1;
+		true
+	}
+
+	/// Applies the next instruction stepped back over again, returning
+	/// `false` at the end of the history
+	pub fn step_forward(&mut self, cpu: &mut Cpu, mem: &mut Memory) -> bool {
+		let Some(entry) = self.entries.get(self.position) else {
+			return false;
+		};
+		for write in &entry.writes {
+			mem.write_byte(write.address, write.new);
+		}
+		self.position += 1;
+		if let Some(state) = self.cpu_at(self.position) {
+			*cpu = state;
+		}
+		true
+	}
+
+	/// Moves to `position`, restoring the closest snapshot when that is
+	/// quicker than stepping there
+	pub fn seek(&mut self, position: usize, cpu: &mut Cpu, mem: &mut Memory) {
+		let position = position.min(self.entries.len());
+		let target = self.start + position as u64;
+		let snapshot = self
+			.snapshots
+			.iter()
+			.rev()
+			.find(|snapshot| snapshot.index >= self.start && snapshot.index <= target);
+		if let Some(snapshot) = snapshot {
+			let from_snapshot = target - snapshot.index;
+			if from_snapshot < self.position.abs_diff(position) as u64 {
+				for (address, &value) in snapshot.memory.iter().enumerate() {
+					if mem.peek(address as u16) != value {
+						mem.write_byte(address as u16, value);
+					}
+				}
+				self.position = (snapshot.index - self.start) as usize;
+				if let Some(state) = self.cpu_at(self.position) {
+					*cpu = state;
+				}
+			}
+		}
+		while self.position > position && self.step_back(cpu, mem) {}
+		while self.position < position && self.step_forward(cpu, mem) {}
+	}
+
+	/// CPU after `position` instructions
+	fn cpu_at(&self, position: usize) -> Option<Cpu> {
+		match self.entries.get(position) {
+			Some(entry) => Some(entry.cpu.clone()),
+			None => self.latest.clone(),
+		}
+	}
+}
+
+impl Default for History {
+	fn default() -> Self {
+		Self::new()
+	}
+}
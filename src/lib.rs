@@ -0,0 +1,35 @@
+//! Emulator core for the MOS 6502 and its variants, the `nemu` binary is a
+//! debugger frontend built on top of it.
+//!
+//! A [`cpu::Cpu`] executes one instruction per call to [`cpu::Cpu::execute`]
+//! against anything implementing [`bus::Bus`], usually a [`bus::Memory`] with
+//! peripherals mapped through [`bus::Memory::map_device`]:
+//!
+//! ```
+//! use nemu::bus::{Memory, MEMORY_SIZE};
+//! use nemu::cpu::{Cpu, Instruction, Operand, Operation, StatusFlags, StepOutcome};
+//!
+//! let mut data = [0; MEMORY_SIZE];
+//! // lda #$80
+//! data[..2].copy_from_slice(&[0xa9, 0x80]);
+//! let mut mem = Memory::new(data);
+//! let mut cpu = Cpu::new();
+//!
+//! let next = cpu.next_instruction(&mut mem);
+//! assert_eq!(next, Some(Instruction(Operation::LDA, Some(Operand::Value(0x80)))));
+//! assert_eq!(cpu.execute(&mut mem), Ok(StepOutcome::Executed(2)));
+//! assert_eq!(cpu.state().a, 0x80);
+//! assert!(cpu.get_flag(StatusFlags::Negative));
+//! ```
+
+pub mod asm;
+pub mod breakpoint;
+pub mod bus;
+pub mod cpu;
+pub mod debug_info;
+pub mod disasm;
+pub mod expr;
+pub mod headless;
+pub mod history;
+pub mod number;
+pub mod save_state;
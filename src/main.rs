@@ -1,52 +1,114 @@
-mod cpu;
-use std::collections::HashMap;
+use std::ops::RangeInclusive;
 
-use cpu::*;
-use egui_macroquad::*;
+use nemu::asm;
+use nemu::bus::*;
+use nemu::cpu::*;
+use nemu::headless;
+use nemu::save_state;
 
+#[cfg(feature = "gui")]
 mod app;
-use app::App;
-use log::{info, LevelFilter};
+#[cfg(feature = "gui")]
+mod devices;
+#[cfg(feature = "gui")]
+mod gui;
+use log::LevelFilter;
 
 use simplelog::{ColorChoice, Config, TermLogger, TerminalMode};
 
-use macroquad::prelude::{
-	clear_background, draw_rectangle, draw_rectangle_lines, is_key_down, next_frame, rand,
-	screen_height, screen_width, Color, KeyCode, BLACK, WHITE,
-};
-const SCREEN_MEMORY_START: usize = 0xfb00;
-const INPUT_MEMORY_LOCATION: usize = 0xfb;
-const RNG_MEMORY_LOCATION: usize = 0xff;
-
-use clap::Parser;
-
-const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 100;
+use clap::{Parser, Subcommand};
 
 /// A simple 6502 emulator
 #[derive(Parser, Debug)]
 struct Args {
-	/// Path to the 6502 binary
+	#[command(subcommand)]
+	command: Option<Command>,
+	/// Path to the 6502 binary, `.asm` sources are assembled on load along with
+	/// their debug symbols
 	#[arg(default_value = "a.out")]
 	executable: String,
 	/// Debug Verbosity level [0-2]
 	#[arg(short, long, default_value_t = 0)]
 	verbosity: u8,
 	/// Start in debug mode
+	#[cfg(feature = "gui")]
 	#[arg(short, long, default_value_t = false)]
 	start_debug: bool,
-	/// Number of CPU instructions to execute per frame
-	#[arg(short, long, default_value_t = DEFAULT_INSTRUCTIONS_PER_FRAME)]
-	instructions_per_frame: u32,
-	/// Debug symbols generated by the provided assembler
-	#[arg(short, long, default_value_t = String::from("symbols.dbg"))]
-	debug_symbols: String,
-	/// Assembly source code
-	#[arg(short, long, default_value_t = String::from("examples/snake.asm"))]
-	assembly_source: String,
+	/// Target clock rate of the CPU in Hz
+	#[cfg(feature = "gui")]
+	#[arg(short, long, default_value_t = gui::DEFAULT_CLOCK_RATE)]
+	frequency: u32,
+	/// Debug symbols generated by the provided assembler, unused when running
+	/// an `.asm` source
+	#[cfg(feature = "gui")]
+	#[arg(short, long)]
+	debug_symbols: Option<String>,
+	/// Assembly source code, unused when running an `.asm` source. Without it
+	/// the debugger only shows the disassembly
+	#[cfg(feature = "gui")]
+	#[arg(short, long)]
+	assembly_source: Option<String>,
+	/// Start execution from the reset vector at 0xfffc instead of address 0
+	#[arg(short, long, default_value_t = false)]
+	reset_vector: bool,
+	/// CPU variant to emulate [6502, 2a03, 65c02], defaults to 6502
+	#[arg(short, long)]
+	cpu: Option<Variant>,
+	/// Restore the machine from a save state after loading the program,
+	/// `--stack-checks` and `--strict` override the saved settings when given
+	#[arg(long)]
+	load_state: Option<String>,
+	/// Treat the stack pointer wrapping around as a fault
+	#[arg(long, default_value_t = false)]
+	stack_checks: bool,
+	/// Treat undocumented opcodes as invalid instead of executing them
+	#[arg(long, default_value_t = false)]
+	strict: bool,
+	/// Run without a window and print the final CPU state on exit, always
+	/// enabled when built without the `gui` feature
+	#[arg(long, default_value_t = false)]
+	headless: bool,
+	/// [Headless] Stop after executing this many instructions
+	#[arg(long)]
+	max_instructions: Option<u64>,
+	/// [Headless] Stop after this many clock cycles
+	#[arg(long)]
+	max_cycles: Option<u64>,
+	/// [Headless] Stop when the program counter reaches this address in hex,
+	/// can be repeated
+	#[arg(long, value_parser = parse_address)]
+	stop_at: Vec<u16>,
+	/// [Headless] Stop before executing a BRK instruction
+	#[arg(long, default_value_t = false)]
+	stop_on_brk: bool,
+	/// [Headless] Stop when a byte is written to this address in hex, exiting
+	/// with the written value as the status code
+	#[arg(long, value_parser = parse_address)]
+	stop_on_write: Option<u16>,
+	/// [Headless] Stop when an instruction jumps or branches to itself
+	#[arg(long, default_value_t = false)]
+	stop_on_trap: bool,
+	/// [Headless] Memory range to print on exit as START:END in hex, can be repeated
+	#[arg(long, value_parser = parse_range)]
+	dump: Vec<RangeInclusive<u16>>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+	/// Assemble a source file into a binary and its debug symbols
+	Asm {
+		/// Path to the assembly source
+		input: String,
+		/// Path for the assembled binary
+		#[arg(short, long, default_value_t = String::from("a.out"))]
+		output: String,
+		/// Path for the debug symbols
+		#[arg(short, long, default_value_t = String::from("symbols.dbg"))]
+		symbols: String,
+	},
 }
 
-#[macroquad::main("6502 Emulator")]
-async fn main() {
+fn main() {
 	let args = Args::parse();
 	#[cfg(not(target_family = "wasm"))]
 	TermLogger::init(
@@ -61,139 +123,146 @@ async fn main() {
 	)
 	.unwrap();
 
-	let mut debug_symbols_map: HashMap<usize, u16> = HashMap::new();
-	#[cfg(target_family = "wasm")]
-	let file = include_str!("../symbols.dbg");
-	#[cfg(not(target_family = "wasm"))]
-	let file = std::fs::read_to_string(args.debug_symbols).expect("Failed to read debug symbols");
-	file.lines().for_each(|line| {
-		let line_number: usize = line.split_whitespace().nth(0).unwrap().parse().unwrap();
-		let pc_address: u16 =
-			u16::from_str_radix(line.split_whitespace().last().unwrap(), 16).unwrap();
-		debug_symbols_map.insert(line_number, pc_address);
-	});
-
-	#[cfg(target_family = "wasm")]
-	let file = include_str!("../examples/snake.asm");
-	#[cfg(not(target_family = "wasm"))]
-	let file = std::fs::read_to_string(args.assembly_source).expect("Failed to read debug symbols");
-	let source_file: Vec<String> = file.lines().map(|i| String::from(i)).collect();
-
-	let mut last_pc_value = 0;
-	let mut debug_symbols: Vec<u16> = Vec::with_capacity(source_file.len());
-	for (line_number, _) in source_file.iter().enumerate() {
-		if let Some(&pc_addr) = debug_symbols_map.get(&(line_number + 1)) {
-			debug_symbols.push(pc_addr);
-			last_pc_value = pc_addr;
-		} else {
-			debug_symbols.push(last_pc_value);
-		}
+	if let Some(Command::Asm {
+		input,
+		output,
+		symbols,
+	}) = &args.command
+	{
+		std::process::exit(assemble(input, output, symbols));
 	}
+	#[cfg(feature = "gui")]
+	if !args.headless {
+		egui_macroquad::macroquad::Window::new("6502 Emulator", gui::run(args));
+		return;
+	}
+	std::process::exit(run_headless(&args));
+}
 
-	let data = read_mem(&args.executable);
-	let mut mem = Memory::new(data);
-	let mut cpu = Cpu::new();
-	let mut app = App::new(debug_symbols, source_file);
-
-	app.paused = args.start_debug;
-	// TODO: Handle the case when the user explicity sets the value to be the default
-	app.instructions_per_frame = if args.instructions_per_frame != DEFAULT_INSTRUCTIONS_PER_FRAME {
-		args.instructions_per_frame
+/// Runs the program until a stop condition from `args` is met, printing the
+/// final state and returning the exit status
+fn run_headless(args: &Args) -> i32 {
+	let data = if is_source(&args.executable) {
+		assemble_file(&args.executable).map(|(_, assembly)| assembly.image)
 	} else {
-		args.instructions_per_frame * 60 / macroquad::time::get_fps() as u32
+		read_mem(&args.executable)
 	};
-	loop {
-		if app.reset {
-			cpu = Cpu::new();
-			mem = Memory::new(data);
-			app.reset = false;
+	let data = match data {
+		Ok(data) => data,
+		Err(err) => {
+			eprintln!("{err}");
+			return headless::EXIT_LOAD_ERROR;
 		}
-		let mut execute_one_cycle = || -> u16 {
-			info!("{cpu:?}");
-			cpu.execute(&mut mem);
-			mem.data[RNG_MEMORY_LOCATION] = rand::gen_range(u8::MIN, u8::MAX);
-			// Left, Down, Up, Right
-			mem.data[INPUT_MEMORY_LOCATION] =
-				(is_key_down(KeyCode::Left) | is_key_down(KeyCode::A)) as u8;
-			mem.data[INPUT_MEMORY_LOCATION + 1] =
-				(is_key_down(KeyCode::Down) | is_key_down(KeyCode::S)) as u8;
-			mem.data[INPUT_MEMORY_LOCATION + 2] =
-				(is_key_down(KeyCode::Up) | is_key_down(KeyCode::W)) as u8;
-			mem.data[INPUT_MEMORY_LOCATION + 3] =
-				(is_key_down(KeyCode::Right) | is_key_down(KeyCode::D)) as u8;
-			cpu.state().program_counter
-		};
-		if !app.paused {
-			for _ in 0..app.instructions_per_frame {
-				if app.breakpoints_addresses().contains(&execute_one_cycle()) {
-					break;
-				};
-			}
-		} else if app.step {
-			execute_one_cycle();
-			app.step = false;
+	};
+	let mut mem = Memory::new(data);
+	let cpu = &mut new_cpu(args, &mut mem);
+	if let Some(path) = &args.load_state {
+		if let Err(err) = load_state(args, path, cpu, &mut mem) {
+			eprintln!("{err}");
+			return headless::EXIT_LOAD_ERROR;
 		}
-		if app
-			.breakpoints_addresses()
-			.contains(&cpu.state().program_counter)
-		{
-			app.paused = true;
+	}
+	let conditions = headless::StopConditions {
+		max_instructions: args.max_instructions,
+		max_cycles: args.max_cycles,
+		addresses: args.stop_at.clone(),
+		on_brk: args.stop_on_brk,
+		on_write: args.stop_on_write,
+		on_trap: args.stop_on_trap,
+	};
+	let (reason, instructions) = headless::run(cpu, &mut mem, &conditions);
+	println!(
+		"Stopped, {reason} after {instructions} instructions, {} cycles",
+		cpu.state().cycles
+	);
+	println!("{cpu:?}");
+	for range in &args.dump {
+		print!("{}", headless::dump_memory(&mem, range.clone()));
+	}
+	reason.exit_code()
+}
+
+/// Assembles `input` into the binary at `output` and its debug symbols at
+/// `symbols`, returning the exit status
+fn assemble(input: &str, output: &str, symbols: &str) -> i32 {
+	let assembly = match assemble_file(input) {
+		Ok((_, assembly)) => assembly,
+		Err(err) => {
+			eprintln!("{err}");
+			return 1;
 		}
-		// Window Decorations
-		clear_background(BLACK);
-		let screen_size = (screen_width(), screen_height());
-		let min_screen_dimension = screen_size.0.min(screen_size.1);
-		let pixel_size = app.window_scale * min_screen_dimension / 32.;
-		let gap = (
-			screen_size.0 - 32. * pixel_size,
-			screen_size.1 - 32. * pixel_size,
-		);
-		draw_rectangle_lines(
-			gap.0 - 2.,
-			gap.1 - 2.,
-			(pixel_size * 32.) + 4.,
-			(pixel_size * 32.) + 4.,
-			5.,
-			WHITE,
-		);
-		(0..32).for_each(|i| {
-			(0..32).for_each(|j| {
-				let color = |byte| {
-					Color::new(
-						(byte >> 5) as f32 / 8.,
-						((byte >> 2) % 8u8) as f32 / 8.,
-						(byte % 4) as f32 / 4.,
-						1.,
-					)
-				};
-				draw_rectangle(
-					gap.0 + j as f32 * pixel_size,
-					gap.1 + i as f32 * pixel_size,
-					pixel_size,
-					pixel_size,
-					color(mem.data[SCREEN_MEMORY_START + (i << 5) + j]),
-				);
-			})
-		});
-
-		egui_macroquad::ui(|egui_ctx| {
-			app.render_ui(egui_ctx, &cpu, &mut mem);
-		});
-
-		egui_macroquad::draw();
-
-		next_frame().await
+	};
+	let written = std::fs::write(output, assembly.image)
+		.and_then(|_| std::fs::write(symbols, assembly.debug_info(input).to_string()));
+	if let Err(err) = written {
+		eprintln!("Failed to write output: {err}");
+		return 1;
 	}
+	println!("Assembled '{input}' to '{output}' with debug symbols at '{symbols}'");
+	0
+}
+
+/// Whether `path` names an assembly source rather than a binary
+fn is_source(path: &str) -> bool {
+	std::path::Path::new(path)
+		.extension()
+		.is_some_and(|extension| extension.eq_ignore_ascii_case("asm"))
+}
+
+/// Reads and assembles the source at `path`, returning the source along with
+/// the assembly. Errors are formatted as `path:line:column: message`
+fn assemble_file(path: &str) -> Result<(String, asm::Assembly), String> {
+	let source =
+		std::fs::read_to_string(path).map_err(|err| format!("Failed to read {path}: {err}"))?;
+	let assembly = asm::assemble(&source).map_err(|err| format!("{path}:{err}"))?;
+	Ok((source, assembly))
 }
 
-fn read_mem(file_path: &str) -> [u8; MEMORY_SIZE] {
+/// Powers on the CPU configured by `args`, ready to run the program in `mem`
+fn new_cpu(args: &Args, mem: &mut Memory) -> Cpu {
+	let mut cpu = args.cpu.map_or_else(Cpu::new, Cpu::with_variant);
+	cpu.set_stack_checks(args.stack_checks);
+	cpu.set_strict(args.strict);
+	if args.reset_vector {
+		cpu.reset(mem);
+	}
+	cpu
+}
+
+/// Restores `cpu` and `mem` from the save state at `path`, checks enabled in
+/// `args` stay enabled whatever the save state says
+fn load_state(args: &Args, path: &str, cpu: &mut Cpu, mem: &mut Memory) -> Result<(), String> {
+	let bytes = std::fs::read(path).map_err(|err| format!("Failed to read {path}: {err}"))?;
+	save_state::load(&bytes, cpu, mem).map_err(|err| format!("{path}: {err}"))?;
+	if args.stack_checks {
+		cpu.set_stack_checks(true);
+	}
+	if args.strict {
+		cpu.set_strict(true);
+	}
+	Ok(())
+}
+
+fn read_mem(file_path: &str) -> Result<[u8; MEMORY_SIZE], String> {
 	#[cfg(not(target_family = "wasm"))]
-	let rom = std::fs::read(file_path).unwrap();
+	let rom = std::fs::read(file_path).map_err(|err| format!("Failed to read {file_path}: {err}"))?;
 	#[cfg(target_family = "wasm")]
 	let rom = include_bytes!("../a.out");
 	let mut data = [0; MEMORY_SIZE];
-	for (index, val) in rom.iter().enumerate() {
-		data[index] = *val;
+	for (byte, val) in data.iter_mut().zip(rom.iter()) {
+		*byte = *val;
 	}
-	data
+	Ok(data)
+}
+
+fn parse_address(s: &str) -> Result<u16, String> {
+	u16::from_str_radix(s.trim_start_matches("0x"), 16)
+		.map_err(|err| format!("Invalid address {s}: {err}"))
+}
+
+fn parse_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+	let (start, end) = s
+		.split_once(':')
+		.ok_or_else(|| format!("Expected START:END, got {s}"))?;
+	Ok(parse_address(start)?..=parse_address(end)?)
 }
@@ -0,0 +1,42 @@
+//! Number syntax shared by the assembler, address entries and breakpoint
+//! conditions, so they all read the same text the same way: `$hex`,
+//! `0xhex`, `%binary` and plain digits in the [`Radix`] of the context.
+
+/// How digits without a prefix are read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Radix {
+	/// `10` is ten, as in the assembler
+	Decimal,
+	/// `10` is sixteen and `#10` ten, as in every debugger entry. Names
+	/// made of hex digits only, such as `fb`, are numbers unless a label or
+	/// register has that name
+	Hex,
+}
+
+/// Whether `term` is written as a number rather than a name
+pub fn is_number(term: &str) -> bool {
+	term.starts_with(|c: char| c.is_ascii_digit() || matches!(c, '$' | '%' | '#'))
+}
+
+/// Value of the number `term`, see [`is_number`]
+pub fn parse(term: &str, radix: Radix) -> Result<i64, String> {
+	let (digits, radix) = if let Some(hex) = term.strip_prefix('$') {
+		(hex, 16)
+	} else if let Some(hex) = term.strip_prefix("0x") {
+		(hex, 16)
+	} else if let Some(binary) = term.strip_prefix('%') {
+		(binary, 2)
+	} else if let (Some(decimal), Radix::Hex) = (term.strip_prefix('#'), radix) {
+		(decimal, 10)
+	} else {
+		match radix {
+			Radix::Decimal => (term, 10),
+			Radix::Hex => (term, 16),
+		}
+	};
+	// from_str_radix would take a sign the caller already dealt with
+	if digits.starts_with(['+', '-']) {
+		return Err(format!("Invalid number {term}"));
+	}
+	i64::from_str_radix(digits, radix).map_err(|_| format!("Invalid number {term}"))
+}
@@ -0,0 +1,127 @@
+//! Save states holding the whole machine, the CPU with its cycle count and
+//! pending interrupts, all 64 KiB of memory and the state of mapped devices.
+//!
+//! The file starts with [`MAGIC`] and the [`VERSION`] it was written with,
+//! followed by the little endian fields. Files from a newer version are
+//! rejected rather than misread.
+
+use std::fmt;
+
+use crate::bus::{Memory, MEMORY_SIZE};
+use crate::cpu::Cpu;
+
+pub const MAGIC: &[u8; 8] = b"NEMUSAVE";
+/// Bumped whenever the layout changes
+pub const VERSION: u16 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SaveStateError {
+	/// Doesn't start with [`MAGIC`]
+	NotASaveState,
+	/// Written by a version of the format this one can't read
+	UnsupportedVersion(u16),
+	/// Ended before all of the state was read
+	Truncated,
+	Invalid(String),
+}
+
+impl fmt::Display for SaveStateError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::NotASaveState => write!(f, "not a save state"),
+			Self::UnsupportedVersion(version) => {
+				write!(f, "unsupported save state version {version}")
+			}
+			Self::Truncated => write!(f, "save state is truncated"),
+			Self::Invalid(message) => write!(f, "invalid save state, {message}"),
+		}
+	}
+}
+
+impl std::error::Error for SaveStateError {}
+
+/// Encodes the state of `cpu` and `mem`
+pub fn save(cpu: &Cpu, mem: &Memory) -> Vec<u8> {
+	let mut out = MAGIC.to_vec();
+	out.extend_from_slice(&VERSION.to_le_bytes());
+	cpu.save(&mut out);
+	out.extend_from_slice(&mem.data);
+	let devices = mem.save_devices();
+	out.extend_from_slice(&(devices.len() as u16).to_le_bytes());
+	for device in devices {
+		out.extend_from_slice(&(device.len() as u32).to_le_bytes());
+		out.extend_from_slice(&device);
+	}
+	out
+}
+
+/// Restores `cpu` and `mem` from `bytes`, leaving both untouched when the save
+/// state can't be read. The devices must be mapped the same way as when it
+/// was saved, watches and configuration like [`Cpu::set_strict`] come along
+/// with the CPU.
+pub fn load(bytes: &[u8], cpu: &mut Cpu, mem: &mut Memory) -> Result<(), SaveStateError> {
+	let mut reader = Reader(bytes);
+	if reader.take(MAGIC.len())? != MAGIC {
+		return Err(SaveStateError::NotASaveState);
+	}
+	let version = reader.u16()?;
+	if version != VERSION {
+		return Err(SaveStateError::UnsupportedVersion(version));
+	}
+	let mut loaded = cpu.clone();
+	loaded.load(&mut reader)?;
+	let data = reader.take(MEMORY_SIZE)?;
+	let count = reader.u16()? as usize;
+	let devices = (0..count)
+		.map(|_| {
+			let len = reader.u32()? as usize;
+			Ok(reader.take(len)?.to_vec())
+		})
+		.collect::<Result<Vec<_>, SaveStateError>>()?;
+	if !reader.0.is_empty() {
+		return Err(SaveStateError::Invalid("unexpected data at the end".into()));
+	}
+	mem.load_devices(&devices)
+		.map_err(SaveStateError::Invalid)?;
+	mem.data.copy_from_slice(data);
+	*cpu = loaded;
+	Ok(())
+}
+
+/// Reads little endian fields from the front of a save state
+pub(crate) struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+	pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+		if self.0.len() < len {
+			return Err(SaveStateError::Truncated);
+		}
+		let (bytes, rest) = self.0.split_at(len);
+		self.0 = rest;
+		Ok(bytes)
+	}
+
+	pub(crate) fn u8(&mut self) -> Result<u8, SaveStateError> {
+		Ok(self.take(1)?[0])
+	}
+
+	pub(crate) fn u16(&mut self) -> Result<u16, SaveStateError> {
+		Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+	}
+
+	pub(crate) fn u32(&mut self) -> Result<u32, SaveStateError> {
+		Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+	}
+
+	pub(crate) fn u64(&mut self) -> Result<u64, SaveStateError> {
+		Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+	}
+
+	pub(crate) fn bool(&mut self) -> Result<bool, SaveStateError> {
+		match self.u8()? {
+			0 => Ok(false),
+			1 => Ok(true),
+			value => Err(SaveStateError::Invalid(format!("{value} is not a boolean"))),
+		}
+	}
+}
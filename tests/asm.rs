@@ -0,0 +1,190 @@
+//! Checks the assembler against the committed `a.out` and `symbols.dbg`, which
+//! were built from `examples/snake.asm` with vasm.
+
+use nemu::asm::{assemble, AsmError};
+use nemu::debug_info::{DebugInfo, SegmentKind};
+
+fn assemble_ok(source: &str) -> Vec<u8> {
+	let assembly = assemble(source).unwrap();
+	let end = assembly
+		.image
+		.iter()
+		.rposition(|&byte| byte != 0)
+		.map_or(0, |i| i + 1);
+	assembly.image[..end].to_vec()
+}
+
+#[test]
+fn snake_matches_vasm() {
+	let assembly = assemble(include_str!("../examples/snake.asm")).unwrap();
+	let expected = include_bytes!("../a.out");
+	assert_eq!(assembly.image[..expected.len()], expected[..]);
+	assert!(assembly.image[expected.len()..]
+		.iter()
+		.all(|&byte| byte == 0));
+	let expected = DebugInfo::parse(include_str!("../symbols.dbg")).unwrap();
+	let debug_info = assembly.debug_info("examples/snake.asm");
+	assert_eq!(debug_info.lines, expected.lines);
+	assert_eq!(debug_info.files, ["examples/snake.asm"]);
+	assert!(debug_info
+		.labels
+		.iter()
+		.any(|label| label.name == "main" && label.address == 0x0200));
+}
+
+#[test]
+fn examples_assemble() {
+	for source in [
+		include_str!("../examples/ferris.asm"),
+		include_str!("../examples/paint.asm"),
+		include_str!("../examples/rgb.asm"),
+		include_str!("../examples/sequential_random.asm"),
+	] {
+		assemble(source).unwrap();
+	}
+}
+
+#[test]
+fn picks_zero_page_when_operand_fits() {
+	assert_eq!(assemble_ok("lda $fb\nlda $00fb"), [0xa5, 0xfb, 0xa5, 0xfb]);
+	assert_eq!(assemble_ok("lda $1234,x"), [0xbd, 0x34, 0x12]);
+	// LDA has no zero page,Y form
+	assert_eq!(assemble_ok("lda $10,y\nnop"), [0xb9, 0x10, 0x00, 0xea]);
+	assert_eq!(assemble_ok("ldx $10,y"), [0xb6, 0x10]);
+	// STX and STY have no absolute indexed form, so forward references fit
+	assert_eq!(
+		assemble_ok("stx later,y\nsty later,x\n.org $20\nlater:\nnop")[..4],
+		[0x96, 0x20, 0x94, 0x20]
+	);
+	// LDX does, forward references take it
+	assert_eq!(
+		assemble_ok("ldx later,y\n.org $20\nlater:\nnop")[..3],
+		[0xbe, 0x20, 0x00]
+	);
+}
+
+#[test]
+fn operand_syntax() {
+	assert_eq!(
+		assemble_ok(
+			"lda #10\nlda #$0a\nlda #%1010\nasl\nasl a\nlda ($20,x)\nlda ($20),y\njmp ($1234)"
+		),
+		[0xa9, 10, 0xa9, 10, 0xa9, 10, 0x0a, 0x0a, 0xa1, 0x20, 0xb1, 0x20, 0x6c, 0x34, 0x12]
+	);
+	assert_eq!(
+		assemble_ok("lda #-1\nlda #-$80\nlda #2-3\nlda #-1+3\n.word -2"),
+		[0xa9, 0xff, 0xa9, 0x80, 0xa9, 0xff, 0xa9, 0x02, 0xfe, 0xff]
+	);
+}
+
+#[test]
+fn labels_and_directives() {
+	let source = "\
+	jmp main ; forward reference
+	.org $10
+data:
+	.byte 1, $02
+	.word $1234, data
+main: lda data+1
+	ldx #<main
+	ldy #>main
+loop:
+	bne loop
+";
+	let assembly = assemble(source).unwrap();
+	assert_eq!(assembly.image[..3], [0x4c, 0x16, 0x00]);
+	assert_eq!(assembly.image[0x10..0x16], [1, 2, 0x34, 0x12, 0x10, 0x00]);
+	assert_eq!(
+		assembly.image[0x16..0x20],
+		[0xa5, 0x11, 0xa2, 0x16, 0xa0, 0x00, 0xd0, 0xfe, 0, 0]
+	);
+	assert_eq!(
+		assembly.symbols,
+		[
+			(1, 0),
+			(4, 0x10),
+			(5, 0x12),
+			(6, 0x16),
+			(7, 0x18),
+			(8, 0x1a),
+			(10, 0x1c)
+		]
+	);
+}
+
+#[test]
+fn debug_info_has_labels_and_data_segments() {
+	let assembly = assemble("start:\n\tnop\ntable:\n\t.byte 1, 2\n\t.word start\n\tnop").unwrap();
+	let debug_info = assembly.debug_info("test.asm");
+	let labels: Vec<_> = debug_info
+		.labels
+		.iter()
+		.map(|label| (label.name.as_str(), label.address))
+		.collect();
+	assert_eq!(labels, [("start", 0), ("table", 1)]);
+	let segments: Vec<_> = debug_info
+		.segments
+		.iter()
+		.map(|segment| (segment.range.clone(), segment.kind))
+		.collect();
+	assert_eq!(
+		segments,
+		[
+			(0..=0, SegmentKind::Code),
+			(1..=4, SegmentKind::Data),
+			(5..=5, SegmentKind::Code)
+		]
+	);
+	assert_eq!(
+		DebugInfo::parse(&debug_info.to_string()).unwrap(),
+		debug_info
+	);
+}
+
+#[test]
+fn reports_errors_with_line_and_column() {
+	let error = |source| assemble(source).err().unwrap();
+	let expect = |line, column, message: &str| AsmError {
+		line,
+		column,
+		message: message.into(),
+	};
+	assert_eq!(error("nop\nfoo"), expect(2, 1, "Unknown instruction FOO"));
+	assert_eq!(
+		error("lda #256"),
+		expect(1, 6, "Value 256 does not fit in a byte")
+	);
+	assert_eq!(error("\tlda #$1g"), expect(1, 7, "Invalid number $1g"));
+	assert_eq!(
+		error("jmp nowhere"),
+		expect(1, 5, "Undefined label in nowhere")
+	);
+	assert_eq!(
+		error("a:\n  a:"),
+		expect(2, 3, "Label a is already defined")
+	);
+	assert_eq!(
+		error(".org $fffe\nlda $1234"),
+		expect(2, 1, "Program does not fit in memory")
+	);
+	assert_eq!(
+		error("start:\n.org $100\nbne start"),
+		expect(3, 5, "Branch to start is out of range")
+	);
+	assert_eq!(
+		error("stx later,y\n.org $100\nlater:"),
+		expect(1, 5, "Value later does not fit in a byte")
+	);
+	assert_eq!(
+		error("lda #-129"),
+		expect(1, 6, "Value -129 does not fit in a byte")
+	);
+	assert_eq!(
+		error("stx $1234,x"),
+		expect(1, 1, "STX does not support AbsoluteX addressing")
+	);
+	assert_eq!(
+		error("lda #$100").to_string(),
+		"1:6: Value $100 does not fit in a byte"
+	);
+}
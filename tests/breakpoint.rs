@@ -0,0 +1,175 @@
+use nemu::asm::assemble;
+use nemu::breakpoint::{self, Trigger};
+use nemu::bus::{Access, Bus, Memory, Watch, WatchHit, MEMORY_SIZE};
+use nemu::cpu::{Cpu, CpuState};
+use nemu::debug_info::DebugInfo;
+use nemu::expr::Expr;
+
+fn hits(trigger: &mut Trigger, states: impl IntoIterator<Item = u8>) -> Vec<bool> {
+	let mem = Memory::new([0; MEMORY_SIZE]);
+	states
+		.into_iter()
+		.map(|a| {
+			let state = CpuState {
+				program_counter: 0,
+				x: 0,
+				y: 0,
+				a,
+				status: 0,
+				stack_pointer: 0xff,
+				cycles: 0,
+			};
+			trigger.hit(&state, &mem)
+		})
+		.collect()
+}
+
+#[test]
+fn breaks_every_time_by_default() {
+	assert_eq!(hits(&mut Trigger::default(), [0, 0]), [true, true]);
+}
+
+#[test]
+fn counts_only_hits_meeting_the_condition() {
+	let mut trigger = Trigger {
+		condition: Some(Expr::parse("A >= 2", &DebugInfo::default()).unwrap()),
+		..Trigger::default()
+	};
+	assert_eq!(hits(&mut trigger, [1, 2, 0, 3]), [false, true, false, true]);
+	assert_eq!(trigger.hits, 2);
+}
+
+#[test]
+fn skips_ignored_hits() {
+	let mut trigger = Trigger {
+		ignore_count: 2,
+		..Trigger::default()
+	};
+	assert_eq!(hits(&mut trigger, [0; 4]), [false, false, true, true]);
+}
+
+#[test]
+fn breaks_only_on_the_given_hit() {
+	let mut trigger = Trigger {
+		hit_count: Some(3),
+		..Trigger::default()
+	};
+	assert_eq!(hits(&mut trigger, [0; 4]), [false, false, true, false]);
+}
+
+/// Runs `source` one instruction at a time, returning the address of each
+/// instruction that triggered `watch` along with the access
+fn watch_hits(source: &str, watch: Watch) -> Vec<(u16, WatchHit)> {
+	let assembly = assemble(source).unwrap();
+	let mut mem = Memory::new(assembly.image);
+	mem.watches.push(watch);
+	let mut cpu = Cpu::new();
+	let mut hits = Vec::new();
+	while mem.data[cpu.state().program_counter as usize] != 0 {
+		let program_counter = cpu.state().program_counter;
+		cpu.execute(&mut mem).unwrap();
+		hits.extend(mem.take_watch_hit().map(|hit| (program_counter, hit)));
+	}
+	hits
+}
+
+const PROGRAM: &str = "lda $20\nlda #1\nsta $21\nsta $21\ninc $21\nldx $22";
+
+fn hit(address: u16, access: Access, old: u8, new: u8) -> WatchHit {
+	WatchHit {
+		address,
+		access,
+		old,
+		new,
+	}
+}
+
+#[test]
+fn watches_reads_in_a_range() {
+	let watch = Watch {
+		range: 0x20..=0x22,
+		access: Access::Read,
+		value: None,
+	};
+	assert_eq!(
+		watch_hits(PROGRAM, watch),
+		[
+			(0x00, hit(0x20, Access::Read, 0, 0)),
+			(0x08, hit(0x21, Access::Read, 1, 1)),
+			(0x0a, hit(0x22, Access::Read, 0, 0))
+		]
+	);
+}
+
+#[test]
+fn watches_writes_and_changes() {
+	let write = Watch {
+		range: 0x21..=0x21,
+		access: Access::Write,
+		value: None,
+	};
+	assert_eq!(
+		watch_hits(PROGRAM, write.clone()),
+		[
+			(0x04, hit(0x21, Access::Write, 0, 1)),
+			(0x06, hit(0x21, Access::Write, 1, 1)),
+			(0x08, hit(0x21, Access::Write, 1, 2))
+		]
+	);
+	let change = Watch {
+		access: Access::Change,
+		..write.clone()
+	};
+	assert_eq!(
+		watch_hits(PROGRAM, change),
+		[
+			(0x04, hit(0x21, Access::Change, 0, 1)),
+			(0x08, hit(0x21, Access::Change, 1, 2))
+		]
+	);
+	let filtered = Watch {
+		value: Some(2),
+		..write
+	};
+	assert_eq!(
+		watch_hits(PROGRAM, filtered),
+		[(0x08, hit(0x21, Access::Write, 1, 2))]
+	);
+}
+
+#[test]
+fn keeps_the_first_hit_until_taken() {
+	let mut mem = Memory::new([0; MEMORY_SIZE]);
+	mem.watches.push(Watch {
+		range: 0..=0xffff,
+		access: Access::Read,
+		value: None,
+	});
+	mem.read_byte(1);
+	mem.read_byte(2);
+	assert_eq!(mem.take_watch_hit().map(|hit| hit.address), Some(1));
+	assert_eq!(mem.take_watch_hit(), None);
+}
+
+fn lines(text: &str) -> Vec<String> {
+	text.lines().map(String::from).collect()
+}
+
+#[test]
+fn remaps_lines_to_their_text() {
+	let old = lines("start:\n\tlda #1\n\tsta $20\nloop:\n\tjmp loop");
+	// Moved down by lines inserted above, indentation doesn't matter
+	let moved = lines("; header\n\nstart:\n  lda #1\n  sta $20\nloop:\n  jmp loop");
+	assert_eq!(breakpoint::remap_line(&old, &moved, 2), Some(4));
+	assert_eq!(breakpoint::remap_line(&old, &moved, 5), Some(7));
+	// Duplicated, the copy closest to where it was wins
+	let duplicated = lines("\tsta $20\n\tnop\n\tnop\n\tsta $20\n\tnop\n\tnop\n\tsta $20");
+	assert_eq!(breakpoint::remap_line(&old, &duplicated, 3), Some(4));
+	// Deleted
+	let deleted = lines("start:\n\tlda #1\nloop:\n\tjmp loop");
+	assert_eq!(breakpoint::remap_line(&old, &deleted, 3), None);
+	assert_eq!(breakpoint::remap_line(&old, &deleted, 2), Some(2));
+	// Lines past the end of the old file
+	assert_eq!(breakpoint::remap_line(&old, &old, 0), None);
+	assert_eq!(breakpoint::remap_line(&old, &old, 6), None);
+}
@@ -0,0 +1,105 @@
+use nemu::asm::assemble;
+use nemu::bus::Memory;
+use nemu::cpu::{Cpu, Frame, FrameKind};
+
+fn load(source: &str) -> (Cpu, Memory) {
+	(Cpu::new(), Memory::new(assemble(source).unwrap().image))
+}
+
+/// Runs until the program counter reaches `address`
+fn run_to(cpu: &mut Cpu, mem: &mut Memory, address: u16) {
+	while cpu.state().program_counter != address {
+		cpu.execute(mem).unwrap();
+	}
+}
+
+fn frame(kind: FrameKind, caller: u16, target: u16, stack_pointer: u8) -> Frame {
+	Frame {
+		kind,
+		caller,
+		target,
+		stack_pointer,
+	}
+}
+
+const NESTED: &str = "
+	jsr outer    ; $00
+	brk          ; $03
+outer:
+	jsr inner    ; $04
+	rts          ; $07
+inner:
+	nop          ; $08
+	rts          ; $09
+";
+
+#[test]
+fn tracks_nested_calls() {
+	let (mut cpu, mut mem) = load(NESTED);
+	run_to(&mut cpu, &mut mem, 0x08);
+	assert_eq!(
+		cpu.call_stack(),
+		[
+			frame(FrameKind::Call, 0x00, 0x04, 0xff),
+			frame(FrameKind::Call, 0x04, 0x08, 0xfd)
+		]
+	);
+	run_to(&mut cpu, &mut mem, 0x07);
+	assert_eq!(cpu.call_stack(), [frame(FrameKind::Call, 0x00, 0x04, 0xff)]);
+	run_to(&mut cpu, &mut mem, 0x03);
+	assert!(cpu.call_stack().is_empty());
+}
+
+#[test]
+fn drops_frames_whose_return_address_is_discarded() {
+	let (mut cpu, mut mem) = load(
+		"
+	jsr first     ; $00
+	nop           ; $03
+first:
+	jsr second    ; $04
+second:
+	pla           ; $07
+	pla           ; $08
+	nop           ; $09
+	ldx #$ff      ; $0a
+	txs           ; $0c
+	nop           ; $0d
+",
+	);
+	run_to(&mut cpu, &mut mem, 0x07);
+	assert_eq!(cpu.call_stack().len(), 2);
+	run_to(&mut cpu, &mut mem, 0x09);
+	assert_eq!(cpu.call_stack(), [frame(FrameKind::Call, 0x00, 0x04, 0xff)]);
+	run_to(&mut cpu, &mut mem, 0x0d);
+	assert!(cpu.call_stack().is_empty());
+}
+
+#[test]
+fn tracks_interrupts() {
+	let (mut cpu, mut mem) = load(
+		"
+	cli          ; $00
+	nop          ; $01
+	brk          ; $02
+	nop          ; $03
+	nop          ; $04
+handler:
+	rti          ; $05
+	.org $fffe
+	.word handler
+",
+	);
+	run_to(&mut cpu, &mut mem, 0x01);
+	cpu.set_irq(true);
+	cpu.execute(&mut mem).unwrap();
+	assert_eq!(cpu.call_stack(), [frame(FrameKind::Irq, 0x01, 0x05, 0xff)]);
+	cpu.set_irq(false);
+	cpu.execute(&mut mem).unwrap();
+	assert!(cpu.call_stack().is_empty());
+	run_to(&mut cpu, &mut mem, 0x05);
+	assert_eq!(cpu.call_stack(), [frame(FrameKind::Brk, 0x02, 0x05, 0xff)]);
+	cpu.execute(&mut mem).unwrap();
+	assert_eq!(cpu.state().program_counter, 0x04);
+	assert!(cpu.call_stack().is_empty());
+}
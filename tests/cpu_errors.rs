@@ -0,0 +1,182 @@
+use nemu::asm::assemble;
+use nemu::bus::{Memory, MEMORY_SIZE};
+use nemu::cpu::{Cpu, CpuError, StepOutcome};
+
+fn program(source: &str) -> Memory {
+	Memory::new(assemble(source).unwrap().image)
+}
+
+/// Memory holding `bytes` at address 0
+fn bytes(bytes: &[u8]) -> Memory {
+	let mut data = [0; MEMORY_SIZE];
+	data[..bytes.len()].copy_from_slice(bytes);
+	Memory::new(data)
+}
+
+fn with_stack_pointer(mut cpu: Cpu, stack_pointer: u8) -> Cpu {
+	let mut state = cpu.state();
+	state.stack_pointer = stack_pointer;
+	cpu.set_state(state);
+	cpu
+}
+
+#[test]
+fn invalid_opcode_leaves_the_program_counter_on_it() {
+	// LAX $10 is undocumented, so strict mode has no instruction for it
+	let mut mem = bytes(&[0xea, 0xa7, 0x10]);
+	let mut cpu = Cpu::new();
+	cpu.set_strict(true);
+	cpu.execute(&mut mem).unwrap();
+	let expected = CpuError::InvalidOpcode {
+		address: 0x01,
+		opcode: 0xa7,
+	};
+	assert_eq!(cpu.execute(&mut mem), Err(expected));
+	assert_eq!(cpu.state().program_counter, 0x01);
+	assert_eq!(cpu.execute(&mut mem), Err(expected));
+	assert_eq!(cpu.state().program_counter, 0x01);
+}
+
+#[test]
+fn strict_mode_only_rejects_undocumented_opcodes() {
+	// LAX $10, then LDA #$42
+	let mut mem = bytes(&[0xa7, 0x10, 0xa9, 0x42]);
+	mem.data[0x10] = 0x37;
+	let mut cpu = Cpu::new();
+	cpu.execute(&mut mem).unwrap();
+	assert_eq!(cpu.state().a, 0x37);
+	assert_eq!(cpu.state().x, 0x37);
+
+	let mut cpu = Cpu::new();
+	cpu.set_strict(true);
+	assert!(matches!(
+		cpu.execute(&mut mem),
+		Err(CpuError::InvalidOpcode {
+			address: 0x00,
+			opcode: 0xa7
+		})
+	));
+	assert_eq!(cpu.state().x, 0);
+	let mut state = cpu.state();
+	state.program_counter = 0x02;
+	cpu.set_state(state);
+	cpu.execute(&mut mem).unwrap();
+	assert_eq!(cpu.state().a, 0x42);
+}
+
+#[test]
+fn every_decoded_opcode_is_executed() {
+	// InvalidInstruction means the decode tables and the executor disagree
+	let mut strict = Cpu::new();
+	strict.set_strict(true);
+	for (variant, cpu) in [Cpu::new(), Cpu::new_65c02(), strict].iter().enumerate() {
+		for opcode in 0..=u8::MAX {
+			let mut cpu = cpu.clone();
+			let mut mem = bytes(&[]);
+			mem.data[0x200] = opcode;
+			let mut state = cpu.state();
+			state.program_counter = 0x200;
+			cpu.set_state(state);
+			let result = cpu.execute(&mut mem);
+			assert!(
+				!matches!(result, Err(CpuError::InvalidInstruction { .. })),
+				"opcode 0x{opcode:02x} of cpu {variant}: {result:?}"
+			);
+		}
+	}
+}
+
+#[test]
+fn jam_keeps_failing_until_reset() {
+	let mut mem = bytes(&[0xea, 0x02, 0xea]);
+	let mut cpu = Cpu::new();
+	cpu.execute(&mut mem).unwrap();
+	let expected = CpuError::Jam {
+		address: 0x01,
+		opcode: 0x02,
+	};
+	for _ in 0..3 {
+		assert_eq!(cpu.execute(&mut mem), Err(expected));
+		assert_eq!(cpu.state().program_counter, 0x01);
+	}
+	// The same opcode is a two byte NOP on the 65C02
+	let mut cpu = Cpu::new_65c02();
+	cpu.execute(&mut mem).unwrap();
+	cpu.execute(&mut mem).unwrap();
+	assert_eq!(cpu.state().program_counter, 0x03);
+	// and undocumented in strict mode
+	let mut cpu = Cpu::new();
+	cpu.set_strict(true);
+	cpu.execute(&mut mem).unwrap();
+	assert_eq!(
+		cpu.execute(&mut mem),
+		Err(CpuError::InvalidOpcode {
+			address: 0x01,
+			opcode: 0x02
+		})
+	);
+}
+
+#[test]
+fn stop_keeps_failing_until_reset() {
+	let mut mem = bytes(&[0xea, 0xdb, 0xea]);
+	let mut cpu = Cpu::new_65c02();
+	cpu.execute(&mut mem).unwrap();
+	for _ in 0..3 {
+		assert_eq!(
+			cpu.execute(&mut mem),
+			Err(CpuError::Stopped { address: 0x01 })
+		);
+		assert_eq!(cpu.state().program_counter, 0x01);
+	}
+	mem.data[0xfffc..0xfffe].copy_from_slice(&[0x02, 0x00]);
+	cpu.reset(&mut mem);
+	assert!(matches!(
+		cpu.execute(&mut mem),
+		Ok(StepOutcome::Executed(_))
+	));
+	assert_eq!(cpu.state().program_counter, 0x03);
+}
+
+#[test]
+fn push_past_the_bottom_of_the_stack_overflows() {
+	let mut mem = program("\tlda #$42\n\tpha\n\tpha");
+	let mut cpu = with_stack_pointer(Cpu::new(), 0x00);
+	cpu.set_stack_checks(true);
+	cpu.execute(&mut mem).unwrap();
+	assert_eq!(
+		cpu.execute(&mut mem),
+		Err(CpuError::StackOverflow { address: 0x02 })
+	);
+	// The push still happened
+	assert_eq!(mem.data[0x100], 0x42);
+	assert_eq!(cpu.state().stack_pointer, 0xff);
+	assert_eq!(cpu.state().program_counter, 0x03);
+	cpu.execute(&mut mem).unwrap();
+}
+
+#[test]
+fn pull_past_the_top_of_the_stack_underflows() {
+	let mut mem = program("\tpla\n\tpla");
+	mem.data[0x100] = 0x42;
+	let mut cpu = with_stack_pointer(Cpu::new(), 0xff);
+	cpu.set_stack_checks(true);
+	assert_eq!(
+		cpu.execute(&mut mem),
+		Err(CpuError::StackUnderflow { address: 0x00 })
+	);
+	assert_eq!(cpu.state().a, 0x42);
+	assert_eq!(cpu.state().stack_pointer, 0x00);
+	cpu.execute(&mut mem).unwrap();
+}
+
+#[test]
+fn stack_wraps_silently_without_checks() {
+	let mut mem = program("\tpha\n\tpla\n\tpla");
+	let mut cpu = with_stack_pointer(Cpu::new(), 0x00);
+	cpu.execute(&mut mem).unwrap();
+	assert_eq!(cpu.state().stack_pointer, 0xff);
+	cpu.execute(&mut mem).unwrap();
+	cpu.execute(&mut mem).unwrap();
+	assert_eq!(cpu.state().stack_pointer, 0x01);
+}
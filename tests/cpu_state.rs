@@ -0,0 +1,45 @@
+use nemu::asm::assemble;
+use nemu::bus::Memory;
+use nemu::cpu::{Cpu, StatusFlags};
+
+#[test]
+fn flags_are_read_and_set() {
+	let mut state = Cpu::new().state();
+	state.set_flag(StatusFlags::Carry, true);
+	state.set_flag(StatusFlags::Negative, true);
+	assert_eq!(state.status, 0b1000_0001);
+	assert!(state.get_flag(StatusFlags::Carry));
+	assert!(!state.get_flag(StatusFlags::Zero));
+	state.set_flag(StatusFlags::Carry, false);
+	assert_eq!(state.status, 0b1000_0000);
+}
+
+#[test]
+fn patched_state_is_executed_from() {
+	let mut mem = Memory::new(
+		assemble("\tbcs skip\n\tlda #1\nskip:\n\tadc #$10")
+			.unwrap()
+			.image,
+	);
+	let mut cpu = Cpu::new();
+	let mut state = cpu.state();
+	state.a = 0x20;
+	state.set_flag(StatusFlags::Carry, true);
+	// B and the unused bit don't exist in the register itself
+	state.set_flag(StatusFlags::Break, true);
+	state.set_flag(StatusFlags::Unused, true);
+	cpu.set_state(state);
+	assert!(cpu.get_flag(StatusFlags::Carry));
+	assert!(!cpu.get_flag(StatusFlags::Break));
+	assert!(!cpu.get_flag(StatusFlags::Unused));
+	cpu.execute(&mut mem).unwrap();
+	assert_eq!(cpu.state().program_counter, 0x04);
+	cpu.execute(&mut mem).unwrap();
+	assert_eq!(cpu.state().a, 0x31);
+
+	state = cpu.state();
+	state.program_counter = 0x02;
+	cpu.set_state(state);
+	cpu.execute(&mut mem).unwrap();
+	assert_eq!(cpu.state().a, 1);
+}
@@ -0,0 +1,197 @@
+use nemu::debug_info::{DebugInfo, Label, LineInfo, Scope, Segment, SegmentKind};
+
+fn line(file: usize, line: usize, address: u16) -> LineInfo {
+	LineInfo {
+		file,
+		line,
+		address,
+	}
+}
+
+#[test]
+fn reads_legacy_line_pairs() {
+	let info = DebugInfo::parse("15 0\n21 200\n").unwrap();
+	assert_eq!(info.lines, [line(0, 15, 0), line(0, 21, 0x200)]);
+	assert!(info.files.is_empty());
+}
+
+#[test]
+fn native_format_round_trips() {
+	let info = DebugInfo {
+		files: vec!["main.asm".into(), "lib/util.asm".into()],
+		lines: vec![line(0, 3, 0x200), line(1, 10, 0x300)],
+		labels: vec![
+			Label {
+				name: "main".into(),
+				address: 0x200,
+				scope: None,
+			},
+			Label {
+				name: "loop".into(),
+				address: 0x205,
+				scope: Some(1),
+			},
+		],
+		scopes: vec![
+			Scope {
+				name: "".into(),
+				parent: None,
+			},
+			Scope {
+				name: "game".into(),
+				parent: Some(0),
+			},
+		],
+		segments: vec![Segment {
+			name: "DATA".into(),
+			range: 0x400..=0x4ff,
+			kind: SegmentKind::Data,
+		}],
+	};
+	let text = info.to_string();
+	assert!(text.contains("label 205 loop 1\n"));
+	assert_eq!(DebugInfo::parse(&text).unwrap(), info);
+	assert_eq!(info.labels_at(0x205).collect::<Vec<_>>(), ["game::loop"]);
+	assert!(info.is_data(0x480));
+	assert!(!info.is_data(0x200));
+}
+
+#[test]
+fn reports_malformed_native_entries() {
+	let error = |text| DebugInfo::parse(text).unwrap_err().to_string();
+	assert_eq!(
+		error("file 0 a.asm\nline 1 3 200"),
+		"line 2: Unknown file 1"
+	);
+	assert_eq!(error("15 zz"), "line 1: Invalid address zz");
+	assert_eq!(error("label 200"), "line 1: Expected at least 3 fields");
+	assert_eq!(error("scope 1 - main"), "line 1: Expected id 0, got 1");
+	assert_eq!(error("symbol 200 main"), "line 1: Unknown entry symbol");
+}
+
+const LD65: &str = r#"version	major=2,minor=0
+info	csym=0,file=2,lib=0,line=4,mod=1,scope=2,seg=2,span=3,sym=3,type=1
+file	id=0,name="main.s",size=120,mtime=0x5D2E8A1C,mod=0
+file	id=1,name="inc, with comma.inc",size=20,mtime=0x5D2E8A1C,mod=0
+line	id=0,file=0,line=4,span=0
+line	id=1,file=0,line=5,span=1
+line	id=2,file=1,line=2,span=2+1
+line	id=3,file=0,line=9,type=2,span=1
+mod	id=0,name="main.o",file=0
+seg	id=0,name="CODE",start=0x008000,size=0x0010,addrsize=absolute,type=ro,oname="a.bin",ooffs=0
+seg	id=1,name="BSS",start=0x000200,size=0x0100,addrsize=absolute,type=bss
+span	id=0,seg=0,start=0,size=2
+span	id=1,seg=0,start=2,size=3
+span	id=2,seg=0,start=5,size=1
+scope	id=0,name="",mod=0,size=16,span=0+1
+scope	id=1,name="main",mod=0,type=scope,size=6,parent=0,span=1
+sym	id=0,name="reset",addrsize=absolute,scope=0,def=0,val=0x8000,seg=0,type=lab
+sym	id=1,name="@loop",addrsize=absolute,scope=1,def=1,val=0x8002,seg=0,type=lab
+sym	id=2,name="SCREEN",addrsize=absolute,scope=0,def=1,val=0xFB00,type=equ
+"#;
+
+#[test]
+fn reads_ld65_debug_files() {
+	let info = DebugInfo::parse(LD65).unwrap();
+	assert_eq!(info.files, ["main.s", "inc, with comma.inc"]);
+	assert_eq!(
+		info.lines,
+		[line(0, 4, 0x8000), line(0, 5, 0x8002), line(1, 2, 0x8005)]
+	);
+	assert_eq!(
+		info.segments,
+		[
+			Segment {
+				name: "CODE".into(),
+				range: 0x8000..=0x800f,
+				kind: SegmentKind::Code,
+			},
+			Segment {
+				name: "BSS".into(),
+				range: 0x200..=0x2ff,
+				kind: SegmentKind::Data,
+			}
+		]
+	);
+	assert_eq!(info.labels.len(), 2);
+	assert_eq!(info.labels_at(0x8002).collect::<Vec<_>>(), ["main::@loop"]);
+	assert_eq!(info.labels_at(0x8000).collect::<Vec<_>>(), ["reset"]);
+}
+
+#[test]
+fn reports_ld65_references_to_missing_entries() {
+	let text =
+		"version\tmajor=2,minor=0\nfile\tid=0,name=\"a.s\"\nline\tid=0,file=0,line=1,span=7\n";
+	assert_eq!(
+		DebugInfo::parse(text).unwrap_err().to_string(),
+		"line 3: Unknown span 7"
+	);
+}
+
+#[test]
+fn reads_vice_label_files() {
+	let info = DebugInfo::parse("al C:0810 .start\nal 00fb00 .screen\n\nbreak 0810\n").unwrap();
+	let labels: Vec<_> = info
+		.labels
+		.iter()
+		.map(|label| (label.name.as_str(), label.address))
+		.collect();
+	assert_eq!(labels, [("start", 0x0810), ("screen", 0xfb00)]);
+	assert_eq!(
+		DebugInfo::parse("al zz .start").unwrap_err().to_string(),
+		"line 1: Invalid address zz"
+	);
+}
+
+fn labelled(labels: &[(&str, u16)]) -> DebugInfo {
+	DebugInfo {
+		labels: labels
+			.iter()
+			.map(|&(name, address)| Label {
+				name: name.into(),
+				address,
+				scope: None,
+			})
+			.collect(),
+		..DebugInfo::default()
+	}
+}
+
+#[test]
+fn symbolizes_addresses_relative_to_labels() {
+	let info = labelled(&[("main", 0x200), ("loop", 0x210), ("also_loop", 0x210)]);
+	assert_eq!(info.symbolize(0x200).as_deref(), Some("main"));
+	assert_eq!(info.symbolize(0x20f).as_deref(), Some("main+15"));
+	assert_eq!(info.symbolize(0x212).as_deref(), Some("loop+2"));
+	assert_eq!(info.symbolize(0x30f).as_deref(), Some("loop+255"));
+	assert_eq!(info.symbolize(0x310), None);
+	assert_eq!(info.symbolize(0x1ff), None);
+}
+
+#[test]
+fn evaluates_address_expressions() {
+	let info = labelled(&[("snake_body", 0x12), ("screen", 0xfb00)]);
+	assert_eq!(info.evaluate("snake_body+2"), Ok(0x14));
+	assert_eq!(info.evaluate(" screen - 1 + $10 "), Ok(0xfb0f));
+	assert_eq!(info.evaluate("0xff+%10+3"), Ok(0x104));
+	assert_eq!(info.evaluate("$fb00"), Ok(0xfb00));
+	assert_eq!(info.evaluate("$fb+2"), Ok(0xfd));
+	// Plain numbers are hex like in every other debugger entry
+	assert_eq!(info.evaluate("fb+2"), Ok(0xfd));
+	assert_eq!(info.evaluate("200"), Ok(0x200));
+	assert_eq!(info.evaluate("#200"), Ok(200));
+	assert_eq!(info.evaluate("tail"), Err("Unknown label tail".into()));
+	assert_eq!(info.evaluate("$xyz"), Err("Invalid number $xyz".into()));
+	assert_eq!(
+		info.evaluate("snake_body+"),
+		Err("Missing term in snake_body+".into())
+	);
+	assert_eq!(
+		info.evaluate("snake_body-$20"),
+		Err("Address -14 is out of range".into())
+	);
+	assert_eq!(
+		DebugInfo::parse(LD65).unwrap().evaluate("main::@loop+1"),
+		Ok(0x8003)
+	);
+}
@@ -0,0 +1,236 @@
+use nemu::asm::assemble;
+use nemu::bus::{Device, Memory, MEMORY_SIZE};
+use nemu::cpu::{Cpu, Variant};
+use nemu::disasm::{disassemble, disassemble_range, return_addresses, RunTarget};
+
+fn memory(address: u16, bytes: &[u8]) -> Memory {
+	let mut data = [0; MEMORY_SIZE];
+	data[address as usize..][..bytes.len()].copy_from_slice(bytes);
+	Memory::new(data)
+}
+
+fn text(cpu: &Cpu, bytes: &[u8]) -> String {
+	disassemble(cpu, &memory(0x1000, bytes), 0x1000).text
+}
+
+#[test]
+fn formats_addressing_modes() {
+	let cpu = Cpu::new();
+	for (bytes, expected) in [
+		(&[0xea][..], "nop"),
+		(&[0x0a], "asl"),
+		(&[0xa9, 0x0c], "lda #$0c"),
+		(&[0xa5, 0xfb], "lda $fb"),
+		(&[0xb5, 0x10], "lda $10,x"),
+		(&[0xb6, 0x10], "ldx $10,y"),
+		(&[0xad, 0x34, 0x12], "lda $1234"),
+		(&[0xbd, 0x34, 0x12], "lda $1234,x"),
+		(&[0xb9, 0x34, 0x12], "lda $1234,y"),
+		(&[0x6c, 0xfc, 0xff], "jmp ($fffc)"),
+		(&[0xa1, 0x20], "lda ($20,x)"),
+		(&[0xb1, 0x20], "lda ($20),y"),
+		(&[0xd0, 0xfe], "bne $1000"),
+		(&[0xf0, 0x10], "beq $1012"),
+		(&[0xa7, 0x10], "lax $10"),
+	] {
+		assert_eq!(text(&cpu, bytes), expected);
+	}
+}
+
+#[test]
+fn follows_the_cpu_variant() {
+	let cmos = Cpu::new_65c02();
+	assert_eq!(text(&cmos, &[0xb2, 0x20]), "lda ($20)");
+	assert_eq!(text(&cmos, &[0x7c, 0x00, 0x20]), "jmp ($2000,x)");
+	assert_eq!(text(&cmos, &[0x3f, 0x20, 0x03]), "bbr3 $20,$1006");
+	let mut strict = Cpu::with_variant(Variant::Nmos);
+	strict.set_strict(true);
+	assert_eq!(text(&strict, &[0xa7, 0x10]), ".byte $a7");
+	assert_eq!(text(&Cpu::new(), &[0x02]), ".byte $02");
+}
+
+#[test]
+fn displays_address_and_bytes() {
+	let line = disassemble(&Cpu::new(), &memory(0x0200, &[0x8d, 0x00, 0xfb]), 0x0200);
+	assert_eq!(line.to_string(), "0200  8d 00 fb  sta $fb00");
+}
+
+#[test]
+fn finds_branch_and_call_targets() {
+	let target =
+		|bytes: &[u8]| disassemble(&Cpu::new_65c02(), &memory(0x1000, bytes), 0x1000).target;
+	assert_eq!(target(&[0x20, 0x34, 0x12]), Some(0x1234));
+	assert_eq!(target(&[0x4c, 0x00, 0x02]), Some(0x0200));
+	assert_eq!(target(&[0xd0, 0xfe]), Some(0x1000));
+	assert_eq!(target(&[0x3f, 0x20, 0x03]), Some(0x1006));
+	assert_eq!(target(&[0x6c, 0xfc, 0xff]), None);
+	assert_eq!(target(&[0xad, 0x34, 0x12]), None);
+}
+
+/// Shows a NOP to debuggers but reads as BRK
+struct NopWindow;
+
+impl Device for NopWindow {
+	fn read(&mut self, _offset: u16) -> u8 {
+		0x00
+	}
+	fn write(&mut self, _offset: u16, _value: u8) {}
+	fn peek(&self, _offset: u16) -> Option<u8> {
+		Some(0xea)
+	}
+}
+
+#[test]
+fn disassembles_what_devices_show() {
+	let mut mem = memory(0x1000, &[0x20, 0x00, 0x20]);
+	mem.map_device(0x1001..=0x1001, NopWindow);
+	assert_eq!(disassemble(&Cpu::new(), &mem, 0x1000).text, "jsr $20ea");
+	assert_eq!(disassemble(&Cpu::new(), &mem, 0x1001).text, "nop");
+}
+
+#[test]
+fn finds_return_addresses_on_the_stack() {
+	let mut data = [0; MEMORY_SIZE];
+	// jsr at $0200 and $0300, with a byte pushed by pha in between
+	data[0x0200] = 0x20;
+	data[0x0300] = 0x20;
+	data[0x01fb..=0x01ff].copy_from_slice(&[0x02, 0x03, 0x42, 0x02, 0x02]);
+	let mem = Memory::new(data);
+	assert_eq!(return_addresses(&mem, 0xfa), [0x0303, 0x0203]);
+	assert_eq!(return_addresses(&mem, 0xfd), [0x0203]);
+	assert!(return_addresses(&mem, 0xff).is_empty());
+}
+
+/// Runs until `target` is reached, returning the program counter there
+fn run_until(cpu: &mut Cpu, mem: &mut Memory, target: RunTarget) -> u16 {
+	for _ in 0..1000 {
+		let opcode = mem.peek(cpu.state().program_counter);
+		cpu.execute(mem).unwrap();
+		if target.reached(opcode, &cpu.state()) {
+			return cpu.state().program_counter;
+		}
+	}
+	panic!("{target:?} not reached");
+}
+
+/// Runs until the program counter reaches `address`
+fn run_to(cpu: &mut Cpu, mem: &mut Memory, address: u16) {
+	run_until(cpu, mem, RunTarget::Address(address));
+}
+
+#[test]
+fn steps_over_and_out_of_nested_calls() {
+	let mut mem = Memory::new(
+		assemble(
+			"
+	jsr outer    ; $00
+	brk          ; $03
+outer:
+	jsr inner    ; $04
+	rts          ; $07
+inner:
+	nop          ; $08
+	rts          ; $09
+",
+		)
+		.unwrap()
+		.image,
+	);
+	let mut cpu = Cpu::new();
+	let step_over = |cpu: &Cpu, mem: &Memory| RunTarget::step_over(mem, &cpu.state()).unwrap();
+	let target = step_over(&cpu, &mem);
+	assert_eq!(run_until(&mut cpu, &mut mem, target), 0x03);
+
+	let mut cpu = Cpu::new();
+	run_to(&mut cpu, &mut mem, 0x04);
+	let target = step_over(&cpu, &mem);
+	assert_eq!(run_until(&mut cpu, &mut mem, target), 0x07);
+	// Anything but a call is a single step
+	assert_eq!(RunTarget::step_over(&mem, &cpu.state()), None);
+
+	let mut cpu = Cpu::new();
+	run_to(&mut cpu, &mut mem, 0x08);
+	let target = RunTarget::step_out(&cpu.state());
+	assert_eq!(run_until(&mut cpu, &mut mem, target), 0x07);
+	let target = RunTarget::step_out(&cpu.state());
+	assert_eq!(run_until(&mut cpu, &mut mem, target), 0x03);
+}
+
+#[test]
+fn steps_over_recursive_calls() {
+	let mut mem = Memory::new(
+		assemble(
+			"
+	ldx #3       ; $00
+	jsr count    ; $02
+	brk          ; $05
+count:
+	dex          ; $06
+	beq done     ; $07
+	jsr count    ; $09
+done:
+	rts          ; $0c
+",
+		)
+		.unwrap()
+		.image,
+	);
+	let mut cpu = Cpu::new();
+	run_to(&mut cpu, &mut mem, 0x09);
+	let state = cpu.state();
+	let target = RunTarget::step_over(&mem, &state).unwrap();
+	// The deeper calls pass $0c with the stack pointer lower
+	assert_eq!(run_until(&mut cpu, &mut mem, target), 0x0c);
+	assert_eq!(cpu.state().stack_pointer, state.stack_pointer);
+	assert_eq!(cpu.state().x, 0);
+}
+
+#[test]
+fn steps_out_of_interrupt_handlers() {
+	let mut mem = Memory::new(
+		assemble(
+			"
+	cli          ; $00
+	nop          ; $01
+	nop          ; $02
+	brk          ; $03
+handler:
+	jsr save     ; $04
+	rti          ; $07
+save:
+	pha          ; $08
+	pla          ; $09
+	rts          ; $0a
+	.org $fffe
+	.word handler
+",
+		)
+		.unwrap()
+		.image,
+	);
+	let mut cpu = Cpu::new();
+	run_to(&mut cpu, &mut mem, 0x01);
+	cpu.set_irq(true);
+	cpu.execute(&mut mem).unwrap();
+	cpu.set_irq(false);
+	assert_eq!(cpu.state().program_counter, 0x04);
+	// The RTS of the call inside the handler doesn't leave it
+	let target = RunTarget::step_out(&cpu.state());
+	assert_eq!(run_until(&mut cpu, &mut mem, target), 0x01);
+}
+
+#[test]
+fn snake_round_trips_through_the_assembler() {
+	let image = include_bytes!("../a.out");
+	let mem = memory(0, image);
+	let cpu = Cpu::new();
+	let mut source = String::new();
+	for range in [0x0000..=0x0002, 0x0200..=image.len() as u16 - 1] {
+		source += &format!(".org ${:04x}\n", range.start());
+		for line in disassemble_range(&cpu, &mem, range) {
+			source += &format!("\t{}\n", line.text);
+		}
+	}
+	let assembly = assemble(&source).unwrap();
+	assert_eq!(assembly.image[..image.len()], image[..]);
+}
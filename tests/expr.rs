@@ -0,0 +1,116 @@
+use nemu::bus::{Device, Memory, MEMORY_SIZE};
+use nemu::cpu::CpuState;
+use nemu::debug_info::{DebugInfo, Label};
+use nemu::expr::{BinaryOp, Expr, Register};
+
+fn state() -> CpuState {
+	CpuState {
+		program_counter: 0x0200,
+		x: 4,
+		y: 0,
+		a: 0x10,
+		status: 0b0010_0001,
+		stack_pointer: 0xfd,
+		cycles: 0,
+	}
+}
+
+fn memory() -> Memory {
+	let mut data = [0; MEMORY_SIZE];
+	data[0xfb] = 7;
+	data[0x12] = 0xfb;
+	Memory::new(data)
+}
+
+fn debug_info() -> DebugInfo {
+	DebugInfo {
+		labels: vec![Label {
+			name: "snake_body".into(),
+			address: 0x10,
+			scope: None,
+		}],
+		..DebugInfo::default()
+	}
+}
+
+fn evaluate(text: &str) -> i64 {
+	Expr::parse(text, &debug_info())
+		.unwrap()
+		.evaluate(&state(), &memory())
+}
+
+#[test]
+fn reads_registers_and_memory() {
+	assert_eq!(evaluate("A"), 0x10);
+	assert_eq!(evaluate("x + y + sp"), 4 + 0xfd);
+	assert_eq!(evaluate("PC"), 0x0200);
+	assert_eq!(evaluate("P & %1"), 1);
+	assert_eq!(evaluate("[$fb]"), 7);
+	assert_eq!(evaluate("[[snake_body+2]]"), 7);
+	assert_eq!(evaluate("0x10 - 0x20"), -16);
+}
+
+/// Counts its reads, which evaluating must not do
+struct Counter(u8);
+
+impl Device for Counter {
+	fn read(&mut self, _offset: u16) -> u8 {
+		self.0 += 1;
+		self.0
+	}
+	fn write(&mut self, _offset: u16, _value: u8) {}
+	fn peek(&self, _offset: u16) -> Option<u8> {
+		Some(self.0)
+	}
+}
+
+#[test]
+fn peeks_at_devices() {
+	let mut mem = memory();
+	mem.map_device(0xfb..=0xfb, Counter(5));
+	let expr = Expr::parse("[$fb] == 5", &debug_info()).unwrap();
+	assert!(expr.is_true(&state(), &mem));
+	assert!(expr.is_true(&state(), &mem));
+}
+
+#[test]
+fn evaluates_conditions() {
+	assert_eq!(evaluate("A == $10 && X > 3"), 1);
+	assert_eq!(evaluate("A == $10 && X > 4"), 0);
+	assert_eq!(evaluate("A != #16 || [$fb] != 0"), 1);
+	assert_eq!(evaluate("A == 10 && [fb] == 7"), 1);
+	assert_eq!(evaluate("A == $a"), 0);
+	assert_eq!(evaluate("!(X >= 4)"), 0);
+	assert_eq!(evaluate("-X < 0"), 1);
+	// Bitwise operators bind tighter than comparisons
+	assert_eq!(evaluate("P & 1 == 1"), 1);
+	assert_eq!(evaluate("X <= 4 == 1"), 1);
+}
+
+#[test]
+fn builds_a_tree_by_precedence() {
+	assert_eq!(
+		Expr::parse("a+1 == 2", &DebugInfo::default()),
+		Ok(Expr::Binary(
+			BinaryOp::Equal,
+			Box::new(Expr::Binary(
+				BinaryOp::Add,
+				Box::new(Expr::Register(Register::A)),
+				Box::new(Expr::Value(1))
+			)),
+			Box::new(Expr::Value(2))
+		))
+	);
+}
+
+#[test]
+fn reports_parse_errors() {
+	let error = |text| Expr::parse(text, &debug_info()).unwrap_err();
+	assert_eq!(error("A =="), "Unexpected end of expression");
+	assert_eq!(error("[$fb"), "Expected ]");
+	assert_eq!(error("(A == 1]"), "Expected ), found ]");
+	assert_eq!(error("A 1"), "Unexpected 1");
+	assert_eq!(error("tail == 1"), "Unknown label tail");
+	assert_eq!(error("$fg"), "Invalid number $fg");
+	assert_eq!(error("A = 1"), "Unexpected character =");
+}
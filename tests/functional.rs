@@ -0,0 +1,138 @@
+//! Runs Klaus Dormann's 6502 functional test, see `tests/roms/README.md` for
+//! how to obtain the image. It isn't checked in, so the test is skipped with a
+//! notice when the image is missing, except on CI where it fails.
+//!
+//! The same author's decimal test is ported to Rust instead of being run from
+//! an image, checking ADC and SBC in decimal mode against Bruce Clark's
+//! reference algorithm for every operand and carry.
+
+use std::fs;
+use std::path::PathBuf;
+
+use nemu::bus::{Memory, MEMORY_SIZE};
+use nemu::cpu::{Cpu, StatusFlags};
+use nemu::headless::{self, StopConditions, StopReason};
+
+/// Entry point of `6502_functional_test.bin`
+const FUNCTIONAL_TEST_START: u16 = 0x0400;
+/// Address of the `jmp *` reached once every functional test has passed
+const FUNCTIONAL_TEST_SUCCESS: u16 = 0x3469;
+/// Upper bound so a broken CPU can't hang the test run
+const MAX_CYCLES: u64 = 200_000_000;
+const ADC_IMMEDIATE: u8 = 0x69;
+const SBC_IMMEDIATE: u8 = 0xe9;
+
+/// Loads a 64 KiB image from `tests/roms`, `None` when it is missing outside
+/// of CI
+fn load_rom(name: &str) -> Option<Memory> {
+	let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "roms", name]
+		.iter()
+		.collect();
+	let bytes = match fs::read(&path) {
+		Ok(bytes) => bytes,
+		Err(err) if std::env::var_os("CI").is_some() => panic!(
+			"{}: {err}, see tests/roms/README.md for how to get it",
+			path.display()
+		),
+		Err(err) => {
+			eprintln!(
+				"skipping, {}: {err}, see tests/roms/README.md for how to get it",
+				path.display()
+			);
+			return None;
+		}
+	};
+	let mut data = [0; MEMORY_SIZE];
+	data[..bytes.len()].copy_from_slice(&bytes);
+	Some(Memory::new(data))
+}
+
+#[test]
+fn functional_test() {
+	let Some(mut mem) = load_rom("6502_functional_test.bin") else {
+		return;
+	};
+	let mut cpu = Cpu::new();
+	cpu.set_program_counter(FUNCTIONAL_TEST_START);
+	let conditions = StopConditions {
+		max_cycles: Some(MAX_CYCLES),
+		on_trap: true,
+		..Default::default()
+	};
+	let (reason, _) = headless::run(&mut cpu, &mut mem, &conditions);
+	assert_eq!(reason, StopReason::Trap(FUNCTIONAL_TEST_SUCCESS));
+}
+
+/// Accumulator and carry of a decimal mode ADC on the NMOS 6502, invalid BCD
+/// operands included
+fn reference_adc(a: u8, operand: u8, carry: bool) -> (u8, bool) {
+	let (a, operand) = (a as i32, operand as i32);
+	let mut low = (a & 0x0f) + (operand & 0x0f) + carry as i32;
+	if low >= 0x0a {
+		low = ((low + 0x06) & 0x0f) + 0x10;
+	}
+	let mut sum = (a & 0xf0) + (operand & 0xf0) + low;
+	if sum >= 0xa0 {
+		sum += 0x60;
+	}
+	(sum as u8, sum >= 0x100)
+}
+
+/// Accumulator and carry of a decimal mode SBC, the carry is the same as in
+/// binary mode
+fn reference_sbc(a: u8, operand: u8, carry: bool) -> (u8, bool) {
+	let (a, operand) = (a as i32, operand as i32);
+	let mut low = (a & 0x0f) - (operand & 0x0f) + carry as i32 - 1;
+	if low < 0 {
+		low = ((low - 0x06) & 0x0f) - 0x10;
+	}
+	let mut difference = (a & 0xf0) - (operand & 0xf0) + low;
+	if difference < 0 {
+		difference -= 0x60;
+	}
+	(difference as u8, a + carry as i32 > operand)
+}
+
+#[test]
+fn decimal_test() {
+	let mut mem = Memory::new([0; MEMORY_SIZE]);
+	let mut failures = Vec::new();
+	for (opcode, reference) in [
+		(
+			ADC_IMMEDIATE,
+			reference_adc as fn(u8, u8, bool) -> (u8, bool),
+		),
+		(SBC_IMMEDIATE, reference_sbc),
+	] {
+		mem.data[0x0200] = opcode;
+		for operand in 0..=u8::MAX {
+			mem.data[0x0201] = operand;
+			for a in 0..=u8::MAX {
+				for carry in [false, true] {
+					let mut cpu = Cpu::new();
+					let mut state = cpu.state();
+					state.program_counter = 0x0200;
+					state.a = a;
+					state.set_flag(StatusFlags::DecimalMode, true);
+					state.set_flag(StatusFlags::Carry, carry);
+					cpu.set_state(state);
+					cpu.execute(&mut mem).unwrap();
+					let actual = (cpu.state().a, cpu.get_flag(StatusFlags::Carry));
+					let expected = reference(a, operand, carry);
+					if actual != expected {
+						failures.push(format!(
+							"{opcode:02x} {operand:02x} with A {a:02x} and carry {carry}: \
+							 expected {expected:02x?}, got {actual:02x?}"
+						));
+					}
+				}
+			}
+		}
+	}
+	assert!(
+		failures.is_empty(),
+		"{} failures, first ones:\n{}",
+		failures.len(),
+		failures[..failures.len().min(20)].join("\n")
+	);
+}
@@ -0,0 +1,203 @@
+use nemu::asm::assemble;
+use nemu::bus::{Bus, Device, Memory};
+use nemu::cpu::{Cpu, CpuError};
+use nemu::headless::{
+	self, StopConditions, StopReason, EXIT_FAULT, EXIT_LIMIT_REACHED, EXIT_LOAD_ERROR,
+	EXIT_TRAPPED, EXIT_WRITE_MAX,
+};
+
+/// Counts up at $10 and reports it at $20 forever
+const COUNTER: &str = "
+loop:
+	inc $10      ; $00
+	lda $10      ; $02
+	sta $20      ; $04
+	jmp loop     ; $06
+";
+
+fn run(source: &str, conditions: &StopConditions) -> (StopReason, u64, Cpu, Memory) {
+	let mut mem = Memory::new(assemble(source).unwrap().image);
+	let mut cpu = Cpu::new();
+	let (reason, instructions) = headless::run(&mut cpu, &mut mem, conditions);
+	(reason, instructions, cpu, mem)
+}
+
+#[test]
+fn stops_on_limits() {
+	let conditions = StopConditions {
+		max_instructions: Some(10),
+		..Default::default()
+	};
+	let (reason, instructions, _, mem) = run(COUNTER, &conditions);
+	assert_eq!((reason, instructions), (StopReason::InstructionLimit, 10));
+	assert_eq!(mem.data[0x10], 3);
+	let conditions = StopConditions {
+		max_cycles: Some(20),
+		..Default::default()
+	};
+	let (reason, _, cpu, _) = run(COUNTER, &conditions);
+	assert_eq!(reason, StopReason::CycleLimit);
+	assert!(cpu.state().cycles >= 20);
+}
+
+#[test]
+fn stops_at_addresses() {
+	let conditions = StopConditions {
+		addresses: vec![0x30, 0x04],
+		..Default::default()
+	};
+	let (reason, instructions, _, _) = run(COUNTER, &conditions);
+	assert_eq!((reason, instructions), (StopReason::Address(0x04), 2));
+}
+
+#[test]
+fn stops_before_brk() {
+	let conditions = StopConditions {
+		on_brk: true,
+		..Default::default()
+	};
+	let (reason, instructions, cpu, _) = run("\tnop\n\tnop\n\tbrk", &conditions);
+	assert_eq!((reason, instructions), (StopReason::Break(0x02), 2));
+	assert_eq!(cpu.state().program_counter, 0x02);
+}
+
+/// Reads as BRK while the RAM underneath holds a NOP
+struct BrkRom;
+
+impl Device for BrkRom {
+	fn read(&mut self, _offset: u16) -> u8 {
+		0x00
+	}
+	fn write(&mut self, _offset: u16, _value: u8) {}
+	fn peek(&self, _offset: u16) -> Option<u8> {
+		Some(0x00)
+	}
+}
+
+#[test]
+fn brk_is_looked_up_through_devices() {
+	let mut mem = Memory::new(assemble("\tnop\n\tnop").unwrap().image);
+	mem.map_device(0x01..=0x01, BrkRom);
+	let conditions = StopConditions {
+		on_brk: true,
+		..Default::default()
+	};
+	let (reason, _) = headless::run(&mut Cpu::new(), &mut mem, &conditions);
+	assert_eq!(reason, StopReason::Break(0x01));
+}
+
+#[test]
+fn stops_on_write() {
+	let conditions = StopConditions {
+		on_write: Some(0x20),
+		..Default::default()
+	};
+	let (reason, instructions, _, _) = run(COUNTER, &conditions);
+	assert_eq!((reason, instructions), (StopReason::Write(0x20, 1), 3));
+}
+
+#[test]
+fn write_stop_leaves_memory_alone() {
+	// Reads $20 before writing to it, then runs on past the write
+	let source = "
+	lda $20
+	sta $21
+	lda #7
+	sta $20
+	nop
+	.org $20
+	.byte $42
+";
+	let conditions = StopConditions {
+		on_write: Some(0x20),
+		..Default::default()
+	};
+	let (reason, instructions, _, mut mem) = run(source, &conditions);
+	assert_eq!((reason, instructions), (StopReason::Write(0x20, 7), 4));
+	assert_eq!(mem.data[0x21], 0x42);
+	assert!(headless::dump_memory(&mem, 0x20..=0x21).contains("07 42"));
+	assert!(mem.watches.is_empty());
+	// Nothing is left mapped over the address
+	mem.data[0x20] = 0x09;
+	assert_eq!(mem.read_byte(0x20), 0x09);
+}
+
+#[test]
+fn stops_on_trap() {
+	let conditions = StopConditions {
+		on_trap: true,
+		..Default::default()
+	};
+	let (reason, instructions, _, _) = run("\tnop\nhere:\n\tjmp here", &conditions);
+	assert_eq!((reason, instructions), (StopReason::Trap(0x01), 2));
+}
+
+#[test]
+fn waiting_is_not_a_trap() {
+	// WAI keeps the program counter on itself until an interrupt comes
+	let mut mem = Memory::new(assemble("\t.byte $cb").unwrap().image);
+	let mut cpu = Cpu::new_65c02();
+	let conditions = StopConditions {
+		max_instructions: Some(5),
+		on_trap: true,
+		..Default::default()
+	};
+	let (reason, _) = headless::run(&mut cpu, &mut mem, &conditions);
+	assert_eq!(reason, StopReason::InstructionLimit);
+}
+
+#[test]
+fn stops_on_fault() {
+	let (reason, instructions, _, _) = run("\tnop\n\t.byte $02", &StopConditions::default());
+	assert_eq!(
+		(reason, instructions),
+		(
+			StopReason::Fault(CpuError::Jam {
+				address: 0x01,
+				opcode: 0x02
+			}),
+			1
+		)
+	);
+}
+
+#[test]
+fn exit_codes_are_disjoint() {
+	assert_eq!(StopReason::Address(0).exit_code(), 0);
+	assert_eq!(StopReason::Break(0).exit_code(), 0);
+	assert_eq!(StopReason::InstructionLimit.exit_code(), EXIT_LIMIT_REACHED);
+	assert_eq!(StopReason::CycleLimit.exit_code(), EXIT_LIMIT_REACHED);
+	assert_eq!(StopReason::Trap(0).exit_code(), EXIT_TRAPPED);
+	assert_eq!(
+		StopReason::Fault(CpuError::Stopped { address: 0 }).exit_code(),
+		EXIT_FAULT
+	);
+	let runner = [
+		EXIT_LOAD_ERROR,
+		EXIT_LIMIT_REACHED,
+		EXIT_TRAPPED,
+		EXIT_FAULT,
+	];
+	for value in 0..=u8::MAX {
+		let code = StopReason::Write(0, value).exit_code();
+		assert!(!runner.contains(&code), "{value} exits with {code}");
+		assert_eq!(code, (value as i32).min(EXIT_WRITE_MAX));
+	}
+	assert!(runner.iter().all(|code| (0..=255).contains(code)));
+}
+
+#[test]
+fn dumps_memory_rows() {
+	let mem = Memory::new(assemble("\t.byte 1, 2, 3").unwrap().image);
+	assert_eq!(
+		headless::dump_memory(&mem, 0x00..=0x12),
+		"0000: 01 02 03 00 00 00 00 00 00 00 00 00 00 00 00 00\n0010: 00 00 00\n"
+	);
+}
+
+#[test]
+fn dumps_what_devices_show() {
+	let mut mem = Memory::new(assemble("\tnop\n\tnop").unwrap().image);
+	mem.map_device(0x01..=0x01, BrkRom);
+	assert_eq!(headless::dump_memory(&mem, 0x00..=0x02), "0000: ea 00 00\n");
+}
@@ -0,0 +1,136 @@
+use nemu::asm::assemble;
+use nemu::bus::Memory;
+use nemu::cpu::{Cpu, CpuState};
+use nemu::history::History;
+
+/// Counts up at $10 forever, writing the count to $11 too
+const COUNTER: &str = "
+loop:
+	inc $10
+	lda $10
+	sta $11
+	jmp loop
+";
+
+fn load(source: &str) -> (Cpu, Memory) {
+	(Cpu::new(), Memory::new(assemble(source).unwrap().image))
+}
+
+/// Runs `count` instructions, returning the CPU and memory before each one
+/// and after the last
+fn record(
+	history: &mut History,
+	cpu: &mut Cpu,
+	mem: &mut Memory,
+	count: usize,
+) -> Vec<(CpuState, Vec<u8>)> {
+	let mut states = vec![(cpu.state(), mem.data.clone())];
+	for _ in 0..count {
+		history.execute(cpu, mem).unwrap();
+		states.push((cpu.state(), mem.data.clone()));
+	}
+	states
+}
+
+#[test]
+fn steps_back_and_forward() {
+	let (mut cpu, mut mem) = load(COUNTER);
+	let mut history = History::new();
+	let states = record(&mut history, &mut cpu, &mut mem, 20);
+	assert_eq!((history.position(), history.len()), (20, 20));
+	for position in (0..20).rev() {
+		assert!(history.step_back(&mut cpu, &mut mem));
+		assert_eq!(history.position(), position);
+		assert_eq!((cpu.state(), mem.data.clone()), states[position]);
+	}
+	assert!(!history.step_back(&mut cpu, &mut mem));
+	for state in &states[1..] {
+		assert!(history.step_forward(&mut cpu, &mut mem));
+		assert_eq!(&(cpu.state(), mem.data.clone()), state);
+	}
+	assert!(!history.step_forward(&mut cpu, &mut mem));
+}
+
+#[test]
+fn seeks_through_snapshots() {
+	let (mut cpu, mut mem) = load(COUNTER);
+	let mut history = History::with_capacity(1000, 7);
+	let states = record(&mut history, &mut cpu, &mut mem, 100);
+	for position in [3, 50, 0, 99, 14, 100, 64] {
+		history.seek(position, &mut cpu, &mut mem);
+		assert_eq!(history.position(), position);
+		assert_eq!((cpu.state(), mem.data.clone()), states[position]);
+	}
+}
+
+#[test]
+fn keeps_last_instructions_up_to_capacity() {
+	let (mut cpu, mut mem) = load(COUNTER);
+	let mut history = History::with_capacity(10, 4);
+	let states = record(&mut history, &mut cpu, &mut mem, 25);
+	assert_eq!((history.start(), history.len()), (15, 10));
+	history.seek(0, &mut cpu, &mut mem);
+	assert_eq!((cpu.state(), mem.data.clone()), states[15]);
+	history.seek(10, &mut cpu, &mut mem);
+	assert_eq!((cpu.state(), mem.data.clone()), states[25]);
+}
+
+#[test]
+fn executing_discards_later_history() {
+	let (mut cpu, mut mem) = load(COUNTER);
+	let mut history = History::with_capacity(100, 3);
+	let states = record(&mut history, &mut cpu, &mut mem, 12);
+	history.seek(5, &mut cpu, &mut mem);
+	let mut state = cpu.state();
+	state.a = 0x80;
+	cpu.set_state(state);
+	history.execute(&mut cpu, &mut mem).unwrap();
+	assert_eq!((history.position(), history.len()), (6, 6));
+	history.seek(0, &mut cpu, &mut mem);
+	assert_eq!((cpu.state(), mem.data.clone()), states[0]);
+	history.seek(5, &mut cpu, &mut mem);
+	assert_eq!(cpu.state(), state);
+	assert_eq!(mem.data, states[5].1);
+}
+
+#[test]
+fn debugger_writes_are_undone_with_the_instruction_before() {
+	let (mut cpu, mut mem) = load(COUNTER);
+	let mut history = History::with_capacity(100, 4);
+	let states = record(&mut history, &mut cpu, &mut mem, 6);
+	// $10 is counted up by the next INC, $30 is never written by the program
+	history.write(&cpu, &mut mem, 0x10, 0x40);
+	history.write(&cpu, &mut mem, 0x30, 0x55);
+	let edited = mem.data.clone();
+	record(&mut history, &mut cpu, &mut mem, 6);
+	assert_eq!(mem.data[0x10], 0x41);
+	history.seek(6, &mut cpu, &mut mem);
+	assert_eq!(mem.data, edited);
+	history.step_back(&mut cpu, &mut mem);
+	assert_eq!((cpu.state(), mem.data.clone()), states[5]);
+	history.seek(0, &mut cpu, &mut mem);
+	assert_eq!((cpu.state(), mem.data.clone()), states[0]);
+	history.seek(12, &mut cpu, &mut mem);
+	assert_eq!(mem.data[0x10], 0x41);
+	assert_eq!(mem.data[0x30], 0x55);
+}
+
+#[test]
+fn debugger_register_edits_are_undone_with_the_instruction_before() {
+	let (mut cpu, mut mem) = load(COUNTER);
+	let mut history = History::with_capacity(100, 4);
+	let states = record(&mut history, &mut cpu, &mut mem, 6);
+	history.seek(4, &mut cpu, &mut mem);
+	let mut edited = cpu.state();
+	edited.a = 0x80;
+	history.set_state(&mut cpu, edited);
+	assert_eq!(cpu.state(), edited);
+	// Later history no longer follows from the edited registers
+	assert_eq!((history.position(), history.len()), (4, 4));
+	assert!(!history.step_forward(&mut cpu, &mut mem));
+	history.step_back(&mut cpu, &mut mem);
+	assert_eq!((cpu.state(), mem.data.clone()), states[3]);
+	history.step_forward(&mut cpu, &mut mem);
+	assert_eq!(cpu.state(), edited);
+	assert_eq!(mem.data, states[4].1);
+}
@@ -0,0 +1,174 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use nemu::asm::assemble;
+use nemu::bus::{Device, Memory};
+use nemu::cpu::{Cpu, StatusFlags, StepOutcome};
+use nemu::headless::{self, StopConditions, StopReason};
+
+/// Interrupt lines held by the test
+#[derive(Default)]
+struct Lines {
+	irq: bool,
+	nmi: bool,
+}
+
+impl Device for Lines {
+	fn read(&mut self, _offset: u16) -> u8 {
+		0
+	}
+	fn write(&mut self, _offset: u16, _value: u8) {}
+	fn irq(&self) -> bool {
+		self.irq
+	}
+	fn nmi(&self) -> bool {
+		self.nmi
+	}
+}
+
+/// A main loop at 0x00 counting in X, handlers at 0x40 counting in Y and
+/// every vector pointing at them
+const PROGRAM: &str = "
+main:
+	inx
+	jmp main
+.org $40
+handler:
+	iny
+	rti
+.org $fffa
+	.word handler, main, handler
+";
+
+fn machine() -> (Cpu, Memory, Rc<RefCell<Lines>>) {
+	let mut mem = Memory::new(assemble(PROGRAM).unwrap().image);
+	let lines = Rc::new(RefCell::new(Lines::default()));
+	mem.map_device(0xff00..=0xff00, lines.clone());
+	let mut cpu = Cpu::new();
+	cpu.reset(&mut mem);
+	(cpu, mem, lines)
+}
+
+fn step(cpu: &mut Cpu, mem: &mut Memory) -> StepOutcome {
+	cpu.poll_interrupts(mem);
+	cpu.execute(mem).unwrap()
+}
+
+#[test]
+fn reset_loads_the_vector_and_masks_interrupts() {
+	let (mut cpu, mut mem, _) = machine();
+	let mut state = cpu.state();
+	state.program_counter = 0x1234;
+	state.stack_pointer = 0xff;
+	state.set_flag(StatusFlags::InterruptDisable, false);
+	cpu.set_state(state);
+	cpu.reset(&mut mem);
+	let state = cpu.state();
+	assert_eq!(state.program_counter, 0x0000);
+	assert_eq!(state.stack_pointer, 0xfc);
+	assert!(state.get_flag(StatusFlags::InterruptDisable));
+}
+
+#[test]
+fn nmi_fires_once_per_edge() {
+	let (mut cpu, mut mem, lines) = machine();
+	lines.borrow_mut().nmi = true;
+	assert!(matches!(
+		step(&mut cpu, &mut mem),
+		StepOutcome::Interrupted(7)
+	));
+	assert_eq!(cpu.state().program_counter, 0x40);
+	// Held active, the handler and main loop run undisturbed
+	for _ in 0..10 {
+		assert!(matches!(step(&mut cpu, &mut mem), StepOutcome::Executed(_)));
+	}
+	assert_eq!(cpu.state().y, 1);
+	lines.borrow_mut().nmi = false;
+	step(&mut cpu, &mut mem);
+	lines.borrow_mut().nmi = true;
+	assert!(matches!(
+		step(&mut cpu, &mut mem),
+		StepOutcome::Interrupted(_)
+	));
+}
+
+#[test]
+fn nmi_ignores_the_interrupt_disable_flag() {
+	let (mut cpu, mut mem, lines) = machine();
+	assert!(cpu.get_flag(StatusFlags::InterruptDisable));
+	lines.borrow_mut().nmi = true;
+	assert!(matches!(
+		step(&mut cpu, &mut mem),
+		StepOutcome::Interrupted(_)
+	));
+}
+
+#[test]
+fn irq_is_masked_by_interrupt_disable() {
+	let (mut cpu, mut mem, lines) = machine();
+	lines.borrow_mut().irq = true;
+	for _ in 0..10 {
+		assert!(matches!(step(&mut cpu, &mut mem), StepOutcome::Executed(_)));
+	}
+	assert_eq!(cpu.state().y, 0);
+	let mut state = cpu.state();
+	state.set_flag(StatusFlags::InterruptDisable, false);
+	cpu.set_state(state);
+	assert!(matches!(
+		step(&mut cpu, &mut mem),
+		StepOutcome::Interrupted(_)
+	));
+	assert_eq!(cpu.state().program_counter, 0x40);
+	// Still held after RTI clears the flag again, so it is serviced again
+	step(&mut cpu, &mut mem);
+	step(&mut cpu, &mut mem);
+	assert_eq!(cpu.state().y, 1);
+	assert!(matches!(
+		step(&mut cpu, &mut mem),
+		StepOutcome::Interrupted(_)
+	));
+	lines.borrow_mut().irq = false;
+	step(&mut cpu, &mut mem);
+	step(&mut cpu, &mut mem);
+	assert!(matches!(step(&mut cpu, &mut mem), StepOutcome::Executed(_)));
+	assert_eq!(cpu.state().y, 2);
+}
+
+#[test]
+fn rti_restores_status_and_program_counter() {
+	let (mut cpu, mut mem, lines) = machine();
+	step(&mut cpu, &mut mem);
+	let mut state = cpu.state();
+	state.status = 0;
+	state.set_flag(StatusFlags::Carry, true);
+	state.set_flag(StatusFlags::Negative, true);
+	cpu.set_state(state);
+	let before = cpu.state();
+	lines.borrow_mut().nmi = true;
+	step(&mut cpu, &mut mem);
+	// Pushed with B clear and the unused bit set
+	assert_eq!(
+		mem.data[0x100 | (before.stack_pointer as usize - 2)],
+		0b1010_0001
+	);
+	assert!(cpu.get_flag(StatusFlags::InterruptDisable));
+	step(&mut cpu, &mut mem);
+	assert!(!cpu.get_flag(StatusFlags::Negative));
+	step(&mut cpu, &mut mem);
+	let after = cpu.state();
+	assert_eq!(after.program_counter, before.program_counter);
+	assert_eq!(after.status, before.status);
+	assert_eq!(after.stack_pointer, before.stack_pointer);
+}
+
+#[test]
+fn headless_runs_service_device_interrupts() {
+	let (mut cpu, mut mem, lines) = machine();
+	lines.borrow_mut().nmi = true;
+	let conditions = StopConditions {
+		addresses: vec![0x40],
+		..Default::default()
+	};
+	let (reason, instructions) = headless::run(&mut cpu, &mut mem, &conditions);
+	assert_eq!((reason, instructions), (StopReason::Address(0x40), 1));
+}
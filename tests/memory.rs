@@ -0,0 +1,78 @@
+use nemu::bus::{Access, Bus, Device, Memory, Watch, MEMORY_SIZE};
+
+/// Counts its reads, which peeking must not do
+#[derive(Default)]
+struct Counter(u8);
+
+impl Device for Counter {
+	fn read(&mut self, _offset: u16) -> u8 {
+		self.0 += 1;
+		self.0
+	}
+	fn write(&mut self, _offset: u16, _value: u8) {}
+	fn peek(&self, _offset: u16) -> Option<u8> {
+		Some(self.0)
+	}
+}
+
+/// Reads have side effects and can't be peeked at
+struct Port;
+
+impl Device for Port {
+	fn read(&mut self, _offset: u16) -> u8 {
+		0xee
+	}
+	fn write(&mut self, _offset: u16, _value: u8) {}
+}
+
+#[test]
+fn peeks_without_side_effects() {
+	let mut data = [0; MEMORY_SIZE];
+	data[0x10] = 0x42;
+	data[0x20] = 0x24;
+	let mut mem = Memory::new(data);
+	mem.map_device(0x10..=0x10, Counter::default());
+	mem.map_device(0x20..=0x20, Port);
+	assert_eq!(mem.read_byte(0x10), 1);
+	assert_eq!(mem.peek(0x10), 1);
+	assert_eq!(mem.peek(0x10), 1);
+	assert_eq!(mem.read_byte(0x10), 2);
+	assert_eq!(mem.peek(0x20), 0x24);
+	assert_eq!(mem.read_byte(0x20), 0xee);
+}
+
+#[test]
+fn finds_patterns() {
+	let mut data = [0; MEMORY_SIZE];
+	data[0x200..0x205].copy_from_slice(&[0xa9, 0x10, 0x85, 0x20, 0xea]);
+	data[0x300..0x304].copy_from_slice(&[0xa9, 0x33, 0x85, 0x21]);
+	data[0xfffe..].copy_from_slice(&[0xa9, 0x10]);
+	let mem = Memory::new(data);
+	let lda_sta = [Some(0xa9), None, Some(0x85)];
+	assert_eq!(mem.find(&lda_sta, 0), Some(0x200));
+	assert_eq!(mem.find(&lda_sta, 0x201), Some(0x300));
+	// Wraps around to the start
+	assert_eq!(mem.find(&lda_sta, 0x301), Some(0x200));
+	assert_eq!(mem.find(&[Some(0xa9), Some(0x10)], 0x201), Some(0xfffe));
+	// Matches don't wrap past the end of memory
+	assert_eq!(mem.find(&[Some(0x10), None], 0xffff), Some(0x201));
+	assert_eq!(
+		mem.find(&[Some(0xa9), Some(0x10), Some(0x85), Some(0x21)], 0),
+		None
+	);
+}
+
+#[test]
+fn write_watches_dont_read_devices() {
+	let mut mem = Memory::new([0; MEMORY_SIZE]);
+	mem.map_device(0x10..=0x10, Counter::default());
+	mem.watches.push(Watch {
+		range: 0x10..=0x10,
+		access: Access::Change,
+		value: None,
+	});
+	mem.write_byte(0x10, 5);
+	assert_eq!(mem.peek(0x10), 0);
+	let hit = mem.take_watch_hit().unwrap();
+	assert_eq!((hit.old, hit.new), (0, 5));
+}
@@ -0,0 +1,78 @@
+use nemu::asm::assemble;
+use nemu::bus::{Memory, MEMORY_SIZE};
+use nemu::cpu::Cpu;
+use nemu::debug_info::{DebugInfo, Label};
+use nemu::expr::Expr;
+use nemu::number::{self, Radix};
+
+#[test]
+fn parses_every_prefix() {
+	for radix in [Radix::Decimal, Radix::Hex] {
+		assert_eq!(number::parse("$fb", radix), Ok(0xfb));
+		assert_eq!(number::parse("0x1F", radix), Ok(0x1f));
+		assert_eq!(number::parse("%101", radix), Ok(5));
+		for invalid in ["$", "$-1", "%2", "0x+1", "#"] {
+			assert_eq!(
+				number::parse(invalid, radix),
+				Err(format!("Invalid number {invalid}"))
+			);
+		}
+	}
+	assert_eq!(number::parse("250", Radix::Decimal), Ok(250));
+	assert_eq!(number::parse("250", Radix::Hex), Ok(0x250));
+	assert_eq!(number::parse("fb", Radix::Hex), Ok(0xfb));
+	assert_eq!(number::parse("#250", Radix::Hex), Ok(250));
+	assert!(number::parse("12a", Radix::Decimal).is_err());
+	assert!(number::parse("#12", Radix::Decimal).is_err());
+	assert!(number::is_number("$fb"));
+	assert!(number::is_number("#12"));
+	assert!(!number::is_number("fb"));
+}
+
+fn debug_info() -> DebugInfo {
+	DebugInfo {
+		labels: vec![Label {
+			name: "add".into(),
+			address: 0x0300,
+			scope: None,
+		}],
+		..DebugInfo::default()
+	}
+}
+
+#[test]
+fn address_entries_and_conditions_agree() {
+	let info = debug_info();
+	let state = Cpu::new().state();
+	let mem = Memory::new([0; MEMORY_SIZE]);
+	for text in [
+		"$fb",
+		"0xfb",
+		"%11111011",
+		"fb",
+		"#251",
+		"fb+2",
+		"10-%11",
+		"add",
+		"add+1",
+		"$add",
+		"$xyz",
+		"0x",
+		"xyz",
+	] {
+		let condition = Expr::parse(text, &info).map(|expr| expr.evaluate(&state, &mem));
+		let address = info.evaluate(text).map(i64::from);
+		assert_eq!(condition, address, "{text}");
+	}
+	assert_eq!(info.evaluate("fb"), Ok(0xfb));
+	// Labels come before hex numbers of the same name
+	assert_eq!(info.evaluate("add"), Ok(0x0300));
+	assert_eq!(info.evaluate("$add"), Ok(0x0add));
+	assert_eq!(info.evaluate("xyz"), Err("Unknown label xyz".into()));
+}
+
+#[test]
+fn assembler_reads_plain_numbers_as_decimal() {
+	let image = assemble("\t.byte 10, $10, 0x10, %10").unwrap().image;
+	assert_eq!(image[..4], [10, 0x10, 0x10, 2]);
+}
@@ -0,0 +1,167 @@
+//! Single instruction conformance tests in the
+//! [ProcessorTests](https://github.com/SingleStepTests/65x02) JSON format,
+//! see `tests/vectors/README.md` for how to obtain the full set.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use nemu::bus::{Bus, Memory, MEMORY_SIZE};
+use nemu::cpu::{lookup, lookup_undocumented, Cpu, CpuState, StatusFlags, Variant};
+
+#[derive(Deserialize)]
+struct Vector {
+	name: String,
+	initial: Snapshot,
+	#[serde(rename = "final")]
+	expected: Snapshot,
+	/// One entry per bus access, only the count is checked
+	cycles: Vec<(u16, u8, String)>,
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+	pc: u16,
+	s: u8,
+	a: u8,
+	x: u8,
+	y: u8,
+	p: u8,
+	ram: Vec<(u16, u8)>,
+}
+
+impl Snapshot {
+	fn state(&self) -> CpuState {
+		CpuState {
+			program_counter: self.pc,
+			x: self.x,
+			y: self.y,
+			a: self.a,
+			status: self.p
+				& !(StatusFlags::Break.get_bit_mask() | StatusFlags::Unused.get_bit_mask()),
+			stack_pointer: self.s,
+			cycles: 0,
+		}
+	}
+}
+
+fn vectors_dir() -> PathBuf {
+	[env!("CARGO_MANIFEST_DIR"), "tests", "vectors"]
+		.iter()
+		.collect()
+}
+
+fn load(path: &Path) -> Vec<Vector> {
+	let json = fs::read_to_string(path).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+	serde_json::from_str(&json).unwrap_or_else(|e| panic!("{}: {e}", path.display()))
+}
+
+/// Runs every vector, returning a description of each mismatch
+fn run(vectors: &[Vector], variant: Variant) -> Vec<String> {
+	let mut failures = Vec::new();
+	for vector in vectors {
+		let mut mem = Memory::new([0; MEMORY_SIZE]);
+		vector
+			.initial
+			.ram
+			.iter()
+			.for_each(|&(address, value)| mem.data[address as usize] = value);
+		let mut cpu = Cpu::with_variant(variant);
+		cpu.set_state(vector.initial.state());
+		let cycles = match cpu.execute(&mut mem) {
+			Ok(outcome) => outcome.cycles(),
+			Err(error) => {
+				failures.push(format!("{}: {error}", vector.name));
+				continue;
+			}
+		};
+
+		let expected = CpuState {
+			cycles: vector.cycles.len() as u64,
+			..vector.expected.state()
+		};
+		let actual = cpu.state();
+		if actual != expected {
+			failures.push(format!(
+				"{}: expected {expected:?}, got {actual:?}",
+				vector.name
+			));
+		}
+		if cycles as usize != vector.cycles.len() {
+			failures.push(format!(
+				"{}: expected {} cycles, got {cycles}",
+				vector.name,
+				vector.cycles.len()
+			));
+		}
+		for &(address, value) in &vector.expected.ram {
+			let actual = mem.read_byte(address);
+			if actual != value {
+				failures.push(format!(
+					"{}: expected 0x{value:02x} at 0x{address:04x}, got 0x{actual:02x}",
+					vector.name
+				));
+			}
+		}
+	}
+	failures
+}
+
+fn assert_passes(vectors: &[Vector], variant: Variant) {
+	let failures = run(vectors, variant);
+	assert!(
+		failures.is_empty(),
+		"{} failures, first ones:\n{}",
+		failures.len(),
+		failures[..failures.len().min(20)].join("\n")
+	);
+}
+
+#[test]
+fn handwritten_vectors() {
+	assert_passes(
+		&load(&vectors_dir().join("handwritten.json")),
+		Variant::Nmos,
+	);
+}
+
+#[test]
+fn handwritten_65c02_vectors() {
+	assert_passes(
+		&load(&vectors_dir().join("handwritten_65c02.json")),
+		Variant::Wdc65C02,
+	);
+}
+
+/// Runs `<opcode>.json` from the `name` directory for every opcode in
+/// `opcodes`, skipping missing files
+fn run_directory(name: &str, opcodes: impl Iterator<Item = u8>, variant: Variant) {
+	let dir = vectors_dir().join(name);
+	if !dir.is_dir() {
+		eprintln!("skipping, {} not found", dir.display());
+		return;
+	}
+	for opcode in opcodes {
+		let path = dir.join(format!("{opcode:02x}.json"));
+		if !path.exists() {
+			eprintln!("skipping, {} not found", path.display());
+			continue;
+		}
+		assert_passes(&load(&path), variant);
+	}
+}
+
+#[test]
+fn processor_tests() {
+	run_directory(
+		"6502",
+		(0..=u8::MAX).filter(|&opcode| lookup(opcode).or(lookup_undocumented(opcode)).is_some()),
+		Variant::Nmos,
+	);
+}
+
+#[test]
+fn processor_tests_65c02() {
+	run_directory("65c02", 0..=u8::MAX, Variant::Wdc65C02);
+}
@@ -0,0 +1,160 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use nemu::asm::assemble;
+use nemu::bus::{Bus, Device, Memory, MEMORY_SIZE};
+use nemu::cpu::{Cpu, Variant};
+use nemu::save_state::{self, SaveStateError, MAGIC};
+
+/// Keeps the last byte written to it
+#[derive(Default)]
+struct Latch(u8);
+
+impl Device for Latch {
+	fn read(&mut self, _offset: u16) -> u8 {
+		self.0
+	}
+	fn write(&mut self, _offset: u16, value: u8) {
+		self.0 = value;
+	}
+	fn save(&self) -> Vec<u8> {
+		vec![self.0]
+	}
+	fn load(&mut self, state: &[u8]) -> Result<(), String> {
+		self.0 = state[0];
+		Ok(())
+	}
+}
+
+/// Only accepts states of two bytes
+struct Picky;
+
+impl Device for Picky {
+	fn read(&mut self, _offset: u16) -> u8 {
+		0
+	}
+	fn write(&mut self, _offset: u16, _value: u8) {}
+	fn save(&self) -> Vec<u8> {
+		vec![0, 0]
+	}
+	fn load(&mut self, state: &[u8]) -> Result<(), String> {
+		match state.len() {
+			2 => Ok(()),
+			len => Err(format!("expected 2 bytes, got {len}")),
+		}
+	}
+}
+
+const PROGRAM: &str = "
+	ldx #$10
+	jsr sub
+	brk
+sub:
+	inx
+	stx $20
+	stx $8000
+	nop
+	rts
+";
+
+fn load(cpu: Cpu) -> (Cpu, Memory, Rc<RefCell<Latch>>) {
+	let mut mem = Memory::new(assemble(PROGRAM).unwrap().image);
+	let latch = Rc::new(RefCell::new(Latch::default()));
+	mem.map_device(0x8000..=0x8000, latch.clone());
+	(cpu, mem, latch)
+}
+
+#[test]
+fn restores_cpu_memory_and_devices() {
+	let mut cpu = Cpu::with_variant(Variant::Wdc65C02);
+	cpu.set_strict(true);
+	let (mut cpu, mut mem, _) = load(cpu);
+	for _ in 0..5 {
+		cpu.execute(&mut mem).unwrap();
+	}
+	cpu.set_irq(true);
+	let saved = save_state::save(&cpu, &mem);
+	let (before, data) = (cpu.state(), mem.data.clone());
+	let call_stack = cpu.call_stack().to_vec();
+
+	let (mut restored, mut other, latch) = load(Cpu::new());
+	save_state::load(&saved, &mut restored, &mut other).unwrap();
+	assert_eq!(restored.state(), before);
+	assert_eq!(restored.call_stack(), call_stack);
+	assert_eq!(other.data, data);
+	assert_eq!(latch.borrow().0, 0x11);
+	assert_eq!(other.read_byte(0x20), 0x11);
+	// Both carry on the same way, taking the pending IRQ
+	for _ in 0..3 {
+		assert_eq!(cpu.execute(&mut mem), restored.execute(&mut other));
+		assert_eq!(cpu.state(), restored.state());
+	}
+	assert_eq!(
+		save_state::save(&cpu, &mem),
+		save_state::save(&restored, &other)
+	);
+}
+
+#[test]
+fn headless_memory_ignores_device_state() {
+	let (cpu, mem, _) = load(Cpu::new());
+	let saved = save_state::save(&cpu, &mem);
+	let mut plain = Memory::new(assemble(PROGRAM).unwrap().image);
+	save_state::load(&saved, &mut Cpu::new(), &mut plain).unwrap();
+}
+
+#[test]
+fn rejects_bad_files_untouched() {
+	let (mut cpu, mut mem, _) = load(Cpu::new());
+	cpu.execute(&mut mem).unwrap();
+	let saved = save_state::save(&cpu, &mem);
+	let (mut other_cpu, mut other_mem, _) = load(Cpu::new());
+	let mut check = |bytes: &[u8], expected| {
+		assert_eq!(
+			save_state::load(bytes, &mut other_cpu, &mut other_mem),
+			Err(expected)
+		);
+		assert_eq!(other_cpu.state(), Cpu::new().state());
+	};
+	check(b"not a save state", SaveStateError::NotASaveState);
+	let mut newer = saved.clone();
+	newer[MAGIC.len()] = 2;
+	check(&newer, SaveStateError::UnsupportedVersion(2));
+	check(&saved[..saved.len() - 1], SaveStateError::Truncated);
+	let mut extra = saved.clone();
+	extra.push(0);
+	check(
+		&extra,
+		SaveStateError::Invalid("unexpected data at the end".into()),
+	);
+	let mut variant = saved.clone();
+	variant[MAGIC.len() + 2] = 9;
+	check(
+		&variant,
+		SaveStateError::Invalid("unknown CPU variant 9".into()),
+	);
+}
+
+#[test]
+fn rejected_device_state_restores_nothing() {
+	let (mut cpu, mut mem, latch) = load(Cpu::new());
+	mem.map_device(0x8001..=0x8001, Latch::default());
+	latch.borrow_mut().0 = 0x42;
+	cpu.execute(&mut mem).unwrap();
+	let saved = save_state::save(&cpu, &mem);
+
+	// Same number of devices, the latch is restored before the picky one
+	// rejects the latch state meant for it
+	let mut other = Memory::new([0; MEMORY_SIZE]);
+	other.map_device(0x8001..=0x8001, Picky);
+	let latch = Rc::new(RefCell::new(Latch(7)));
+	other.map_device(0x8000..=0x8000, latch.clone());
+	let mut other_cpu = Cpu::new();
+	assert_eq!(
+		save_state::load(&saved, &mut other_cpu, &mut other),
+		Err(SaveStateError::Invalid("expected 2 bytes, got 1".into()))
+	);
+	assert_eq!(latch.borrow().0, 7);
+	assert_eq!(other_cpu.state(), Cpu::new().state());
+	assert!(other.data.iter().all(|&byte| byte == 0));
+}